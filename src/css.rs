@@ -3,13 +3,160 @@ pub mod stylesheet;
 pub mod parser;
 pub mod style;
 
-use super::html::dom::Node;
+use super::html::diagnostics::Location;
+use std::collections::HashMap;
+
+use super::html::dom::{Document, NodeRef, NodeType};
+use super::loader::Loader;
 use stylesheet::Stylesheet;
 
 pub fn parse_css(css_input: String) -> stylesheet::Stylesheet {
     parser::CssParser::parse_stylesheet(css_input)
 }
 
-pub fn build_styled_tree<'a>(root: &'a Node, stylesheet: &'a Stylesheet) -> style::StyledNode<'a> {
+// like parse_css, but also hands back what had to be dropped and where
+pub fn parse_css_with_warnings(css_input: String) -> (stylesheet::Stylesheet, Vec<parser::Warning>) {
+    parser::CssParser::parse_stylesheet_with_warnings(css_input)
+}
+
+// every <style> element and <link rel="stylesheet"> of the document, in document
+// order, and the style="" of every element
+pub fn author_stylesheet(document: &Document, loader: &Loader) -> Stylesheet {
+    author_stylesheet_with_warnings(document, loader).0
+}
+
+// the warnings of a <style> or style="" point into the document, those of a linked
+// stylesheet into the file and come with its href
+pub fn author_stylesheet_with_warnings(document: &Document, loader: &Loader) -> (Stylesheet, Vec<(Option<String>, parser::Warning)>) {
+    let mut rules = Vec::new();
+    let mut inline_styles = HashMap::new();
+    let mut warnings = Vec::new();
+    for id in document.descendants(document.root()) {
+        let Some(elem) = document.node(id).as_element() else {
+            continue;
+        };
+        if let Some(style) = elem.attributes.get("style") {
+            let (declarations, style_warnings) = parser::CssParser::parse_inline_style_with_warnings(style.clone());
+            inline_styles.insert(id, declarations);
+            let start = document.node(id).attribute_locations.get("style").copied();
+            warnings.extend(style_warnings.into_iter().map(|mut warning| {
+                if let Some(start) = start {
+                    warning.location = warning.location.within(start);
+                }
+                (None, warning)
+            }));
+        }
+        match elem.tag.as_str() {
+            "style" => {
+                let css_input: String = document.get(id).children()
+                    .filter_map(|child| match child.node_type() {
+                        NodeType::Text(text) => Some(text.as_str()),
+                        _ => None,
+                    })
+                    .collect();
+                let start = document.get(id).first_child().and_then(|child| child.node().span).map(|span| span.start);
+                let (stylesheet, style_warnings) = parse_css_with_warnings(css_input);
+                rules.extend(stylesheet.rules);
+                warnings.extend(style_warnings.into_iter().map(|mut warning| {
+                    if let Some(start) = start {
+                        warning.location = warning.location.within(start);
+                    }
+                    (None, warning)
+                }));
+            }
+            "link" if is_stylesheet_link(elem.attributes.get("rel")) => {
+                let Some(href) = elem.attributes.get("href") else { continue };
+                match loader.load_text(href) {
+                    Ok(css_input) => {
+                        let (stylesheet, link_warnings) = parse_css_with_warnings(css_input);
+                        rules.extend(stylesheet.rules);
+                        warnings.extend(link_warnings.into_iter().map(|warning| (Some(href.clone()), warning)));
+                    }
+                    // the whole file is missing, so it is reported at its start
+                    Err(e) => {
+                        let location = Location { offset: 0, line: 1, column: 1 };
+                        warnings.push((Some(href.clone()), parser::Warning { message: e.to_string(), location }));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    (Stylesheet { rules, inline_styles }, warnings)
+}
+
+// rel="stylesheet", but not rel="alternate stylesheet"
+fn is_stylesheet_link(rel: Option<&String>) -> bool {
+    let Some(rel) = rel else { return false };
+    let mut keywords = rel.split_ascii_whitespace();
+    let is_stylesheet = keywords.clone().any(|k| k.eq_ignore_ascii_case("stylesheet"));
+    is_stylesheet && !keywords.any(|k| k.eq_ignore_ascii_case("alternate"))
+}
+
+pub fn build_styled_tree<'a>(root: NodeRef<'a>, stylesheet: &'a Stylesheet) -> style::StyledNode<'a> {
     style::StyledNode::build_styled_tree(root, stylesheet)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::html::parse_html;
+
+    fn stylesheet(html_input: &str) -> (Stylesheet, Vec<(Option<String>, parser::Warning)>) {
+        let document = parse_html(html_input.to_string());
+        let loader = Loader::for_document(Path::new("index.html"), &document);
+        author_stylesheet_with_warnings(&document, &loader)
+    }
+
+    fn first_selectors(stylesheet: &Stylesheet) -> Vec<String> {
+        stylesheet.rules.iter().map(|rule| format!("{:?}", rule.selectors[0])).collect()
+    }
+
+    #[test]
+    fn style_elements_in_document_order() {
+        let (author, warnings) = stylesheet(
+            "<head><style>p { display: block }</style></head><body><style>div { display: none }</style></body>",
+        );
+        let (expected, _) = parse_css_with_warnings("p { display: block } div { display: none }".to_string());
+        assert_eq!(first_selectors(&author), first_selectors(&expected));
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn rel_must_name_stylesheet_and_not_alternate() {
+        let rel = |value: &str| is_stylesheet_link(Some(&value.to_string()));
+        assert!(rel("stylesheet"));
+        assert!(rel("  StyleSheet  "));
+        assert!(rel("preload stylesheet"));
+        assert!(!rel("alternate stylesheet"));
+        assert!(!rel("stylesheet ALTERNATE"));
+        assert!(!rel("icon"));
+        assert!(!is_stylesheet_link(None));
+    }
+
+    #[test]
+    fn warnings_of_a_style_element_point_into_the_document() {
+        let (author, warnings) = stylesheet("<!DOCTYPE html>\n<style>\np { display: block }\n  q:nope { }\n</style>");
+        assert_eq!(author.rules.len(), 1);
+        assert_eq!(warnings.len(), 1);
+        let (href, warning) = &warnings[0];
+        assert_eq!(href, &None);
+        assert_eq!((warning.location.line, warning.location.column), (4, 5));
+    }
+
+    #[test]
+    fn style_attributes_are_parsed_once_with_their_warnings() {
+        let document = parse_html("<p>x</p>\n<div id=a style=\"display: none; width: 10em\"></div>".to_string());
+        let loader = Loader::for_document(Path::new("index.html"), &document);
+        let (author, warnings) = author_stylesheet_with_warnings(&document, &loader);
+        let div = document.query_selector("#a").unwrap();
+        assert_eq!(author.inline_styles.len(), 1);
+        let names: Vec<_> = author.inline_styles[&div].iter().map(|declaration| declaration.name.as_str()).collect();
+        assert_eq!(names, ["display"]);
+        assert_eq!(warnings.len(), 1);
+        let (href, warning) = &warnings[0];
+        assert_eq!(href, &None);
+        assert_eq!((warning.location.line, warning.location.column), (2, 40));
+    }
+}
@@ -1,13 +1,25 @@
+use std::fmt;
+
+use crate::html::diagnostics::{Location, SourceMap};
+
+// https://www.w3.org/TR/css-syntax-3/#tokenization
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Ident(String),          // div, body, color
-    Hash(String),           // #ff0000, #main
+    Function(String),       // rgb(, calc( (函数名, 不含括号)
     AtKeyword(String),      // @media, @import
+    Hash(String),           // #ff0000, #main
     String(String),         // "hello"
-    Number(f32),            // 10.5
-    Percentage(f32),        // 50%
-    Dimension(f32, String), // 10px, 2em (数值, 单位)
-    
+    BadString,              // "hello 后直接换行
+    Url(String),            // url(image.png)
+    BadUrl,                 // url(a b)
+    Number(Numeric),            // 10.5, -3, 1e3
+    Percentage(Numeric),        // 50%
+    Dimension(Numeric, String), // 10px, 2em (数值, 单位)
+
+    Whitespace,
+    CDO,            // <!--
+    CDC,            // -->
     Colon,          // :
     SemiColon,      // ;
     Comma,          // ,
@@ -17,67 +29,306 @@ pub enum Token {
     BracketClose,   // ]
     ParenOpen,      // (
     ParenClose,     // )
-    
-    Delim(char),    // ., >, +, ~, *
+
+    Delim(char),    // ., >, +, ~, *, !
     EOF,
 }
 
+// https://www.w3.org/TR/css-syntax-3/#consume-number
+// the value, and what the An+B syntax and <integer> need to know of how it was written
+#[derive(Debug, Clone, PartialEq)]
+pub struct Numeric {
+    pub value: f32,
+    // the type flag: an integer unless there is a '.' or an exponent
+    pub integer: bool,
+    // whether it starts with a '+' or '-'
+    pub signed: bool,
+    // as written, which is what a unicode-range is read from
+    pub representation: String,
+}
+
+impl Numeric {
+    // the value of an <integer>, read from the digits as written since an f32
+    // can't hold every i32; out of range values are clamped to ±i32::MAX, so
+    // negating one never overflows
+    pub fn as_integer(&self) -> Option<i32> {
+        if !self.integer {
+            return None;
+        }
+        let too_big = if self.representation.starts_with('-') { i64::MIN } else { i64::MAX };
+        Some(clamp_integer(self.representation.parse::<i64>().unwrap_or(too_big)))
+    }
+}
+
+pub fn clamp_integer(value: i64) -> i32 {
+    value.clamp(-i64::from(i32::MAX), i64::from(i32::MAX)) as i32
+}
+
 pub struct Tokenizer {
     pos: usize,
     input: Vec<char>,
+    // for each char of the input, and its end, the index in the original text
+    origins: Vec<usize>,
+    source_map: SourceMap,
 }
 
 impl Tokenizer {
+    // https://www.w3.org/TR/css-syntax-3/#input-preprocessing
     fn new(css_input: String) -> Self {
-        Tokenizer { pos:0, input: css_input.chars().collect(), }
+        let original: Vec<char> = css_input.chars().collect();
+        let mut input = Vec::with_capacity(original.len());
+        let mut origins = Vec::with_capacity(original.len() + 1);
+        for (index, &c) in original.iter().enumerate() {
+            match c {
+                '\n' if index > 0 && original[index - 1] == '\r' => continue,
+                '\r' | '\x0C' => input.push('\n'),
+                '\0' => input.push('\u{FFFD}'),
+                c => input.push(c),
+            }
+            origins.push(index);
+        }
+        origins.push(original.len());
+        Tokenizer { pos: 0, input, origins, source_map: SourceMap::new(&original) }
     }
-    
+
     pub fn parse_token(css_input: String) -> Vec<Token> {
+        let mut tokens: Vec<Token> = Self::parse_token_with_locations(css_input).into_iter()
+            .map(|(token, _)| token)
+            .collect();
+        tokens.pop();
+        tokens
+    }
+
+    // every token with where it starts, ending with the EOF
+    pub fn parse_token_with_locations(css_input: String) -> Vec<(Token, Location)> {
         let mut tokenizer = Tokenizer::new(css_input);
         let mut tokens = Vec::new();
         loop {
+            tokenizer.consume_comments();
+            let location = tokenizer.source_map.location(tokenizer.origins[tokenizer.pos]);
             let token = tokenizer.next_token();
-            if token == Token::EOF { break; }
-            tokens.push(token);
+            let eof = token == Token::EOF;
+            tokens.push((token, location));
+            if eof { break; }
         }
-        return tokens;
+        tokens
     }
 
+    // https://www.w3.org/TR/css-syntax-3/#consume-token
     fn next_token(&mut self) -> Token {
-        self.consume_whitespace();
+        self.consume_comments();
         if self.eof() { return Token::EOF; }
 
         let c = self.curr_char();
         match c {
-            ':' => { self.consume(); Token::Colon }
-            ';' => { self.consume(); Token::SemiColon }
-            ',' => { self.consume(); Token::Comma }
-            '{' => { self.consume(); Token::CurlyOpen }
-            '}' => { self.consume(); Token::CurlyClose }
+            c if is_whitespace(c) => { self.consume_whitespace(); Token::Whitespace }
+            '"' | '\'' => { self.consume_string(c) }
+            '#' => {
+                if is_ident_char(self.peek(1)) || is_valid_escape(self.peek(1), self.peek(2)) {
+                    self.consume();
+                    Token::Hash(self.consume_ident())
+                } else {
+                    self.consume();
+                    Token::Delim('#')
+                }
+            }
             '(' => { self.consume(); Token::ParenOpen }
             ')' => { self.consume(); Token::ParenClose }
-            '[' => { self.consume(); Token::BracketOpen }
-            ']' => { self.consume(); Token::BracketClose }
-            '#' => { self.consume(); Token::Hash(self.consume_ident()) }
-            '.' => {
-                if self.next_char().is_numeric() {
+            '+' if self.starts_number() => { self.consume_numeric() }
+            ',' => { self.consume(); Token::Comma }
+            '-' => {
+                if self.starts_number() {
                     self.consume_numeric()
+                } else if self.peek(1) == '-' && self.peek(2) == '>' {
+                    self.pos += 3;
+                    Token::CDC
+                } else if self.starts_ident() {
+                    self.consume_ident_like()
                 } else {
                     self.consume();
-                    Token::Delim('.')
+                    Token::Delim('-')
                 }
             }
+            '.' if self.starts_number() => { self.consume_numeric() }
+            ':' => { self.consume(); Token::Colon }
+            ';' => { self.consume(); Token::SemiColon }
+            '<' if self.peek(1) == '!' && self.peek(2) == '-' && self.peek(3) == '-' => {
+                self.pos += 4;
+                Token::CDO
+            }
+            '@' => {
+                self.consume();
+                if self.starts_ident() {
+                    Token::AtKeyword(self.consume_ident())
+                } else {
+                    Token::Delim('@')
+                }
+            }
+            '[' => { self.consume(); Token::BracketOpen }
+            '\\' if is_valid_escape(c, self.peek(1)) => { self.consume_ident_like() }
+            ']' => { self.consume(); Token::BracketClose }
+            '{' => { self.consume(); Token::CurlyOpen }
+            '}' => { self.consume(); Token::CurlyClose }
             '0'..='9' => { self.consume_numeric() }
-            '"' | '\'' => { self.consume_string(c) }
-            c if is_ident_start(c) => { Token::Ident(self.consume_ident()) }
+            c if is_ident_start(c) => { self.consume_ident_like() }
             _ => { self.consume(); Token::Delim(c) }
-            // '@' => {
-            //     self.consume();
-            //     Token::AtKeyword(self.consume_ident())
-            // }
         }
     }
 
+// ---------------------
+// consume tokens
+// ---------------------
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-comment
+    fn consume_comments(&mut self) {
+        while self.curr_char() == '/' && self.peek(1) == '*' {
+            self.pos += 2;
+            loop {
+                // an unclosed comment runs to the end
+                if self.eof() { return; }
+                if self.curr_char() == '*' && self.peek(1) == '/' {
+                    self.pos += 2;
+                    break;
+                }
+                self.consume();
+            }
+        }
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-numeric-token
+    fn consume_numeric(&mut self) -> Token {
+        let num = self.consume_number();
+        if self.starts_ident() {
+            let unit = self.consume_ident();
+            Token::Dimension(num, unit)
+        } else if self.curr_char() == '%' {
+            self.consume();
+            Token::Percentage(num)
+        } else {
+            Token::Number(num)
+        }
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-number
+    fn consume_number(&mut self) -> Numeric {
+        let start = self.pos;
+        let mut integer = true;
+        let signed = matches!(self.curr_char(), '+' | '-');
+        if signed {
+            self.consume();
+        }
+        self.consume_while(|c| c.is_ascii_digit());
+        if self.curr_char() == '.' && self.peek(1).is_ascii_digit() {
+            integer = false;
+            self.consume();
+            self.consume_while(|c| c.is_ascii_digit());
+        }
+        if matches!(self.curr_char(), 'e' | 'E') {
+            let digits_at = if matches!(self.peek(1), '+' | '-') { 2 } else { 1 };
+            if self.peek(digits_at).is_ascii_digit() {
+                integer = false;
+                self.pos += digits_at;
+                self.consume_while(|c| c.is_ascii_digit());
+            }
+        }
+        let representation: String = self.input[start..self.pos].iter().collect();
+        Numeric { value: representation.parse().unwrap_or(0.0), integer, signed, representation }
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-ident-like-token
+    fn consume_ident_like(&mut self) -> Token {
+        let name = self.consume_ident();
+        if self.curr_char() != '(' {
+            return Token::Ident(name);
+        }
+        self.consume();
+        if !name.eq_ignore_ascii_case("url") {
+            return Token::Function(name);
+        }
+
+        // url("...") is an ordinary function with a string argument
+        while is_whitespace(self.curr_char()) && is_whitespace(self.peek(1)) {
+            self.consume();
+        }
+        let next = if is_whitespace(self.curr_char()) { self.peek(1) } else { self.curr_char() };
+        if next == '"' || next == '\'' {
+            Token::Function(name)
+        } else {
+            self.consume_url()
+        }
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-url-token
+    fn consume_url(&mut self) -> Token {
+        let mut url = String::new();
+        self.consume_whitespace();
+        loop {
+            if self.eof() { return Token::Url(url); }
+            match self.consume() {
+                ')' => return Token::Url(url),
+                c if is_whitespace(c) => {
+                    self.consume_whitespace();
+                    if self.eof() || self.curr_char() == ')' {
+                        self.pos = (self.pos + 1).min(self.input.len());
+                        return Token::Url(url);
+                    }
+                    self.consume_bad_url();
+                    return Token::BadUrl;
+                }
+                '"' | '\'' | '(' => {
+                    self.consume_bad_url();
+                    return Token::BadUrl;
+                }
+                c if is_non_printable(c) => {
+                    self.consume_bad_url();
+                    return Token::BadUrl;
+                }
+                '\\' if is_valid_escape('\\', self.curr_char()) => url.push(self.consume_escape()),
+                '\\' => {
+                    self.consume_bad_url();
+                    return Token::BadUrl;
+                }
+                c => url.push(c),
+            }
+        }
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-remnants-of-bad-url
+    fn consume_bad_url(&mut self) {
+        while !self.eof() {
+            match self.consume() {
+                ')' => return,
+                '\\' if is_valid_escape('\\', self.curr_char()) => { self.consume_escape(); }
+                _ => {}
+            }
+        }
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-string-token
+    fn consume_string(&mut self, quote: char) -> Token {
+        self.consume();
+        let mut s = String::new();
+        loop {
+            if self.eof() { return Token::String(s); }
+            match self.curr_char() {
+                c if c == quote => {
+                    self.consume();
+                    return Token::String(s);
+                }
+                // the newline is left for the next token
+                '\n' => return Token::BadString,
+                '\\' => {
+                    self.consume();
+                    match self.curr_char() {
+                        _ if self.eof() => {}
+                        '\n' => { self.consume(); }
+                        _ => s.push(self.consume_escape()),
+                    }
+                }
+                _ => s.push(self.consume()),
+            }
+        }
+    }
 
 // ---------------------
 // assistant functions
@@ -88,41 +339,78 @@ impl Tokenizer {
     }
 
     fn curr_char(&self) -> char {
-        if self.eof() { '\0' } else { self.input[self.pos] }
+        self.peek(0)
     }
 
-    fn next_char(&self) -> char {
-        if self.pos + 1 >= self.input.len() { '\0' } else { self.input[self.pos + 1] }
+    // '\0' past the end; the input never contains one after preprocessing
+    fn peek(&self, offset: usize) -> char {
+        self.input.get(self.pos + offset).copied().unwrap_or('\0')
     }
 
-    fn consume_numeric(&mut self) -> Token {
-        let num_str = self.consume_while(|c| c.is_numeric() || c == '.');
-        let num: f32 = num_str.parse().unwrap_or(0.0);
+    // https://www.w3.org/TR/css-syntax-3/#would-start-an-identifier
+    fn starts_ident(&self) -> bool {
+        let (first, second, third) = (self.peek(0), self.peek(1), self.peek(2));
+        match first {
+            '-' => is_ident_start(second) || second == '-' || is_valid_escape(second, third),
+            '\\' => is_valid_escape(first, second),
+            c => is_ident_start(c),
+        }
+    }
 
-        if self.curr_char() == '%' {
-            self.consume();
-            Token::Percentage(num)
-        } else if is_ident_start(self.curr_char()) {
-            let unit = self.consume_ident();
-            Token::Dimension(num, unit)
-        } else {
-            Token::Number(num)
+    // https://www.w3.org/TR/css-syntax-3/#starts-with-a-number
+    fn starts_number(&self) -> bool {
+        let (first, second, third) = (self.peek(0), self.peek(1), self.peek(2));
+        match first {
+            '+' | '-' => second.is_ascii_digit() || (second == '.' && third.is_ascii_digit()),
+            '.' => second.is_ascii_digit(),
+            c => c.is_ascii_digit(),
         }
     }
 
+    // https://www.w3.org/TR/css-syntax-3/#consume-name
     fn consume_ident(&mut self) -> String {
-        self.consume_while(is_ident_char)
+        let mut name = String::new();
+        loop {
+            let c = self.curr_char();
+            if is_ident_char(c) {
+                name.push(self.consume());
+            } else if is_valid_escape(c, self.peek(1)) {
+                self.consume();
+                name.push(self.consume_escape());
+            } else {
+                return name;
+            }
+        }
     }
 
-    fn consume_string(&mut self, quote: char) -> Token {
-        self.consume();
-        let s = self.consume_while(|c| c != quote);
-        self.consume();
-        Token::String(s)
+    // https://www.w3.org/TR/css-syntax-3/#consume-escaped-code-point
+    // the backslash is already consumed
+    fn consume_escape(&mut self) -> char {
+        if self.eof() { return '\u{FFFD}'; }
+        if !self.curr_char().is_ascii_hexdigit() {
+            return self.consume();
+        }
+        let digits = self.consume_hex_digits(6);
+        if is_whitespace(self.curr_char()) {
+            self.consume();
+        }
+        let code = u32::from_str_radix(&digits, 16).unwrap();
+        match char::from_u32(code) {
+            Some(c) if code != 0 => c,
+            _ => '\u{FFFD}',
+        }
+    }
+
+    fn consume_hex_digits(&mut self, max: usize) -> String {
+        let mut digits = String::new();
+        while digits.len() < max && self.curr_char().is_ascii_hexdigit() {
+            digits.push(self.consume());
+        }
+        digits
     }
 
     fn consume_whitespace(&mut self) {
-        self.consume_while(|c| c.is_whitespace());
+        self.consume_while(is_whitespace);
     }
 
     fn consume_while<F>(&mut self, test: F) -> String
@@ -133,20 +421,207 @@ impl Tokenizer {
         while !self.eof() && test(self.curr_char()) {
             result.push(self.consume());
         }
-        return result;
+        result
     }
 
     fn consume(&mut self) -> char {
         let c = self.input[self.pos];
         self.pos += 1;
-        return c;
+        c
     }
 }
 
+// https://www.w3.org/TR/css-syntax-3/#ident-start-code-point
 fn is_ident_start(c: char) -> bool {
-    c.is_alphabetic() || c == '-' || c == '_'
+    c.is_ascii_alphabetic() || c == '_' || c as u32 >= 0x80
 }
 
 fn is_ident_char(c: char) -> bool {
-    c.is_alphanumeric() || c == '-' || c == '_'
+    is_ident_start(c) || c.is_ascii_digit() || c == '-'
+}
+
+// newlines are already normalized to '\n'
+fn is_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n')
+}
+
+fn is_non_printable(c: char) -> bool {
+    matches!(c, '\0'..='\x08' | '\x0B' | '\x0E'..='\x1F' | '\x7F')
+}
+
+// https://www.w3.org/TR/css-syntax-3/#starts-with-a-valid-escape
+fn is_valid_escape(first: char, second: char) -> bool {
+    first == '\\' && second != '\n'
+}
+
+impl fmt::Display for Numeric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.representation)
+    }
+}
+
+// as the token would appear in a stylesheet, for messages
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Function(name) => write!(f, "{}(", name),
+            Token::AtKeyword(name) => write!(f, "@{}", name),
+            Token::Hash(name) => write!(f, "#{}", name),
+            Token::String(s) => write!(f, "{:?}", s),
+            Token::BadString => write!(f, "unterminated string"),
+            Token::Url(url) => write!(f, "url({})", url),
+            Token::BadUrl => write!(f, "invalid url()"),
+            Token::Number(num) => write!(f, "{}", num),
+            Token::Percentage(num) => write!(f, "{}%", num),
+            Token::Dimension(num, unit) => write!(f, "{}{}", num, unit),
+            Token::Whitespace => write!(f, " "),
+            Token::CDO => write!(f, "<!--"),
+            Token::CDC => write!(f, "-->"),
+            Token::Colon => write!(f, ":"),
+            Token::SemiColon => write!(f, ";"),
+            Token::Comma => write!(f, ","),
+            Token::CurlyOpen => write!(f, "{{"),
+            Token::CurlyClose => write!(f, "}}"),
+            Token::BracketOpen => write!(f, "["),
+            Token::BracketClose => write!(f, "]"),
+            Token::ParenOpen => write!(f, "("),
+            Token::ParenClose => write!(f, ")"),
+            Token::Delim(c) => write!(f, "{}", c),
+            Token::EOF => write!(f, "end of input"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(css: &str) -> Vec<Token> {
+        Tokenizer::parse_token(css.to_string())
+    }
+
+    fn number(value: f32, integer: bool, signed: bool) -> Numeric {
+        Numeric { value, integer, signed, representation: String::new() }
+    }
+
+    // the representation is checked on its own
+    fn without_representation(tokens: Vec<Token>) -> Vec<Token> {
+        tokens.into_iter().map(|token| match token {
+            Token::Number(n) => Token::Number(Numeric { representation: String::new(), ..n }),
+            Token::Percentage(n) => Token::Percentage(Numeric { representation: String::new(), ..n }),
+            Token::Dimension(n, unit) => Token::Dimension(Numeric { representation: String::new(), ..n }, unit),
+            token => token,
+        }).collect()
+    }
+
+    #[test]
+    fn rule() {
+        assert_eq!(tokens("p.a > #b{color:red;}"), vec![
+            Token::Ident("p".into()), Token::Delim('.'), Token::Ident("a".into()), Token::Whitespace,
+            Token::Delim('>'), Token::Whitespace, Token::Hash("b".into()), Token::CurlyOpen,
+            Token::Ident("color".into()), Token::Colon, Token::Ident("red".into()), Token::SemiColon,
+            Token::CurlyClose,
+        ]);
+    }
+
+    #[test]
+    fn numbers_keep_type_and_sign() {
+        let tokens = |css: &str| without_representation(tokens(css));
+        assert_eq!(tokens("3"), vec![Token::Number(number(3.0, true, false))]);
+        assert_eq!(tokens("+3"), vec![Token::Number(number(3.0, true, true))]);
+        assert_eq!(tokens("-3"), vec![Token::Number(number(-3.0, true, true))]);
+        assert_eq!(tokens("3.0"), vec![Token::Number(number(3.0, false, false))]);
+        assert_eq!(tokens("1e3"), vec![Token::Number(number(1000.0, false, false))]);
+        assert_eq!(tokens(".5"), vec![Token::Number(number(0.5, false, false))]);
+        assert_eq!(tokens("-2.5%"), vec![Token::Percentage(number(-2.5, false, true))]);
+        assert_eq!(tokens("+2n"), vec![Token::Dimension(number(2.0, true, true), "n".into())]);
+        // an "e" without digits after it is the unit
+        assert_eq!(tokens("3em"), vec![Token::Dimension(number(3.0, true, false), "em".into())]);
+    }
+
+    #[test]
+    fn representation() {
+        assert_eq!(tokens("+0025 1.50e2px").iter().map(|t| t.to_string()).collect::<Vec<_>>(), ["+0025", " ", "1.50e2px"]);
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#urange
+    // u+ is only read as a range by the unicode-range descriptor
+    #[test]
+    fn no_unicode_range_token() {
+        assert_eq!(without_representation(tokens("u+a")), vec![
+            Token::Ident("u".into()), Token::Delim('+'), Token::Ident("a".into()),
+        ]);
+        assert_eq!(without_representation(tokens("U+4??")), vec![
+            Token::Ident("U".into()), Token::Number(number(4.0, true, true)), Token::Delim('?'), Token::Delim('?'),
+        ]);
+    }
+
+    #[test]
+    fn integer_value() {
+        let integer = |css: &str| match &tokens(css)[0] {
+            Token::Number(n) | Token::Dimension(n, _) => n.as_integer(),
+            token => panic!("unexpected token {:?}", token),
+        };
+        assert_eq!(integer("4"), Some(4));
+        assert_eq!(integer("+0025"), Some(25));
+        assert_eq!(integer("-3n"), Some(-3));
+        assert_eq!(integer("4.0"), None);
+        assert_eq!(integer("1e3"), None);
+        // more digits than an f32 keeps
+        assert_eq!(integer("16777217"), Some(16777217));
+        assert_eq!(integer("99999999999"), Some(i32::MAX));
+        assert_eq!(integer("-99999999999999999999999"), Some(-i32::MAX));
+    }
+
+    #[test]
+    fn comments_are_dropped() {
+        assert_eq!(tokens("a/* x */b/* open"), vec![Token::Ident("a".into()), Token::Ident("b".into())]);
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(tokens(r#""a\"b" 'c'"#), vec![
+            Token::String("a\"b".into()), Token::Whitespace, Token::String("c".into()),
+        ]);
+        assert_eq!(tokens("\"a\nb"), vec![Token::BadString, Token::Whitespace, Token::Ident("b".into())]);
+        assert_eq!(tokens("\"unclosed"), vec![Token::String("unclosed".into())]);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(tokens(r"\31 0"), vec![Token::Ident("10".into())]);
+        assert_eq!(tokens(r"a\.b"), vec![Token::Ident("a.b".into())]);
+        assert_eq!(tokens(r"\0"), vec![Token::Ident("\u{FFFD}".into())]);
+    }
+
+    #[test]
+    fn urls() {
+        assert_eq!(tokens("url( a.png )"), vec![Token::Url("a.png".into())]);
+        assert_eq!(tokens("url(\"a.png\")"), vec![
+            Token::Function("url".into()), Token::String("a.png".into()), Token::ParenClose,
+        ]);
+        assert_eq!(tokens("url(a b) c"), vec![Token::BadUrl, Token::Whitespace, Token::Ident("c".into())]);
+    }
+
+    #[test]
+    fn idents_functions_and_at_keywords() {
+        assert_eq!(tokens("--x -y rgb( @media"), vec![
+            Token::Ident("--x".into()), Token::Whitespace, Token::Ident("-y".into()), Token::Whitespace,
+            Token::Function("rgb".into()), Token::Whitespace, Token::AtKeyword("media".into()),
+        ]);
+    }
+
+    #[test]
+    fn cdo_and_cdc() {
+        assert_eq!(tokens("<!-- -->"), vec![Token::CDO, Token::Whitespace, Token::CDC]);
+    }
+
+    #[test]
+    fn locations() {
+        let located = Tokenizer::parse_token_with_locations("a\r\n  b".to_string());
+        let positions: Vec<(usize, usize)> = located.iter().map(|(_, l)| (l.line, l.column)).collect();
+        assert_eq!(positions, vec![(1, 1), (1, 2), (2, 3), (2, 4)]);
+        assert_eq!(located.last().unwrap().0, Token::EOF);
+    }
 }
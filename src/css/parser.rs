@@ -1,92 +1,208 @@
+use std::fmt;
+
 use super::stylesheet::*;
 use super::lexical::*;
+use crate::html::diagnostics::Location;
 
 pub struct CssParser {
     pos: usize,
     tokens: Vec<Token>,
+    // where each token starts; the last token is always the EOF
+    locations: Vec<Location>,
+    warnings: Vec<Warning>,
+}
+
+// something the parser had to drop to carry on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub message: String,
+    pub location: Location,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
 }
 
+// https://www.w3.org/TR/css-syntax-3/#error-handling
+// an invalid declaration is dropped up to the next ';', an invalid rule up to
+// the end of its block, and the parser carries on after it
 impl CssParser {
     fn new(css_input: String) -> Self {
-        let tokens = Tokenizer::parse_token(css_input);
-        CssParser { pos: 0, tokens: tokens, }
+        let (tokens, locations) = Tokenizer::parse_token_with_locations(css_input).into_iter().unzip();
+        CssParser { pos: 0, tokens, locations, warnings: Vec::new() }
     }
 
     pub fn parse_stylesheet(css_input: String) -> Stylesheet {
+        Self::parse_stylesheet_with_warnings(css_input).0
+    }
+
+    pub fn parse_stylesheet_with_warnings(css_input: String) -> (Stylesheet, Vec<Warning>) {
         let mut css_parser = Self::new(css_input);
         let mut rules = Vec::new();
         loop {
+            // <!-- and --> are allowed around the rules for old browsers that lack <style>
+            while matches!(css_parser.curr_token(), Token::Whitespace | Token::CDO | Token::CDC) {
+                css_parser.consume();
+            }
             if css_parser.eof() { break; }
-            rules.push(css_parser.parse_rule());
+            if let Some(rule) = css_parser.parse_rule() {
+                rules.push(rule);
+            }
         }
-        Stylesheet { rules }
+        (Stylesheet { rules, ..Stylesheet::default() }, css_parser.warnings)
     }
 
-    fn parse_rule(&mut self) -> Rule {
-        Rule {
-            selectors: self.parse_selectors(),
-            declarations: self.parse_declarations(),
+    // declarations of a style="" attribute, without the braces
+    pub fn parse_inline_style(css_input: String) -> Vec<Declaration> {
+        Self::parse_inline_style_with_warnings(css_input).0
+    }
+
+    pub fn parse_inline_style_with_warnings(css_input: String) -> (Vec<Declaration>, Vec<Warning>) {
+        let mut css_parser = Self::new(css_input);
+        let declarations = css_parser.parse_declaration_list();
+        (declarations, css_parser.warnings)
+    }
+
+    // a bare selector list, as taken by query_selector()
+    pub fn parse_selector_list(css_input: String) -> Result<Vec<Selector>, Warning> {
+        let mut css_parser = Self::new(css_input);
+        css_parser.skip_whitespace();
+        if css_parser.eof() {
+            return Ok(Vec::new());
         }
+        css_parser.parse_selectors()
     }
 
-    fn parse_selectors(&mut self) -> Vec<Selector> {
+// ---------------------
+// rules
+// ---------------------
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-qualified-rule
+    fn parse_rule(&mut self) -> Option<Rule> {
+        let start = self.pos;
+        if let Token::AtKeyword(name) = self.curr_token() {
+            self.skip_at_rule();
+            self.warn(start, format!("unsupported at-rule '@{}', rule dropped", name));
+            return None;
+        }
+
+        while !matches!(self.curr_token(), Token::CurlyOpen | Token::EOF) {
+            self.consume_component_value();
+        }
+        if self.eof() {
+            self.warn(start, "unexpected end of input before '{', rule dropped".to_string());
+            return None;
+        }
+        let block_start = self.pos;
+        self.consume_component_value();
+        let block_end = self.pos;
+
+        // the block of a dropped rule is not looked at, so it reports nothing of its own
+        let mut prelude = self.nested(start, block_start);
+        let selectors = match prelude.parse_selectors() {
+            Ok(selectors) => selectors,
+            Err(warning) => {
+                self.warnings.push(Warning { message: format!("{}, rule dropped", warning.message), ..warning });
+                return None;
+            }
+        };
+        let mut block = self.nested(block_start + 1, self.block_content_end(block_start, block_end));
+        let declarations = block.parse_declaration_list();
+        self.warnings.append(&mut block.warnings);
+        Some(Rule { selectors, declarations })
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-at-rule
+    // none are supported yet, so only its end matters
+    fn skip_at_rule(&mut self) {
+        self.consume();
+        loop {
+            match self.curr_token() {
+                Token::SemiColon => { self.consume(); break; }
+                Token::EOF => break,
+                Token::CurlyOpen => { self.consume_component_value(); break; }
+                _ => self.consume_component_value(),
+            }
+        }
+    }
+
+// ---------------------
+// selectors
+// ---------------------
+
+    fn parse_selectors(&mut self) -> Result<Vec<Selector>, Warning> {
         let mut selectors = Vec::new();
         loop {
-            selectors.push(self.parse_selector_chain());
+            self.skip_whitespace();
+            selectors.push(self.parse_selector_chain()?);
             match self.curr_token() {
                 Token::Comma => { self.consume(); },
-                Token::CurlyOpen => break,
-                _ => panic!("Unexpected token in selector list: {:?}", self.curr_token()),
+                Token::EOF => break,
+                token => return Err(self.warning(self.pos, format!("unexpected '{}' in selector", token))),
             }
         }
-        selectors.sort_by(|a, b| b.specificity().cmp(&a.specificity()));
-        return selectors;
+        selectors.sort_by_key(|selector| std::cmp::Reverse(selector.specificity()));
+        Ok(selectors)
     }
 
-    fn parse_selector_chain(&mut self) -> Selector {
-        let mut selector = Selector::Simple(self.parse_simple_selector());
+    fn parse_selector_chain(&mut self) -> Result<Selector, Warning> {
+        let mut selector = Selector::Simple(self.parse_simple_selector()?);
 
         loop {
+            // whitespace is the descendant combinator, unless another one follows
+            let spaced = self.skip_whitespace();
             if self.eof() { break; }
-            
+
             match self.curr_token() {
-                Token::Comma | Token::CurlyOpen => break,
-                
+                Token::Comma => break,
+
+                _ if selector.pseudo_element().is_some() => {
+                    return Err(self.warning(self.pos, "a pseudo-element has to come last in a selector".to_string()));
+                }
                 Token::Delim('>') => {
                     self.consume();
-                    let next = self.parse_simple_selector();
+                    self.skip_whitespace();
+                    let next = self.parse_simple_selector()?;
                     selector = Selector::Child(Box::new(selector), Box::new(Selector::Simple(next)));
                 }
                 Token::Delim('+') => {
                     self.consume();
-                    let next = self.parse_simple_selector();
+                    self.skip_whitespace();
+                    let next = self.parse_simple_selector()?;
                     selector = Selector::AdjacentSibling(Box::new(selector), Box::new(Selector::Simple(next)));
                 }
                 Token::Delim('~') => {
                     self.consume();
-                    let next = self.parse_simple_selector();
+                    self.skip_whitespace();
+                    let next = self.parse_simple_selector()?;
                     selector = Selector::GeneralSibling(Box::new(selector), Box::new(Selector::Simple(next)));
                 }
                 token => {
                     match token {
-                        Token::Hash(_) | Token::Delim('.') | Token::Delim('*') | Token::Ident(_) => {
-                            let next = self.parse_simple_selector();
+                        Token::Hash(_) | Token::Delim('.') | Token::Delim('*') | Token::Ident(_) | Token::BracketOpen | Token::Colon if spaced => {
+                            let next = self.parse_simple_selector()?;
                             selector = Selector::Descendant(Box::new(selector), Box::new(Selector::Simple(next)));
                         }
-                        _ => panic!("Unexpected token in selector chain: {:?}", token),
+                        _ => return Err(self.warning(self.pos, format!("unexpected '{}' in selector", token))),
                     }
                 }
             }
         }
-        return selector;
+        Ok(selector)
     }
 
-    fn parse_simple_selector(&mut self) -> SimpleSelector {
+    fn parse_simple_selector(&mut self) -> Result<SimpleSelector, Warning> {
         let mut selector = SimpleSelector {
             tag: None,
             id: None,
             class: Vec::new(),
+            attributes: Vec::new(),
+            pseudo_classes: Vec::new(),
+            pseudo_element: None,
         };
+        let start = self.pos;
         loop {
             match self.curr_token() {
                 Token::Hash(id) => {
@@ -95,75 +211,557 @@ impl CssParser {
                 }
                 Token::Delim('.') => {
                     self.consume();
-                    if let Token::Ident(class_name) = self.consume() {
-                        selector.class.push(class_name.clone());
-                    } else {
-                        panic!("Expected identifier after .");
+                    match self.curr_token() {
+                        Token::Ident(class_name) => {
+                            self.consume();
+                            selector.class.push(class_name);
+                        }
+                        token => return Err(self.warning(self.pos, format!("expected a class name after '.', found '{}'", token))),
                     }
                 }
                 Token::Delim('*') => {
                     self.consume();
                 }
+                Token::BracketOpen => {
+                    let attribute = self.parse_attribute_selector()?;
+                    selector.attributes.push(attribute);
+                }
+                // nothing else can follow it in the same compound
+                Token::Colon if self.at_pseudo_element() => {
+                    selector.pseudo_element = Some(self.parse_pseudo_element()?);
+                    break;
+                }
+                Token::Colon => {
+                    let pseudo_class = self.parse_pseudo_class()?;
+                    selector.pseudo_classes.push(pseudo_class);
+                }
                 Token::Ident(tag) => {
                     self.consume();
                     selector.tag = Some(tag.clone());
                 }
-                _ => break, 
+                _ => break,
+            }
+        }
+        if self.pos == start {
+            let message = match self.curr_token() {
+                Token::EOF => "missing selector".to_string(),
+                token => format!("expected a selector, found '{}'", token),
+            };
+            return Err(self.warning(self.pos, message));
+        }
+        Ok(selector)
+    }
+
+    // https://www.w3.org/TR/selectors-4/#attribute-selectors
+    fn parse_attribute_selector(&mut self) -> Result<AttributeSelector, Warning> {
+        self.consume();
+        self.skip_whitespace();
+        let name = match self.consume() {
+            Token::Ident(name) => name,
+            token => return Err(self.warning(self.pos - 1, format!("expected an attribute name, found '{}'", token))),
+        };
+        self.skip_whitespace();
+
+        let operator = match self.consume() {
+            Token::BracketClose => {
+                return Ok(AttributeSelector { name, operator: AttributeOperator::Exists, value: String::new(), case: AttributeCase::Default });
+            }
+            Token::Delim('=') => AttributeOperator::Equals,
+            Token::Delim(c @ ('~' | '|' | '^' | '$' | '*')) if self.curr_token() == Token::Delim('=') => {
+                self.consume();
+                match c {
+                    '~' => AttributeOperator::Includes,
+                    '|' => AttributeOperator::DashMatch,
+                    '^' => AttributeOperator::Prefix,
+                    '$' => AttributeOperator::Suffix,
+                    _ => AttributeOperator::Substring,
+                }
             }
+            token => return Err(self.warning(self.pos - 1, format!("unexpected '{}' in attribute selector", token))),
+        };
+        self.skip_whitespace();
+
+        let value = match self.consume() {
+            Token::Ident(value) | Token::String(value) => value,
+            token => return Err(self.warning(self.pos - 1, format!("expected an attribute value, found '{}'", token))),
+        };
+        self.skip_whitespace();
+
+        let case = match self.curr_token() {
+            Token::Ident(flag) if flag.eq_ignore_ascii_case("i") => AttributeCase::Insensitive,
+            Token::Ident(flag) if flag.eq_ignore_ascii_case("s") => AttributeCase::Sensitive,
+            _ => AttributeCase::Default,
+        };
+        if case != AttributeCase::Default {
+            self.consume();
+            self.skip_whitespace();
+        }
+
+        match self.consume() {
+            Token::BracketClose => Ok(AttributeSelector { name, operator, value, case }),
+            token => Err(self.warning(self.pos - 1, format!("expected ']', found '{}'", token))),
         }
-        return selector;
     }
 
-    fn parse_declarations(&mut self) -> Vec<Declaration> {
-        assert_eq!(self.consume(), Token::CurlyOpen);
+    // https://www.w3.org/TR/selectors-4/#pseudo-classes
+    fn parse_pseudo_class(&mut self) -> Result<PseudoClass, Warning> {
+        self.consume();
+        let start = self.pos;
+        let name = match self.consume() {
+            Token::Ident(name) => {
+                return match name.to_ascii_lowercase().as_str() {
+                    "root" => Ok(PseudoClass::Root),
+                    "empty" => Ok(PseudoClass::Empty),
+                    "first-child" => Ok(PseudoClass::FirstChild),
+                    "last-child" => Ok(PseudoClass::LastChild),
+                    "only-child" => Ok(PseudoClass::OnlyChild),
+                    "first-of-type" => Ok(PseudoClass::FirstOfType),
+                    "last-of-type" => Ok(PseudoClass::LastOfType),
+                    "only-of-type" => Ok(PseudoClass::OnlyOfType),
+                    _ => Err(self.warning(start, format!("unsupported pseudo-class ':{}'", name))),
+                };
+            }
+            Token::Function(name) => name,
+            token => return Err(self.warning(start, format!("unsupported selector ':{}'", token))),
+        };
+
+        let mut arguments = self.function_arguments(start);
+
+        let lowercase = name.to_ascii_lowercase();
+        match lowercase.as_str() {
+            "nth-child" | "nth-last-child" => {
+                let nth = arguments.parse_nth()?;
+                arguments.skip_whitespace();
+                // https://www.w3.org/TR/selectors-4/#the-nth-child-pseudo
+                let selectors = match arguments.consume() {
+                    Token::EOF => Vec::new(),
+                    Token::Ident(of) if of.eq_ignore_ascii_case("of") => arguments.parse_nested_selectors()?,
+                    token => return Err(arguments.warning(arguments.pos - 1, format!("unexpected '{}' in ':{}()'", token, name))),
+                };
+                if lowercase == "nth-child" {
+                    Ok(PseudoClass::NthChild(nth, selectors))
+                } else {
+                    Ok(PseudoClass::NthLastChild(nth, selectors))
+                }
+            }
+            "nth-of-type" | "nth-last-of-type" => {
+                let nth = arguments.parse_nth()?;
+                arguments.skip_whitespace();
+                if !arguments.eof() {
+                    return Err(arguments.warning(arguments.pos, format!("unexpected '{}' in ':{}()'", arguments.curr_token(), name)));
+                }
+                if lowercase == "nth-of-type" {
+                    Ok(PseudoClass::NthOfType(nth))
+                } else {
+                    Ok(PseudoClass::NthLastOfType(nth))
+                }
+            }
+            "not" => Ok(PseudoClass::Not(arguments.parse_nested_selectors()?)),
+            "is" | "matches" | "-webkit-any" => Ok(PseudoClass::Is(arguments.parse_forgiving_selectors())),
+            "where" => Ok(PseudoClass::Where(arguments.parse_forgiving_selectors())),
+            "has" => Ok(PseudoClass::Has(arguments.parse_relative_selectors()?)),
+            _ => Err(self.warning(start, format!("unsupported pseudo-class ':{}()'", name))),
+        }
+    }
+
+    // the selectors an element is matched against from within a pseudo-class,
+    // where a pseudo-element would never match
+    fn parse_nested_selectors(&mut self) -> Result<Vec<Selector>, Warning> {
+        let start = self.pos;
+        let selectors = self.parse_selectors()?;
+        if selectors.iter().any(|selector| selector.pseudo_element().is_some()) {
+            return Err(self.warning(start, "a pseudo-element cannot be used in a pseudo-class".to_string()));
+        }
+        Ok(selectors)
+    }
+
+    // "::before", and the ":before" and ":after" of CSS 2
+    fn at_pseudo_element(&self) -> bool {
+        match self.tokens.get(self.pos + 1) {
+            Some(Token::Colon) => true,
+            Some(Token::Ident(name)) => name.eq_ignore_ascii_case("before") || name.eq_ignore_ascii_case("after"),
+            _ => false,
+        }
+    }
+
+    // https://www.w3.org/TR/css-pseudo-4/#generated-content
+    fn parse_pseudo_element(&mut self) -> Result<PseudoElement, Warning> {
+        self.consume();
+        if self.curr_token() == Token::Colon {
+            self.consume();
+        }
+        let start = self.pos;
+        match self.consume() {
+            Token::Ident(name) if name.eq_ignore_ascii_case("before") => Ok(PseudoElement::Before),
+            Token::Ident(name) if name.eq_ignore_ascii_case("after") => Ok(PseudoElement::After),
+            token => Err(self.warning(start, format!("unsupported pseudo-element '::{}'", token))),
+        }
+    }
+
+    // https://www.w3.org/TR/selectors-4/#typedef-forgiving-selector-list
+    // for :is() and :where(), an invalid selector is left out instead of
+    // invalidating the whole list
+    fn parse_forgiving_selectors(&mut self) -> Vec<Selector> {
+        let mut selectors = Vec::new();
+        loop {
+            let start = self.pos;
+            while !matches!(self.curr_token(), Token::Comma | Token::EOF) {
+                self.consume_component_value();
+            }
+            let mut selector = self.nested(start, self.pos);
+            selector.skip_whitespace();
+            if !selector.eof()
+                && let Ok(parsed) = selector.parse_nested_selectors()
+            {
+                selectors.extend(parsed);
+            }
+            if self.consume() == Token::EOF { break; }
+        }
+        selectors
+    }
+
+    // https://www.w3.org/TR/selectors-4/#typedef-relative-selector-list
+    fn parse_relative_selectors(&mut self) -> Result<Vec<RelativeSelector>, Warning> {
+        let mut selectors = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let combinator = match self.curr_token() {
+                Token::Delim('>') => Combinator::Child,
+                Token::Delim('+') => Combinator::AdjacentSibling,
+                Token::Delim('~') => Combinator::GeneralSibling,
+                _ => Combinator::Descendant,
+            };
+            if combinator != Combinator::Descendant {
+                self.consume();
+                self.skip_whitespace();
+            }
+            let start = self.pos;
+            let selector = self.parse_selector_chain()?;
+            // https://www.w3.org/TR/selectors-4/#relational
+            if contains_has(&selector) {
+                return Err(self.warning(start, "':has()' cannot be nested".to_string()));
+            }
+            if selector.pseudo_element().is_some() {
+                return Err(self.warning(start, "a pseudo-element cannot be used in a pseudo-class".to_string()));
+            }
+            selectors.push(RelativeSelector { combinator, selector });
+            match self.consume() {
+                Token::Comma => {}
+                Token::EOF => break,
+                token => return Err(self.warning(self.pos - 1, format!("unexpected '{}' in selector", token))),
+            }
+        }
+        Ok(selectors)
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#anb-microsyntax
+    // odd, even, 5, -n+3, 2n-1, 2n - 1, +n ...; every number is an integer, and
+    // B has its sign written next to it or as a separate '+' or '-', never both
+    fn parse_nth(&mut self) -> Result<Nth, Warning> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let invalid = |parser: &Self| parser.warning(start, "invalid An+B expression".to_string());
+
+        let (a, rest) = match self.consume() {
+            Token::Number(b) if b.integer => return Ok(Nth { a: 0, b: b.as_integer().unwrap() }),
+            Token::Ident(ident) => {
+                let ident = ident.to_ascii_lowercase();
+                match ident.as_str() {
+                    "odd" => return Ok(Nth { a: 2, b: 1 }),
+                    "even" => return Ok(Nth { a: 2, b: 0 }),
+                    _ => match ident.strip_prefix('-') {
+                        Some(rest) => (-1, rest.to_string()),
+                        None => (1, ident),
+                    },
+                }
+            }
+            // "+n", with nothing in between
+            Token::Delim('+') => match self.consume() {
+                Token::Ident(ident) if !ident.starts_with('-') => (1, ident.to_ascii_lowercase()),
+                _ => return Err(invalid(self)),
+            },
+            Token::Dimension(a, unit) if a.integer => (a.as_integer().unwrap(), unit.to_ascii_lowercase()),
+            _ => return Err(invalid(self)),
+        };
+
+        let b = match rest.as_str() {
+            "n" => {
+                // an optional "+ B" or "- B", or a signed number
+                let before = self.pos;
+                self.skip_whitespace();
+                match self.curr_token() {
+                    Token::Number(b) if b.integer && b.signed => {
+                        self.consume();
+                        b.as_integer().unwrap()
+                    }
+                    Token::Delim(sign @ ('+' | '-')) => {
+                        self.consume();
+                        self.skip_whitespace();
+                        match self.consume() {
+                            Token::Number(b) if b.integer && !b.signed => {
+                                let b = b.as_integer().unwrap();
+                                if sign == '-' { -b } else { b }
+                            }
+                            _ => return Err(invalid(self)),
+                        }
+                    }
+                    _ => {
+                        self.pos = before;
+                        0
+                    }
+                }
+            }
+            // "n-" and then the number on its own
+            "n-" => {
+                self.skip_whitespace();
+                match self.consume() {
+                    Token::Number(b) if b.integer && !b.signed => -b.as_integer().unwrap(),
+                    _ => return Err(invalid(self)),
+                }
+            }
+            // "n-3" is one ident or unit
+            rest => match rest.strip_prefix("n-") {
+                Some(digits) if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) => {
+                    -clamp_integer(digits.parse::<i64>().unwrap_or(i64::MAX))
+                }
+                _ => return Err(invalid(self)),
+            },
+        };
+        Ok(Nth { a, b })
+    }
+
+// ---------------------
+// declarations
+// ---------------------
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-list-of-declarations
+    fn parse_declaration_list(&mut self) -> Vec<Declaration> {
         let mut declarations = Vec::new();
         loop {
-            if self.curr_token() == Token::CurlyClose { break; }
-            declarations.push(self.parse_declaration());
+            let start = self.pos;
             match self.curr_token() {
-                Token::SemiColon => { self.consume(); }
-                Token::CurlyClose => { break; }
-                _ => panic!("Expected ; or }} after declaration, found {:?}", self.curr_token()),
+                Token::Whitespace | Token::SemiColon => { self.consume(); }
+                Token::EOF => break,
+                Token::AtKeyword(name) => {
+                    self.skip_at_rule();
+                    self.warn(start, format!("unsupported at-rule '@{}', rule dropped", name));
+                }
+                _ => {
+                    while !matches!(self.curr_token(), Token::SemiColon | Token::EOF) {
+                        self.consume_component_value();
+                    }
+                    match self.nested(start, self.pos).parse_declaration() {
+                        Ok(declaration) => declarations.push(declaration),
+                        Err(warning) => {
+                            self.warnings.push(Warning { message: format!("{}, declaration dropped", warning.message), ..warning });
+                        }
+                    }
+                }
             }
         }
-        assert_eq!(self.consume(), Token::CurlyClose);
-        return declarations;
+        declarations
     }
 
-    fn parse_declaration(&mut self) -> Declaration {
+    // https://www.w3.org/TR/css-syntax-3/#consume-declaration
+    // the parser holds exactly one declaration
+    fn parse_declaration(&mut self) -> Result<Declaration, Warning> {
         let name = match self.consume() {
             Token::Ident(name) => name,
-            _ => panic!("Expected property name"),
+            token => return Err(self.warning(0, format!("expected a property name, found '{}'", token))),
+        };
+        self.skip_whitespace();
+        if self.consume() != Token::Colon {
+            return Err(self.warning(self.pos - 1, format!("expected ':' after '{}'", name)));
+        }
+        self.skip_whitespace();
+
+        // everything up to the end, less "! important" and the whitespace around it
+        let mut end = self.tokens.len() - 1;
+        let last = |end: usize, tokens: &[Token]| (self.pos..end).rev().find(|&i| tokens[i] != Token::Whitespace);
+        let mut important = false;
+        if let Some(i) = last(end, &self.tokens)
+            && matches!(&self.tokens[i], Token::Ident(s) if s.eq_ignore_ascii_case("important"))
+            && let Some(j) = last(i, &self.tokens)
+            && self.tokens[j] == Token::Delim('!')
+        {
+            important = true;
+            end = j;
+        }
+        let end = last(end, &self.tokens).map_or(self.pos, |i| i + 1);
+
+        if self.pos == end {
+            return Err(self.warning(self.pos, format!("missing value for '{}'", name)));
+        }
+
+        // the properties that take more than one value
+        let mut value = self.nested(self.pos, end);
+        let parsed = match name.to_ascii_lowercase().as_str() {
+            "content" => Some(value.parse_content()),
+            "counter-reset" => Some(value.parse_counters(0)),
+            "counter-increment" => Some(value.parse_counters(1)),
+            "quotes" => Some(value.parse_quotes()),
+            "unicode-range" => Some(value.parse_unicode_ranges()),
+            _ => None,
         };
-        assert_eq!(self.consume(), Token::Colon, "Expected ':' after property name");
-        let value = self.parse_value();
-        Declaration { name: name, value: value, }
+        if let Some(parsed) = parsed {
+            return parsed
+                .map(|value| Declaration { name: name.clone(), value, important })
+                .map_err(|warning| Warning { message: format!("{} for '{}'", warning.message, name), ..warning });
+        }
+
+        if end - self.pos > 1 {
+            return Err(self.warning(self.pos + 1, format!("only a single value is supported for '{}'", name)));
+        }
+        match parse_value(self.consume()) {
+            Ok(value) => Ok(Declaration { name, value, important }),
+            Err(message) => Err(self.warning(self.pos - 1, format!("{} for '{}'", message, name))),
+        }
+    }
+
+// ---------------------
+// values
+// ---------------------
+
+    // https://www.w3.org/TR/css-content-3/#content-property
+    // normal | none | [ <string> | attr() | counter() | counters() | open-quote | close-quote
+    //                 | no-open-quote | no-close-quote ]+
+    fn parse_content(&mut self) -> Result<Value, Warning> {
+        if let Some(keyword) = self.single_keyword(&["none", "normal"]) {
+            return Ok(keyword);
+        }
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let start = self.pos;
+            let item = match self.consume() {
+                Token::EOF => break,
+                Token::String(text) => ContentItem::String(text),
+                Token::Ident(keyword) => match keyword.to_ascii_lowercase().as_str() {
+                    "open-quote" => ContentItem::OpenQuote,
+                    "close-quote" => ContentItem::CloseQuote,
+                    "no-open-quote" => ContentItem::NoOpenQuote,
+                    "no-close-quote" => ContentItem::NoCloseQuote,
+                    _ => return Err(self.warning(start, format!("unexpected '{}'", keyword))),
+                },
+                Token::Function(name) => self.function_arguments(start).parse_content_function(&name)?,
+                token => return Err(self.warning(start, format!("unexpected '{}'", token))),
+            };
+            items.push(item);
+        }
+        Ok(Value::Content(items))
     }
 
-    fn parse_value(&mut self) -> Value {
-         match self.consume() {
-            Token::Dimension(v, unit) => {
-                match unit.as_str() {
-                    "px" => Value::Length(v, Unit::Px),
-                    _ => panic!("Unknown unit"),
+    // attr(name), counter(name, style) and counters(name, separator, style)
+    fn parse_content_function(&mut self, name: &str) -> Result<ContentItem, Warning> {
+        let mut arguments = Vec::new();
+        loop {
+            self.skip_whitespace();
+            arguments.push(self.consume());
+            self.skip_whitespace();
+            match self.consume() {
+                Token::Comma => {}
+                Token::EOF => break,
+                token => return Err(self.warning(self.pos - 1, format!("unexpected '{}' in '{}()'", token, name))),
+            }
+        }
+        let style = |style: &String| style.to_ascii_lowercase();
+        match (name.to_ascii_lowercase().as_str(), arguments.as_slice()) {
+            ("attr", [Token::Ident(attr)]) => Ok(ContentItem::Attr(attr.clone())),
+            ("counter", [Token::Ident(counter)]) => Ok(ContentItem::Counter(counter.clone(), "decimal".to_string())),
+            ("counter", [Token::Ident(counter), Token::Ident(s)]) => Ok(ContentItem::Counter(counter.clone(), style(s))),
+            ("counters", [Token::Ident(counter), Token::String(separator)]) => {
+                Ok(ContentItem::Counters(counter.clone(), separator.clone(), "decimal".to_string()))
+            }
+            ("counters", [Token::Ident(counter), Token::String(separator), Token::Ident(s)]) => {
+                Ok(ContentItem::Counters(counter.clone(), separator.clone(), style(s)))
+            }
+            ("attr" | "counter" | "counters", _) => Err(self.warning(0, format!("invalid arguments for '{}()'", name))),
+            _ => Err(self.warning(0, format!("unsupported function '{}()'", name))),
+        }
+    }
+
+    // https://www.w3.org/TR/css-lists-3/#counter-properties
+    // none | [ <counter-name> <integer>? ]+, where the integer defaults to 0 for
+    // counter-reset and 1 for counter-increment
+    fn parse_counters(&mut self, default: i32) -> Result<Value, Warning> {
+        if let Some(keyword) = self.single_keyword(&["none"]) {
+            return Ok(keyword);
+        }
+        let mut counters: Vec<(String, i32)> = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let start = self.pos;
+            match self.consume() {
+                Token::EOF => break,
+                Token::Ident(name) if !name.eq_ignore_ascii_case("none") => counters.push((name, default)),
+                Token::Number(value) if value.integer && !counters.is_empty() => {
+                    counters.last_mut().unwrap().1 = value.as_integer().unwrap();
                 }
+                token => return Err(self.warning(start, format!("unexpected '{}'", token))),
             }
-            Token::Number(v) => { 
-                Value::Length(v, Unit::Px)
+        }
+        Ok(Value::Counters(counters))
+    }
+
+    // https://www.w3.org/TR/css-content-3/#quotes-property
+    // auto | none | [ <string> <string> ]+
+    fn parse_quotes(&mut self) -> Result<Value, Warning> {
+        if let Some(keyword) = self.single_keyword(&["auto", "none"]) {
+            return Ok(keyword);
+        }
+        let mut strings = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let start = self.pos;
+            match self.consume() {
+                Token::EOF => break,
+                Token::String(quote) => strings.push(quote),
+                token => return Err(self.warning(start, format!("unexpected '{}'", token))),
             }
-            Token::Hash(color) => {
-                let r = u8::from_str_radix(&color[0..2], 16).unwrap();
-                let g = u8::from_str_radix(&color[2..4], 16).unwrap();
-                let b = u8::from_str_radix(&color[4..6], 16).unwrap();
-                Value::ColorValue(Color { r, g, b, a: 255 })
+        }
+        if strings.len() % 2 != 0 {
+            return Err(self.warning(self.pos, "quotes come in pairs".to_string()));
+        }
+        let pairs = strings.chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect();
+        Ok(Value::Quotes(pairs))
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#urange-syntax
+    // u+0025-00ff, u+4??: the tokens after the "u" are put back together as
+    // they were written and read as hex digits, '?' and a '-'
+    fn parse_unicode_ranges(&mut self) -> Result<Value, Warning> {
+        let mut ranges = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let start = self.pos;
+            let invalid = |parser: &Self| parser.warning(start, "invalid unicode-range".to_string());
+            if !matches!(self.consume(), Token::Ident(u) if u.eq_ignore_ascii_case("u")) {
+                return Err(invalid(self));
             }
-            Token::Ident(s) => {
-                Value::Keyword(s)
+            let mut text = String::new();
+            loop {
+                match self.curr_token() {
+                    Token::Delim(c @ ('+' | '?')) => text.push(c),
+                    Token::Ident(ident) => text.push_str(&ident),
+                    Token::Number(number) => text.push_str(&number.representation),
+                    Token::Dimension(number, unit) => {
+                        text.push_str(&number.representation);
+                        text.push_str(&unit);
+                    }
+                    _ => break,
+                }
+                self.consume();
             }
-            _ => {
-                panic!("Unsupported value")
+            ranges.push(unicode_range(&text).ok_or_else(|| invalid(self))?);
+
+            self.skip_whitespace();
+            match self.consume() {
+                Token::Comma => {}
+                Token::EOF => break,
+                token => return Err(self.warning(self.pos - 1, format!("unexpected '{}'", token))),
             }
         }
+        Ok(Value::UnicodeRange(ranges))
     }
 
 // ---------------------
@@ -171,16 +769,478 @@ impl CssParser {
 // ---------------------
 
     fn eof(&self) -> bool {
-        self.pos >= self.tokens.len()
+        self.curr_token() == Token::EOF
+    }
+
+    // true if there was any
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.pos;
+        while self.curr_token() == Token::Whitespace {
+            self.consume();
+        }
+        self.pos > start
     }
 
     fn curr_token(&self) -> Token {
-        if self.pos >= self.tokens.len() { Token::EOF } else { self.tokens[self.pos].clone() }
+        self.tokens.get(self.pos).cloned().unwrap_or(Token::EOF)
     }
 
+    // the EOF is never consumed
     fn consume(&mut self) -> Token {
-        let token = self.tokens[self.pos].clone();
-        self.pos += 1;
-        return token;
+        let token = self.curr_token();
+        if token != Token::EOF {
+            self.pos += 1;
+        }
+        token
+    }
+
+    // https://www.w3.org/TR/css-syntax-3/#consume-component-value
+    // a block or function goes in one piece, up to its closing token
+    fn consume_component_value(&mut self) {
+        let close = match self.consume() {
+            Token::CurlyOpen => Token::CurlyClose,
+            Token::BracketOpen => Token::BracketClose,
+            Token::ParenOpen | Token::Function(_) => Token::ParenClose,
+            _ => return,
+        };
+        loop {
+            let token = self.curr_token();
+            if token == close {
+                self.consume();
+                return;
+            }
+            if token == Token::EOF { return; }
+            self.consume_component_value();
+        }
+    }
+
+    // a parser for what a function starting at start takes, up to the matching ')'
+    fn function_arguments(&mut self, start: usize) -> CssParser {
+        self.pos = start;
+        self.consume_component_value();
+        let end = if self.tokens[self.pos - 1] == Token::ParenClose { self.pos - 1 } else { self.pos };
+        self.nested(start + 1, end)
+    }
+
+    // the value when it is nothing but one of these keywords
+    fn single_keyword(&self, keywords: &[&str]) -> Option<Value> {
+        match self.tokens.as_slice() {
+            [Token::Ident(keyword), Token::EOF] => {
+                let keyword = keyword.to_ascii_lowercase();
+                keywords.contains(&keyword.as_str()).then_some(Value::Keyword(keyword))
+            }
+            _ => None,
+        }
+    }
+
+    // the tokens inside a {} block, which may be cut off by the end of input
+    fn block_content_end(&self, block_start: usize, block_end: usize) -> usize {
+        if block_end > block_start + 1 && self.tokens[block_end - 1] == Token::CurlyClose {
+            block_end - 1
+        } else {
+            block_end
+        }
+    }
+
+    // a parser for the tokens from start to end, which reports at the same locations
+    fn nested(&self, start: usize, end: usize) -> CssParser {
+        let mut tokens = self.tokens[start..end].to_vec();
+        tokens.push(Token::EOF);
+        CssParser {
+            pos: 0,
+            tokens,
+            locations: self.locations[start..=end].to_vec(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn warning(&self, index: usize, message: String) -> Warning {
+        let location = self.locations[index.min(self.locations.len() - 1)];
+        Warning { message, location }
+    }
+
+    fn warn(&mut self, index: usize, message: String) {
+        let warning = self.warning(index, message);
+        self.warnings.push(warning);
+    }
+}
+
+fn contains_has(selector: &Selector) -> bool {
+    match selector {
+        Selector::Simple(simple) => simple.pseudo_classes.iter().any(|pseudo| match pseudo {
+            PseudoClass::Has(_) => true,
+            PseudoClass::Not(selectors) | PseudoClass::Is(selectors) | PseudoClass::Where(selectors)
+            | PseudoClass::NthChild(_, selectors) | PseudoClass::NthLastChild(_, selectors) => {
+                selectors.iter().any(contains_has)
+            }
+            _ => false,
+        }),
+        Selector::Descendant(a, b)
+        | Selector::Child(a, b)
+        | Selector::AdjacentSibling(a, b)
+        | Selector::GeneralSibling(a, b) => contains_has(a) || contains_has(b),
+    }
+}
+
+// the text after the "u" of a unicode-range
+fn unicode_range(text: &str) -> Option<(u32, u32)> {
+    let text = text.strip_prefix('+')?;
+    let digits = text.chars().take_while(|c| c.is_ascii_hexdigit()).count();
+    let wildcards = text[digits..].chars().take_while(|&c| c == '?').count();
+    let (first, rest) = text.split_at(digits + wildcards);
+    if first.is_empty() || first.len() > 6 {
+        return None;
+    }
+    let hex = |digits: &str| u32::from_str_radix(digits, 16).unwrap();
+
+    let (start, end) = if wildcards > 0 {
+        if !rest.is_empty() {
+            return None;
+        }
+        (hex(&first.replace('?', "0")), hex(&first.replace('?', "F")))
+    } else if let Some(last) = rest.strip_prefix('-') {
+        if last.is_empty() || last.len() > 6 || !last.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        (hex(first), hex(last))
+    } else if rest.is_empty() {
+        (hex(first), hex(first))
+    } else {
+        return None;
+    };
+    (end <= 0x10FFFF && start <= end).then_some((start, end))
+}
+
+fn parse_value(token: Token) -> Result<Value, String> {
+    match token {
+        Token::Dimension(v, unit) => {
+            match unit.to_ascii_lowercase().as_str() {
+                "px" => Ok(Value::Length(v.value, Unit::Px)),
+                _ => Err(format!("unknown unit '{}'", unit)),
+            }
+        }
+        Token::Number(v) => {
+            Ok(Value::Length(v.value, Unit::Px))
+        }
+        Token::Hash(color) => {
+            parse_hex_color(&color).ok_or(format!("invalid color '#{}'", color))
+        }
+        Token::Ident(s) => {
+            Ok(Value::Keyword(s))
+        }
+        token => {
+            Err(format!("unsupported value '{}'", token))
+        }
+    }
+}
+
+// https://www.w3.org/TR/css-color-4/#hex-notation
+// #rgb, #rgba, #rrggbb and #rrggbbaa
+fn parse_hex_color(color: &str) -> Option<Value> {
+    if !color.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digits: Vec<u8> = match color.len() {
+        3 | 4 => color.chars().map(|c| c.to_digit(16).unwrap() as u8 * 17).collect(),
+        6 | 8 => (0..color.len()).step_by(2).map(|i| u8::from_str_radix(&color[i..i + 2], 16).unwrap()).collect(),
+        _ => return None,
+    };
+    let a = digits.get(3).copied().unwrap_or(255);
+    Some(Value::ColorValue(Color { r: digits[0], g: digits[1], b: digits[2], a }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selector(css: &str) -> Selector {
+        let mut selectors = CssParser::parse_selector_list(css.to_string()).unwrap();
+        assert_eq!(selectors.len(), 1);
+        selectors.remove(0)
+    }
+
+    fn tag(name: &str) -> Box<Selector> {
+        Box::new(Selector::Simple(SimpleSelector {
+            tag: Some(name.to_string()),
+            id: None,
+            class: Vec::new(),
+            attributes: Vec::new(),
+            pseudo_classes: Vec::new(),
+            pseudo_element: None,
+        }))
+    }
+
+    fn value(css: &str) -> Result<Value, String> {
+        let (sheet, warnings) = CssParser::parse_stylesheet_with_warnings(format!("a {{ {} }}", css));
+        match warnings.first() {
+            Some(warning) => Err(warning.message.clone()),
+            None => Ok(sheet.rules[0].declarations[0].value.clone()),
+        }
+    }
+
+    fn nth(argument: &str) -> Option<Nth> {
+        let selectors = CssParser::parse_selector_list(format!(":nth-child({})", argument)).ok()?;
+        match &selectors[0] {
+            Selector::Simple(simple) => match &simple.pseudo_classes[0] {
+                PseudoClass::NthChild(nth, _) => Some(*nth),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn an_plus_b() {
+        let cases = [
+            ("odd", 2, 1), ("EVEN", 2, 0), ("5", 0, 5), ("-5", 0, -5), ("+5", 0, 5),
+            ("n", 1, 0), ("+n", 1, 0), ("-n", -1, 0), ("2n", 2, 0), ("-2n", -2, 0),
+            ("2n+1", 2, 1), ("2n-1", 2, -1), ("2n + 1", 2, 1), ("2n - 1", 2, -1), ("2n +1", 2, 1),
+            ("-n+3", -1, 3), ("n- 3", 1, -3), ("-n- 3", -1, -3), ("+n-3", 1, -3), ("3n-12", 3, -12),
+        ];
+        for (argument, a, b) in cases {
+            assert_eq!(nth(argument), Some(Nth { a, b }), "{}", argument);
+        }
+    }
+
+    // every path clamps to the same range
+    #[test]
+    fn out_of_range_an_plus_b() {
+        let max = i32::MAX;
+        let cases = [
+            ("-99999999999", 0, -max), ("99999999999", 0, max), ("n-99999999999", 1, -max),
+            ("n- 99999999999", 1, -max), ("-n+99999999999", -1, max), ("2n-99999999999999999999999", 2, -max),
+            ("99999999999n+1", max, 1), ("-99999999999n", -max, 0),
+        ];
+        for (argument, a, b) in cases {
+            assert_eq!(nth(argument), Some(Nth { a, b }), "{}", argument);
+        }
+    }
+
+    #[test]
+    fn invalid_an_plus_b() {
+        let cases = [
+            "2n 1", "2n + -1", "2n - +1", "n- -3", "1.0", "2n+1.5", "2.0n", "1e1", "+ n", "+-n",
+            "2n++1", "3n-", "n-a", "foo", "",
+        ];
+        for argument in cases {
+            assert_eq!(nth(argument), None, "{}", argument);
+        }
+    }
+
+    #[test]
+    fn nth_child_of_a_selector_list() {
+        let Selector::Simple(simple) = selector(":nth-child(2n+1 of p, .a)") else { panic!() };
+        let PseudoClass::NthChild(nth, selectors) = &simple.pseudo_classes[0] else { panic!() };
+        assert_eq!(*nth, Nth { a: 2, b: 1 });
+        assert_eq!(selectors.len(), 2);
+        for invalid in [":nth-child(2n of)", ":nth-child(of p)", ":nth-of-type(1 of p)", ":nth-child(1 of ::before)"] {
+            assert!(CssParser::parse_selector_list(invalid.to_string()).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn logical_pseudo_classes() {
+        let Selector::Simple(simple) = selector("a:has(> img, + p ~ b)") else { panic!() };
+        let PseudoClass::Has(relatives) = &simple.pseudo_classes[0] else { panic!() };
+        assert_eq!(relatives[0], RelativeSelector { combinator: Combinator::Child, selector: *tag("img") });
+        assert_eq!(relatives[1].combinator, Combinator::AdjacentSibling);
+        assert_eq!(relatives[1].selector, Selector::GeneralSibling(tag("p"), tag("b")));
+        for css in [":has()", ":has(:has(p))", ":has(p::before)", ":not()", ":not(p::after)", ":has(> )"] {
+            assert!(CssParser::parse_selector_list(css.to_string()).is_err(), "{}", css);
+        }
+        // the lists of :is() and :where() forgive what they cannot parse
+        let Selector::Simple(simple) = selector(":is(p::before, :nope, b):where(,)") else { panic!() };
+        assert_eq!(simple.pseudo_classes, [PseudoClass::Is(vec![*tag("b")]), PseudoClass::Where(Vec::new())]);
+    }
+
+    #[test]
+    fn combinators_group_to_the_left() {
+        let descendant = Selector::Descendant(tag("a"), tag("b"));
+        let child = Selector::Child(Box::new(descendant), tag("c"));
+        let adjacent = Selector::AdjacentSibling(Box::new(child), tag("d"));
+        let expected = Selector::GeneralSibling(Box::new(adjacent), tag("e"));
+        assert_eq!(selector("a b>c + d~ e"), expected);
+        assert_eq!(selector("  a\tb > c+d ~e  "), expected);
+    }
+
+    fn attribute(css: &str) -> Result<AttributeSelector, String> {
+        match CssParser::parse_selector_list(css.to_string()).map_err(|warning| warning.message)?.remove(0) {
+            Selector::Simple(mut simple) => Ok(simple.attributes.remove(0)),
+            selector => panic!("unexpected selector {:?}", selector),
+        }
+    }
+
+    #[test]
+    fn attribute_selectors() {
+        let cases = [
+            ("[href]", "href", AttributeOperator::Exists, ""),
+            ("[ type = text ]", "type", AttributeOperator::Equals, "text"),
+            ("[class~=a]", "class", AttributeOperator::Includes, "a"),
+            ("[lang|=\"en\"]", "lang", AttributeOperator::DashMatch, "en"),
+            ("[href^='http']", "href", AttributeOperator::Prefix, "http"),
+            ("[href$=\".pdf\"]", "href", AttributeOperator::Suffix, ".pdf"),
+            ("[title*=\"a b\"]", "title", AttributeOperator::Substring, "a b"),
+        ];
+        for (css, name, operator, value) in cases {
+            let expected = AttributeSelector {
+                name: name.to_string(),
+                operator,
+                value: value.to_string(),
+                case: AttributeCase::Default,
+            };
+            assert_eq!(attribute(css), Ok(expected), "{}", css);
+        }
+    }
+
+    #[test]
+    fn attribute_case_flags() {
+        assert_eq!(attribute("[a=b i]").map(|a| a.case), Ok(AttributeCase::Insensitive));
+        assert_eq!(attribute("[a='b'S]").map(|a| a.case), Ok(AttributeCase::Sensitive));
+        assert_eq!(attribute("[a=b]").map(|a| a.case), Ok(AttributeCase::Default));
+        for invalid in ["[]", "[a", "[a=]", "[a~ =b]", "[a==b]", "[a=b x]", "[a=b i s]", "[a i]", "[1=b]"] {
+            assert!(attribute(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn u_is_an_element() {
+        for css in ["u+a", "u+b", "u+em", "u + a"] {
+            let name = css.trim_start_matches(['u', '+', ' ']);
+            assert_eq!(selector(css), Selector::AdjacentSibling(tag("u"), tag(name)), "{}", css);
+        }
+    }
+
+    #[test]
+    fn unicode_ranges() {
+        assert_eq!(value("unicode-range: u+26"), Ok(Value::UnicodeRange(vec![(0x26, 0x26)])));
+        assert_eq!(value("unicode-range: U+0025-00FF, u+4??"), Ok(Value::UnicodeRange(vec![(0x25, 0xFF), (0x400, 0x4FF)])));
+        assert_eq!(value("unicode-range: u+1e3"), Ok(Value::UnicodeRange(vec![(0x1E3, 0x1E3)])));
+        assert_eq!(value("unicode-range: u+a-f"), Ok(Value::UnicodeRange(vec![(0xA, 0xF)])));
+        assert_eq!(value("unicode-range: u+10????"), Ok(Value::UnicodeRange(vec![(0x100000, 0x10FFFF)])));
+        for invalid in ["u+??????", "u+1234567", "u+5-3", "u+3?-5", "u +3", "u+", "v+3", "u+g"] {
+            assert!(value(&format!("unicode-range: {}", invalid)).is_err(), "{}", invalid);
+        }
+    }
+
+    // the declaration names of each rule kept, and where and why something was dropped
+    fn recover(css: &str) -> (Vec<Vec<String>>, Vec<String>) {
+        let (sheet, warnings) = CssParser::parse_stylesheet_with_warnings(css.to_string());
+        let rules = sheet.rules.iter()
+            .map(|rule| rule.declarations.iter().map(|declaration| declaration.name.clone()).collect())
+            .collect();
+        (rules, warnings.iter().map(|warning| warning.to_string()).collect())
+    }
+
+    #[test]
+    fn invalid_declarations_are_dropped_up_to_the_semicolon() {
+        let (rules, warnings) = recover("a { color: ; width: 10em; display: block; ! ; 5: 3; width 3px; x: (;}); top: 1px }");
+        assert_eq!(rules, [["display", "top"]]);
+        assert_eq!(warnings, [
+            "1:12: missing value for 'color', declaration dropped",
+            "1:21: unknown unit 'em' for 'width', declaration dropped",
+            "1:43: expected a property name, found '!', declaration dropped",
+            "1:47: expected a property name, found '5', declaration dropped",
+            "1:59: expected ':' after 'width', declaration dropped",
+            "1:68: only a single value is supported for 'x', declaration dropped",
+        ]);
+    }
+
+    #[test]
+    fn inline_style_reports_what_it_drops() {
+        let css = "width: 10em; display: none".to_string();
+        let (declarations, warnings) = CssParser::parse_inline_style_with_warnings(css);
+        assert_eq!(declarations.iter().map(|declaration| declaration.name.as_str()).collect::<Vec<_>>(), ["display"]);
+        let warnings: Vec<_> = warnings.iter().map(|warning| warning.to_string()).collect();
+        assert_eq!(warnings, ["1:8: unknown unit 'em' for 'width', declaration dropped"]);
+    }
+
+    #[test]
+    fn invalid_rules_are_dropped_up_to_their_block() {
+        let (rules, warnings) = recover("a:nope { p { x: y } } b { display: none } c[x=] { } d { top: 1px }");
+        assert_eq!(rules, [vec!["display"], vec!["top"]]);
+        assert_eq!(warnings, [
+            "1:3: unsupported pseudo-class ':nope', rule dropped",
+            "1:47: expected an attribute value, found ']', rule dropped",
+        ]);
+    }
+
+    #[test]
+    fn at_rules_are_skipped() {
+        let (rules, warnings) = recover("@import \"x.css\"; @media print { a { top: 1px } } a { @page { } display: block }");
+        assert_eq!(rules, [["display"]]);
+        assert_eq!(warnings, [
+            "1:1: unsupported at-rule '@import', rule dropped",
+            "1:18: unsupported at-rule '@media', rule dropped",
+            "1:54: unsupported at-rule '@page', rule dropped",
+        ]);
+    }
+
+    #[test]
+    fn blocks_are_matched_when_skipping() {
+        // the brackets run to the end, and take the braces with them
+        assert_eq!(recover("c[ { } d { top: 1px }").0, Vec::<Vec<String>>::new());
+        assert_eq!(recover("a { x: [;] (;) {;}; top: 1px }").0, [["top"]]);
+    }
+
+    #[test]
+    fn unfinished_input() {
+        assert_eq!(recover("a { display: block").0, [["display"]]);
+        assert_eq!(recover("a { top: 1px } b").1, ["1:16: unexpected end of input before '{', rule dropped"]);
+        for css in ["}}} ;; { a { b", "a { x: (", "a[", ":", "::", "a:nth-child(", "{", "@", "a { !important }"] {
+            let (rules, warnings) = recover(css);
+            assert!(rules.is_empty() || !warnings.is_empty(), "{}", css);
+        }
+    }
+
+    #[test]
+    fn counter_properties() {
+        let counters = |list: &[(&str, i32)]| {
+            Ok(Value::Counters(list.iter().map(|&(name, value)| (name.to_string(), value)).collect()))
+        };
+        assert_eq!(value("counter-reset: a 2 b"), counters(&[("a", 2), ("b", 0)]));
+        assert_eq!(value("counter-increment: a b -1"), counters(&[("a", 1), ("b", -1)]));
+        assert_eq!(value("counter-increment: a +3"), counters(&[("a", 3)]));
+        assert_eq!(value("counter-reset: a 16777217"), counters(&[("a", 16777217)]));
+        assert_eq!(value("counter-reset: none"), Ok(Value::Keyword("none".to_string())));
+        // only integers, which a '.' or an exponent rules out even when the value is whole
+        for invalid in ["a 1.0", "a 1e1", "a 1.5", "2", "a none", "a 'b'", "none a"] {
+            assert!(value(&format!("counter-reset: {}", invalid)).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn content_and_quotes() {
+        assert_eq!(
+            value("content: 'a' attr(href) counter(n) counters(n, '.', upper-roman) open-quote NO-CLOSE-QUOTE"),
+            Ok(Value::Content(vec![
+                ContentItem::String("a".to_string()),
+                ContentItem::Attr("href".to_string()),
+                ContentItem::Counter("n".to_string(), "decimal".to_string()),
+                ContentItem::Counters("n".to_string(), ".".to_string(), "upper-roman".to_string()),
+                ContentItem::OpenQuote,
+                ContentItem::NoCloseQuote,
+            ]))
+        );
+        assert_eq!(value("content: none"), Ok(Value::Keyword("none".to_string())));
+        for invalid in ["attr()", "attr(a, b)", "counters(n)", "counter('n')", "url(x)", "foo", "'a' none"] {
+            assert!(value(&format!("content: {}", invalid)).is_err(), "{}", invalid);
+        }
+
+        let pairs = vec![("<".to_string(), ">".to_string()), ("[".to_string(), "]".to_string())];
+        assert_eq!(value("quotes: '<' '>' '[' ']'"), Ok(Value::Quotes(pairs)));
+        assert_eq!(value("quotes: auto"), Ok(Value::Keyword("auto".to_string())));
+        assert!(value("quotes: '<'").is_err());
+    }
+
+    #[test]
+    fn pseudo_elements() {
+        for css in ["p::before", "p:before", "p::AFTER", "p:after"] {
+            assert!(selector(css).pseudo_element().is_some(), "{}", css);
+        }
+        assert_eq!(selector("div > p.a::after").pseudo_element(), Some(PseudoElement::After));
+        assert_eq!(selector("p::before").specificity(), (0, 0, 2));
+        for invalid in ["p::first-line", "p::before span", "p::before.a", "p::before::after", "::selection"] {
+            assert!(CssParser::parse_selector_list(invalid.to_string()).is_err(), "{}", invalid);
+        }
     }
 }
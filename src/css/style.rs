@@ -1,13 +1,26 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 
-use super::super::html::dom::{Node, NodeType, ElementData};
-use super::stylesheet::{Value, Stylesheet, Selector, SimpleSelector};
+use super::super::html::dom::{NodeId, NodeRef, NodeType, ElementData};
+use super::stylesheet::{
+    Value, Stylesheet, Selector, SimpleSelector, AttributeSelector, AttributeOperator, AttributeCase, PseudoClass,
+    RelativeSelector, Combinator, PseudoElement, ContentItem,
+};
 
 #[derive(Debug)]
 pub struct StyledNode<'a> {
-    pub node: &'a Node,
+    pub node: NodeRef<'a>,
     pub styles: HashMap<String, Value>,
     pub children: Vec<StyledNode<'a>>,
+    // only on the node of a ::before or ::after, whose `node` is the element it belongs to
+    pub generated: Option<Generated>,
+}
+
+#[derive(Debug)]
+pub struct Generated {
+    pub pseudo_element: PseudoElement,
+    // what `content` comes to for this element
+    pub text: String,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -17,6 +30,25 @@ pub enum Display {
     None,
 }
 
+// https://drafts.csswg.org/css-text/#white-space-property
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WhiteSpace {
+    #[default]
+    Normal,
+    Pre,
+    Nowrap,
+    PreWrap,
+    BreakSpaces,
+    PreLine,
+}
+
+impl WhiteSpace {
+    // spaces and tabs collapse, and so do line breaks unless it is pre-line
+    pub fn collapses_spaces(self) -> bool {
+        matches!(self, WhiteSpace::Normal | WhiteSpace::Nowrap | WhiteSpace::PreLine)
+    }
+}
+
 // pub fn build_styled_tree<'a>(node: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
 //     let styles = get_styles(node, stylesheet);
 //     let children = node.children.iter()
@@ -30,33 +62,116 @@ pub enum Display {
 // }
 
 impl<'a> StyledNode<'a> {
-    pub fn build_styled_tree(node: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
-        StyledNode {
-            node,
-            styles: match &node.node_type {
-                NodeType::Element(elem) => Self::specified_values(elem, stylesheet),
-                NodeType::Text(_) => HashMap::new(),
-                NodeType::Comment(_) => HashMap::new(),
-            },
-            children: node.children.iter()
-                .map(|child| StyledNode::build_styled_tree(child, stylesheet))
-                .collect(),
+    pub fn build_styled_tree(node: NodeRef<'a>, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
+        // one context for the whole tree, so :has() looks at each subtree once per
+        // build; it is dropped with the build, so a changed dom never sees stale answers
+        let cx = MatchingContext::default();
+        Self::build_styled_node(&cx, &mut ContentState::default(), node, stylesheet)
+    }
+
+    // in document order, which is the order counters and quotes are counted in
+    fn build_styled_node(
+        cx: &MatchingContext,
+        content: &mut ContentState,
+        node: NodeRef<'a>,
+        stylesheet: &'a Stylesheet,
+    ) -> StyledNode<'a> {
+        let styles = match node.node_type() {
+            NodeType::Element(_) => Self::specified_values(cx, node, None, stylesheet),
+            NodeType::Document => HashMap::new(),
+            NodeType::DocumentFragment => HashMap::new(),
+            NodeType::Doctype(_) => HashMap::new(),
+            NodeType::Text(_) => HashMap::new(),
+            NodeType::Comment(_) => HashMap::new(),
+        };
+        let (quotes, hidden) = (content.quotes.clone(), content.hidden);
+        content.hidden |= is_display_none(&styles);
+        content.update(&styles);
+        // the counters of an element are also seen by its following siblings,
+        // so only the ones its descendants make go out of scope here
+        let counters = content.counters.len();
+        content.depth += 1;
+
+        let mut children = Vec::new();
+        let elem = node.as_element().filter(|_| !content.hidden);
+        if let Some(elem) = elem {
+            children.extend(Self::build_pseudo_element(cx, content, node, elem, PseudoElement::Before, stylesheet));
+        }
+        for child in node.children() {
+            children.push(StyledNode::build_styled_node(cx, content, child, stylesheet));
         }
+        if let Some(elem) = elem {
+            children.extend(Self::build_pseudo_element(cx, content, node, elem, PseudoElement::After, stylesheet));
+        }
+
+        content.depth -= 1;
+        content.counters.truncate(counters);
+        content.quotes = quotes;
+        content.hidden = hidden;
+        StyledNode { node, styles, children, generated: None }
     }
 
-    fn specified_values(elem: &ElementData, stylesheet: &Stylesheet) -> HashMap<String, Value> {
+    // https://www.w3.org/TR/css-pseudo-4/#generated-content
+    // there is a box only when `content` says what goes into it
+    fn build_pseudo_element(
+        cx: &MatchingContext,
+        content: &mut ContentState,
+        node: NodeRef<'a>,
+        elem: &ElementData,
+        pseudo_element: PseudoElement,
+        stylesheet: &'a Stylesheet,
+    ) -> Option<StyledNode<'a>> {
+        let styles = Self::specified_values(cx, node, Some(pseudo_element), stylesheet);
+        let Some(Value::Content(items)) = styles.get("content") else {
+            return None;
+        };
+        if is_display_none(&styles) {
+            return None;
+        }
+        let quotes = content.quotes.clone();
+        content.update(&styles);
+        let text = content.generate(items, elem);
+        content.quotes = quotes;
+        Some(StyledNode {
+            node,
+            styles,
+            children: Vec::new(),
+            generated: Some(Generated { pseudo_element, text }),
+        })
+    }
+
+    // the rules for the element itself, or for one of its pseudo-elements
+    fn specified_values(
+        cx: &MatchingContext,
+        node: NodeRef,
+        pseudo_element: Option<PseudoElement>,
+        stylesheet: &Stylesheet,
+    ) -> HashMap<String, Value> {
         let mut values = HashMap::new();
         let mut rules = stylesheet.rules.iter()
             .filter_map(|rule| {
                 rule.selectors.iter()
-                    .find(|selector| matches(elem, selector))
+                    .find(|selector| selector.pseudo_element() == pseudo_element && matches_in(cx, node, selector))
                     .map(|selector| (selector.specificity(), rule))
             })
             .collect::<Vec<_>>();
 
-        rules.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
-        for (_, rule) in rules {
-            for declaration in &rule.declarations {
+        rules.sort_by_key(|&(specificity, _)| specificity);
+        // style="" is for the element only
+        let inline = stylesheet.inline_styles.get(&node.id)
+            .filter(|_| pseudo_element.is_none())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        // inline style="" wins over every rule, and !important turns that
+        // order around: rules, style="", !important rules, !important style=""
+        // https://www.w3.org/TR/css-cascade-4/#cascade-origin
+        for important in [false, true] {
+            let declarations = rules.iter()
+                .flat_map(|(_, rule)| &rule.declarations)
+                .chain(inline)
+                .filter(|declaration| declaration.important == important);
+            for declaration in declarations {
                 values.insert(declaration.name.clone(), declaration.value.clone());
             }
         }
@@ -74,6 +189,22 @@ impl<'a> StyledNode<'a> {
         }
     }
 
+    // only the specified value; white-space is inherited, which layout takes care of
+    pub fn white_space(&self) -> Option<WhiteSpace> {
+        match self.value("white-space") {
+            Some(Value::Keyword(s)) => match &*s {
+                "normal" => Some(WhiteSpace::Normal),
+                "pre" => Some(WhiteSpace::Pre),
+                "nowrap" => Some(WhiteSpace::Nowrap),
+                "pre-wrap" => Some(WhiteSpace::PreWrap),
+                "break-spaces" => Some(WhiteSpace::BreakSpaces),
+                "pre-line" => Some(WhiteSpace::PreLine),
+                _ => None
+            },
+            _ => None
+        }
+    }
+
     pub fn value(&self, name: &str) -> Option<Value> {
         self.styles.get(name).cloned()
     }
@@ -135,27 +266,104 @@ impl<'a> StyledNode<'a> {
 //     return selector.matches(&elem.tag_name, id, &classes);
 // }
 
-fn matches(elem: &ElementData, selector: &Selector) -> bool {
+// state shared by the matching of many selectors against one unchanged dom
+#[derive(Debug, Default)]
+pub struct MatchingContext {
+    // whether the element matches the relative selector of a :has()
+    has_cache: RefCell<HashMap<(NodeId, *const RelativeSelector), bool>>,
+}
+
+// a selector for a pseudo-element never matches the element itself
+pub fn matches(node: NodeRef, selector: &Selector) -> bool {
+    selector.pseudo_element().is_none() && matches_in(&MatchingContext::default(), node, selector)
+}
+
+// the pseudo-element of the selector is left to the caller, so this tells
+// whether the element is the one it belongs to
+pub fn matches_in(cx: &MatchingContext, node: NodeRef, selector: &Selector) -> bool {
+    let Some(elem) = node.as_element() else {
+        return false;
+    };
+    // right to left: the right side is the element itself, and only when it
+    // matches do we walk up the ancestors or back over the previous siblings
+    // looking for one that matches the left side
     match selector {
         Selector::Simple(simple) => {
-            matches_simple(elem, simple)
+            matches_simple(cx, node, elem, simple)
         },
-        Selector::Descendant(_, _) => {
-            false
+        Selector::Descendant(ancestor, subject) => {
+            matches_in(cx, node, subject) && ancestors(node).any(|a| matches_in(cx, a, ancestor))
         },
-        Selector::Child(_, _) => {
-            false
+        Selector::Child(parent, subject) => {
+            matches_in(cx, node, subject) && node.parent().is_some_and(|p| matches_in(cx, p, parent))
         },
-        Selector::AdjacentSibling(_, _) => {
-            false
+        Selector::AdjacentSibling(previous, subject) => {
+            matches_in(cx, node, subject) && previous_siblings(node).next().is_some_and(|p| matches_in(cx, p, previous))
         },
-        Selector::GeneralSibling(_, _) => {
-            false
+        Selector::GeneralSibling(previous, subject) => {
+            matches_in(cx, node, subject) && previous_siblings(node).any(|p| matches_in(cx, p, previous))
         },
     }
 }
 
-fn matches_simple(elem: &ElementData, simple: &SimpleSelector) -> bool {
+// https://www.w3.org/TR/selectors-4/#relational
+// the other way around: a relative selector starts at the anchor, so it is
+// matched left to right, following each combinator down or forward from there
+fn matches_relative(cx: &MatchingContext, anchor: NodeRef, relative: &RelativeSelector) -> bool {
+    let key = (anchor.id, relative as *const RelativeSelector);
+    if let Some(&matched) = cx.has_cache.borrow().get(&key) {
+        return matched;
+    }
+    let mut compounds = Vec::new();
+    compound_selectors(&relative.selector, relative.combinator, &mut compounds);
+    let matched = matches_forward(cx, anchor, &compounds);
+    cx.has_cache.borrow_mut().insert(key, matched);
+    matched
+}
+
+fn matches_forward(cx: &MatchingContext, anchor: NodeRef, compounds: &[(Combinator, &SimpleSelector)]) -> bool {
+    let Some((&(combinator, simple), rest)) = compounds.split_first() else {
+        return true;
+    };
+    let mut candidates: Box<dyn Iterator<Item = NodeRef>> = match combinator {
+        Combinator::Descendant => Box::new(anchor.descendants().filter(|n| n.as_element().is_some())),
+        Combinator::Child => Box::new(anchor.children().filter(|n| n.as_element().is_some())),
+        Combinator::AdjacentSibling => Box::new(next_siblings(anchor).take(1)),
+        Combinator::GeneralSibling => Box::new(next_siblings(anchor)),
+    };
+    candidates.any(|n| {
+        n.as_element().is_some_and(|elem| matches_simple(cx, n, elem, simple)) && matches_forward(cx, n, rest)
+    })
+}
+
+// the compound selectors of a selector from left to right, each with the
+// combinator that leads to it
+fn compound_selectors<'s>(selector: &'s Selector, combinator: Combinator, out: &mut Vec<(Combinator, &'s SimpleSelector)>) {
+    let (left, right, inner) = match selector {
+        Selector::Simple(simple) => return out.push((combinator, simple)),
+        Selector::Descendant(left, right) => (left, right, Combinator::Descendant),
+        Selector::Child(left, right) => (left, right, Combinator::Child),
+        Selector::AdjacentSibling(left, right) => (left, right, Combinator::AdjacentSibling),
+        Selector::GeneralSibling(left, right) => (left, right, Combinator::GeneralSibling),
+    };
+    compound_selectors(left, combinator, out);
+    compound_selectors(right, inner, out);
+}
+
+// only elements take part in combinators; text and comments in between are skipped
+fn ancestors<'a>(node: NodeRef<'a>) -> impl Iterator<Item = NodeRef<'a>> {
+    std::iter::successors(node.parent(), |n| n.parent()).filter(|n| n.as_element().is_some())
+}
+
+fn previous_siblings<'a>(node: NodeRef<'a>) -> impl Iterator<Item = NodeRef<'a>> {
+    std::iter::successors(node.prev_sibling(), |n| n.prev_sibling()).filter(|n| n.as_element().is_some())
+}
+
+fn next_siblings<'a>(node: NodeRef<'a>) -> impl Iterator<Item = NodeRef<'a>> {
+    std::iter::successors(node.next_sibling(), |n| n.next_sibling()).filter(|n| n.as_element().is_some())
+}
+
+fn matches_simple(cx: &MatchingContext, node: NodeRef, elem: &ElementData, simple: &SimpleSelector) -> bool {
     let elem_classes: Vec<&str> = elem.attributes.get("class")
                                     .map(|s| s.split_whitespace().collect())
                                     .unwrap_or_default();
@@ -163,9 +371,615 @@ fn matches_simple(elem: &ElementData, simple: &SimpleSelector) -> bool {
     if simple.tag.iter().any(|name| name != &elem.tag)
         || simple.id.iter().any(|id| Some(id) != elem.attributes.get("id"))
         || simple.class.iter().any(|class| !elem_classes.contains(&class.as_str()))
+        || simple.attributes.iter().any(|attribute| !matches_attribute(elem, attribute))
+        || simple.pseudo_classes.iter().any(|pseudo| !matches_pseudo_class(cx, node, elem, pseudo))
     {
         return false;
     }
-    
-    return true
-}
\ No newline at end of file
+
+    true
+}
+
+// https://www.w3.org/TR/selectors-4/#attribute-representation
+fn matches_attribute(elem: &ElementData, selector: &AttributeSelector) -> bool {
+    // attribute names of html elements are stored in lowercase
+    let name = if elem.is_html() { selector.name.to_ascii_lowercase() } else { selector.name.clone() };
+    let Some(value) = elem.attributes.get(&name) else {
+        return false;
+    };
+
+    let insensitive = match selector.case {
+        AttributeCase::Insensitive => true,
+        AttributeCase::Sensitive => false,
+        AttributeCase::Default => elem.is_html() && is_case_insensitive_attribute(&name),
+    };
+    let (value, wanted) = if insensitive {
+        (value.to_ascii_lowercase(), selector.value.to_ascii_lowercase())
+    } else {
+        (value.clone(), selector.value.clone())
+    };
+
+    match selector.operator {
+        AttributeOperator::Exists => true,
+        AttributeOperator::Equals => value == wanted,
+        AttributeOperator::Includes => {
+            !wanted.is_empty() && !wanted.contains([' ', '\t', '\n', '\r', '\x0C'])
+                && value.split_ascii_whitespace().any(|word| word == wanted)
+        }
+        AttributeOperator::DashMatch => {
+            value == wanted || value.strip_prefix(&wanted).is_some_and(|rest| rest.starts_with('-'))
+        }
+        AttributeOperator::Prefix => !wanted.is_empty() && value.starts_with(&wanted),
+        AttributeOperator::Suffix => !wanted.is_empty() && value.ends_with(&wanted),
+        AttributeOperator::Substring => !wanted.is_empty() && value.contains(&wanted),
+    }
+}
+
+// https://www.w3.org/TR/selectors-4/#structural-pseudos
+fn matches_pseudo_class(cx: &MatchingContext, node: NodeRef, elem: &ElementData, pseudo: &PseudoClass) -> bool {
+    let same_type = |n: &NodeRef| n.as_element().is_some_and(|e| e.tag == elem.tag && e.namespace == elem.namespace);
+    // of S only counts the siblings that match S, and the element has to match it too
+    let of = |selectors: &[Selector], n: &NodeRef| selectors.is_empty() || selectors.iter().any(|s| matches_in(cx, *n, s));
+    let position = |siblings: &mut dyn Iterator<Item = NodeRef>| siblings.count() as i32 + 1;
+
+    match pseudo {
+        PseudoClass::Root => node.parent().is_some_and(|parent| matches!(parent.node_type(), NodeType::Document)),
+        // comments don't count, and neither does text that has no characters
+        PseudoClass::Empty => node.children().all(|child| match child.node_type() {
+            NodeType::Text(text) => text.is_empty(),
+            NodeType::Comment(_) => true,
+            _ => false,
+        }),
+        PseudoClass::FirstChild => previous_siblings(node).next().is_none(),
+        PseudoClass::LastChild => next_siblings(node).next().is_none(),
+        PseudoClass::OnlyChild => previous_siblings(node).next().is_none() && next_siblings(node).next().is_none(),
+        PseudoClass::FirstOfType => !previous_siblings(node).any(|n| same_type(&n)),
+        PseudoClass::LastOfType => !next_siblings(node).any(|n| same_type(&n)),
+        PseudoClass::OnlyOfType => !previous_siblings(node).any(|n| same_type(&n)) && !next_siblings(node).any(|n| same_type(&n)),
+        PseudoClass::NthChild(nth, selectors) => {
+            of(selectors, &node) && nth.matches(position(&mut previous_siblings(node).filter(|n| of(selectors, n))))
+        }
+        PseudoClass::NthLastChild(nth, selectors) => {
+            of(selectors, &node) && nth.matches(position(&mut next_siblings(node).filter(|n| of(selectors, n))))
+        }
+        PseudoClass::NthOfType(nth) => nth.matches(position(&mut previous_siblings(node).filter(|n| same_type(n)))),
+        PseudoClass::NthLastOfType(nth) => nth.matches(position(&mut next_siblings(node).filter(|n| same_type(n)))),
+        PseudoClass::Not(selectors) => !selectors.iter().any(|s| matches_in(cx, node, s)),
+        PseudoClass::Is(selectors) | PseudoClass::Where(selectors) => selectors.iter().any(|s| matches_in(cx, node, s)),
+        PseudoClass::Has(relatives) => relatives.iter().any(|r| matches_relative(cx, node, r)),
+    }
+}
+
+// https://html.spec.whatwg.org/multipage/semantics-other.html#case-sensitivity-of-selectors
+fn is_case_insensitive_attribute(name: &str) -> bool {
+    matches!(name,
+        "accept" | "accept-charset" | "align" | "alink" | "axis" | "bgcolor" | "charset" |
+        "checked" | "clear" | "codetype" | "color" | "compact" | "declare" | "defer" | "dir" |
+        "direction" | "disabled" | "enctype" | "face" | "frame" | "hreflang" | "http-equiv" |
+        "lang" | "language" | "link" | "media" | "method" | "multiple" | "nohref" | "noresize" |
+        "noshade" | "nowrap" | "readonly" | "rel" | "rev" | "rules" | "scope" | "scrolling" |
+        "selected" | "shape" | "target" | "text" | "type" | "valign" | "valuetype" | "vlink"
+    )
+}
+
+// ---------------------
+// generated content
+// ---------------------
+
+// https://www.w3.org/TR/css-content-3/#quote-values
+// the quotes when `quotes` is auto
+const DEFAULT_QUOTES: [(&str, &str); 2] = [("\u{201c}", "\u{201d}"), ("\u{2018}", "\u{2019}")];
+
+// what generated content carries from one element to the next in document order
+#[derive(Debug, Default)]
+struct ContentState {
+    // https://www.w3.org/TR/css-lists-3/#nested-counters
+    // the counters in scope, innermost last
+    counters: Vec<Counter>,
+    // how many quotes are open
+    quote_depth: usize,
+    // `quotes` is inherited, so this is the nearest one specified
+    quotes: Option<Value>,
+    // of the element being styled
+    depth: usize,
+    // inside a display: none subtree, where nothing counts, quotes or generates content
+    hidden: bool,
+}
+
+#[derive(Debug)]
+struct Counter {
+    name: String,
+    value: i32,
+    // of the element that made it, whose following siblings can replace it
+    depth: usize,
+}
+
+impl ContentState {
+    // https://www.w3.org/TR/css-lists-3/#counters-without-boxes
+    fn update(&mut self, styles: &HashMap<String, Value>) {
+        if self.hidden {
+            return;
+        }
+        if let Some(Value::Counters(resets)) = styles.get("counter-reset") {
+            for (name, value) in resets {
+                self.reset(name, *value);
+            }
+        }
+        if let Some(Value::Counters(increments)) = styles.get("counter-increment") {
+            for (name, by) in increments {
+                let counter = self.counter(name);
+                counter.value = counter.value.wrapping_add(*by);
+            }
+        }
+        if let Some(quotes) = styles.get("quotes") {
+            self.quotes = Some(quotes.clone());
+        }
+    }
+
+    fn reset(&mut self, name: &str, value: i32) {
+        let depth = self.depth;
+        match self.counters.iter_mut().rev().find(|counter| counter.name == name) {
+            Some(counter) if counter.depth == depth => counter.value = value,
+            _ => self.counters.push(Counter { name: name.to_string(), value, depth }),
+        }
+    }
+
+    // the innermost counter of that name, made on the spot when there is none
+    fn counter(&mut self, name: &str) -> &mut Counter {
+        if !self.counters.iter().any(|counter| counter.name == name) {
+            self.reset(name, 0);
+        }
+        self.counters.iter_mut().rev().find(|counter| counter.name == name).unwrap()
+    }
+
+    fn generate(&mut self, items: &[ContentItem], elem: &ElementData) -> String {
+        let mut text = String::new();
+        for item in items {
+            match item {
+                ContentItem::String(string) => text.push_str(string),
+                ContentItem::Attr(name) => {
+                    let name = if elem.is_html() { name.to_ascii_lowercase() } else { name.clone() };
+                    text.push_str(elem.attributes.get(&name).map_or("", |value| value.as_str()));
+                }
+                ContentItem::Counter(name, style) => {
+                    let value = self.counter(name).value;
+                    text.push_str(&format_counter(value, style));
+                }
+                ContentItem::Counters(name, separator, style) => {
+                    self.counter(name);
+                    let values: Vec<String> = self.counters.iter()
+                        .filter(|counter| &counter.name == name)
+                        .map(|counter| format_counter(counter.value, style))
+                        .collect();
+                    text.push_str(&values.join(separator));
+                }
+                ContentItem::OpenQuote => {
+                    text.push_str(self.quote(self.quote_depth).0);
+                    self.quote_depth += 1;
+                }
+                // a close-quote with nothing open prints nothing
+                ContentItem::CloseQuote if self.quote_depth > 0 => {
+                    self.quote_depth -= 1;
+                    text.push_str(self.quote(self.quote_depth).1);
+                }
+                ContentItem::CloseQuote => {}
+                ContentItem::NoOpenQuote => self.quote_depth += 1,
+                ContentItem::NoCloseQuote => self.quote_depth = self.quote_depth.saturating_sub(1),
+            }
+        }
+        text
+    }
+
+    // the deepest pair is used for anything nested deeper
+    fn quote(&self, depth: usize) -> (&str, &str) {
+        match &self.quotes {
+            Some(Value::Quotes(pairs)) => {
+                let (open, close) = &pairs[depth.min(pairs.len() - 1)];
+                (open, close)
+            }
+            Some(Value::Keyword(keyword)) if keyword == "none" => ("", ""),
+            _ => DEFAULT_QUOTES[depth.min(DEFAULT_QUOTES.len() - 1)],
+        }
+    }
+}
+
+// https://www.w3.org/TR/css-counter-styles-3/#predefined-counters
+// styles that can't show the value fall back to decimal
+fn format_counter(value: i32, style: &str) -> String {
+    match style {
+        "none" => String::new(),
+        "disc" => "\u{2022}".to_string(),
+        "circle" => "\u{25e6}".to_string(),
+        "square" => "\u{25aa}".to_string(),
+        "lower-alpha" | "lower-latin" if value > 0 => alphabetic(value),
+        "upper-alpha" | "upper-latin" if value > 0 => alphabetic(value).to_ascii_uppercase(),
+        "lower-roman" if (1..4000).contains(&value) => roman(value),
+        "upper-roman" if (1..4000).contains(&value) => roman(value).to_ascii_uppercase(),
+        _ => value.to_string(),
+    }
+}
+
+// a, b, ..., z, aa, ab, ...
+fn alphabetic(mut value: i32) -> String {
+    let mut letters = Vec::new();
+    while value > 0 {
+        value -= 1;
+        letters.push((b'a' + (value % 26) as u8) as char);
+        value /= 26;
+    }
+    letters.iter().rev().collect()
+}
+
+fn roman(mut value: i32) -> String {
+    const NUMERALS: [(i32, &str); 13] = [
+        (1000, "m"), (900, "cm"), (500, "d"), (400, "cd"), (100, "c"), (90, "xc"),
+        (50, "l"), (40, "xl"), (10, "x"), (9, "ix"), (5, "v"), (4, "iv"), (1, "i"),
+    ];
+    let mut text = String::new();
+    for (number, numeral) in NUMERALS {
+        while value >= number {
+            text.push_str(numeral);
+            value -= number;
+        }
+    }
+    text
+}
+
+fn is_display_none(styles: &HashMap<String, Value>) -> bool {
+    matches!(styles.get("display"), Some(Value::Keyword(display)) if display == "none")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::css::{author_stylesheet, parse_css};
+    use crate::css::parser::CssParser;
+    use crate::loader::Loader;
+    use crate::html::{dom, parse_html};
+
+    // the rules of css_input and the style="" attributes of the document
+    fn stylesheet(document: &dom::Document, css_input: &str) -> Stylesheet {
+        let loader = Loader::for_document(Path::new("index.html"), document);
+        let inline_styles = author_stylesheet(document, &loader).inline_styles;
+        Stylesheet { inline_styles, ..parse_css(css_input.to_string()) }
+    }
+
+    // the specified display of the element `selector` finds
+    fn display(html_input: &str, css_input: &str, selector: &str) -> Option<Value> {
+        let document = parse_html(html_input.to_string());
+        let stylesheet = stylesheet(&document, css_input);
+        let target = document.query_selector(selector).expect("no element for the selector");
+        let styled = StyledNode::build_styled_tree(document.get(document.root()), &stylesheet);
+        find(&styled, target).and_then(|node| node.value("display"))
+    }
+
+    fn find<'a, 'b>(styled: &'b StyledNode<'a>, id: NodeId) -> Option<&'b StyledNode<'a>> {
+        if styled.node.id == id && styled.generated.is_none() {
+            return Some(styled);
+        }
+        styled.children.iter().find_map(|child| find(child, id))
+    }
+
+    fn keyword(s: &str) -> Option<Value> {
+        Some(Value::Keyword(s.to_string()))
+    }
+
+    // the ids of the elements the selector matches, in document order
+    fn matching(html_input: &str, selector: &str) -> Vec<String> {
+        let document = parse_html(html_input.to_string());
+        let selectors = CssParser::parse_selector_list(selector.to_string()).unwrap();
+        document.descendants(document.root())
+            .map(|id| document.get(id))
+            .filter(|&node| selectors.iter().any(|selector| matches(node, selector)))
+            .filter_map(|node| node.as_element().and_then(|elem| elem.attributes.get("id")).cloned())
+            .collect()
+    }
+
+    #[test]
+    fn more_specific_rules_win() {
+        let html = r#"<p id="a" class="b">x</p>"#;
+        assert_eq!(display(html, "#a { display: block } .b { display: none }", "p"), keyword("block"));
+        assert_eq!(display(html, ".b { display: none } p { display: block }", "p"), keyword("none"));
+    }
+
+    #[test]
+    fn later_rules_win_at_equal_specificity() {
+        let html = "<p>x</p>";
+        assert_eq!(display(html, "p { display: none } p { display: block }", "p"), keyword("block"));
+    }
+
+    #[test]
+    fn style_attribute_beats_rules() {
+        let html = r#"<p id="a" style="display: none">x</p>"#;
+        assert_eq!(display(html, "#a { display: block }", "p"), keyword("none"));
+    }
+
+    #[test]
+    fn important_rules_beat_the_style_attribute() {
+        let html = r#"<p style="display: none">x</p>"#;
+        assert_eq!(display(html, "p { display: block !important }", "p"), keyword("block"));
+        let html = r#"<p style="display: none !important">x</p>"#;
+        assert_eq!(display(html, "p { display: block !important }", "p"), keyword("none"));
+    }
+
+    #[test]
+    fn style_attribute_applies_to_its_element_only() {
+        let html = r#"<div style="display: none"><p>x</p></div>"#;
+        assert_eq!(display(html, "", "div"), keyword("none"));
+        assert_eq!(display(html, "", "p"), None);
+    }
+
+    #[test]
+    fn descendant_and_child_combinators() {
+        let html = r#"<div id=d><section id=s><p id=p1><span id=x></span></p></section><p id=p2></p></div>"#;
+        assert_eq!(matching(html, "div p"), ["p1", "p2"]);
+        assert_eq!(matching(html, "div > p"), ["p2"]);
+        assert_eq!(matching(html, "div span"), ["x"]);
+        // the nearest p fails "div >", so a match needs the walk to go on
+        assert_eq!(matching(html, "div > section p span"), ["x"]);
+        assert_eq!(matching(html, "div > p span"), Vec::<String>::new());
+        assert_eq!(matching(html, "body > * > * > *"), ["p1"]);
+    }
+
+    #[test]
+    fn sibling_combinators() {
+        let html = r#"<h1 id=h></h1>text<!-- c --><p id=a></p><p id=b></p><div id=c></div><p id=d></p>"#;
+        // text and comments in between don't count
+        assert_eq!(matching(html, "h1 + p"), ["a"]);
+        assert_eq!(matching(html, "h1 ~ p"), ["a", "b", "d"]);
+        assert_eq!(matching(html, "p + p"), ["b"]);
+        assert_eq!(matching(html, "p ~ div + p"), ["d"]);
+        assert_eq!(matching(html, "div ~ h1"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn combinators_apply_while_styling() {
+        let html = "<ul><li>a</li></ul><ol><li>b</li></ol>";
+        assert_eq!(display(html, "ul > li { display: block }", "ul li"), keyword("block"));
+        assert_eq!(display(html, "ul > li { display: block }", "ol li"), None);
+    }
+
+    #[test]
+    fn attribute_operators() {
+        let html = concat!(
+            r#"<a id=a href="https://x.org/a.pdf" class="big red" lang=en-GB title="">a</a>"#,
+            r#"<a id=b lang=en>b</a>"#,
+        );
+        assert_eq!(matching(html, "[href]"), ["a"]);
+        assert_eq!(matching(html, "[title]"), ["a"]);
+        assert_eq!(matching(html, "[lang=en]"), ["b"]);
+        assert_eq!(matching(html, "[class~=red]"), ["a"]);
+        assert_eq!(matching(html, "[class~='big red']"), Vec::<String>::new());
+        // only ASCII whitespace separates the words, so a no-break space is part of one
+        assert_eq!(matching(r#"<a id=c class="big&nbsp;red">c</a>"#, r"[class~='big\a0 red']"), ["c"]);
+        assert_eq!(matching(html, "[lang|=en]"), ["a", "b"]);
+        assert_eq!(matching(html, "[href^='https:']"), ["a"]);
+        assert_eq!(matching(html, "[href$='.pdf']"), ["a"]);
+        assert_eq!(matching(html, "[href*='x.org']"), ["a"]);
+        // an empty value matches nothing, except for = and |=
+        assert_eq!(matching(html, "[title^=''], [title$=''], [title*=''], [class~='']"), Vec::<String>::new());
+        assert_eq!(matching(html, "[title='']"), ["a"]);
+    }
+
+    #[test]
+    fn attribute_case_sensitivity() {
+        let html = r#"<input id=a type=TEXT data-x=Yes><svg id=b viewBox="0 0 1 1"></svg>"#;
+        // html says which attribute values ignore case
+        assert_eq!(matching(html, "[type=text]"), ["a"]);
+        assert_eq!(matching(html, "[type=text s]"), Vec::<String>::new());
+        assert_eq!(matching(html, "[data-x=yes]"), Vec::<String>::new());
+        assert_eq!(matching(html, "[data-x=yes i]"), ["a"]);
+        assert_eq!(matching(html, "[DATA-X]"), ["a"]);
+        // names on svg elements keep their case
+        assert_eq!(matching(html, "[viewBox]"), ["b"]);
+        assert_eq!(matching(html, "[viewbox]"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn root_and_empty() {
+        let html = "<div id=a></div><div id=b><!-- c --></div><div id=c> </div><div id=d><i></i></div>";
+        let document = parse_html(html.to_string());
+        let root = CssParser::parse_selector_list(":root".to_string()).unwrap();
+        let html_element = document.get(document.document_element().unwrap());
+        assert!(matches(html_element, &root[0]));
+        assert!(!matches(document.get(document.query_selector("body").unwrap()), &root[0]));
+        // whitespace is text
+        assert_eq!(matching(html, "div:empty"), ["a", "b"]);
+    }
+
+    #[test]
+    fn first_last_and_only() {
+        let html = "<ul>x<li id=a></li><p id=p></p><li id=b></li>y</ul><ol><li id=c></li></ol>";
+        assert_eq!(matching(html, "li:first-child"), ["a", "c"]);
+        assert_eq!(matching(html, "li:last-child"), ["b", "c"]);
+        assert_eq!(matching(html, "li:only-child"), ["c"]);
+        assert_eq!(matching(html, "ul > :first-of-type"), ["a", "p"]);
+        assert_eq!(matching(html, "ul > :last-of-type"), ["p", "b"]);
+        assert_eq!(matching(html, ":only-of-type"), ["p", "c"]);
+    }
+
+    #[test]
+    fn nth_child_and_nth_of_type() {
+        let html = "<ul><li id=1></li><p id=p1></p><li id=2></li><li id=3></li><p id=p2></p><li id=4></li></ul>";
+        assert_eq!(matching(html, "ul > :nth-child(odd)"), ["1", "2", "p2"]);
+        assert_eq!(matching(html, "ul > :nth-child(-n+2)"), ["1", "p1"]);
+        assert_eq!(matching(html, "ul > :nth-last-child(2)"), ["p2"]);
+        assert_eq!(matching(html, "li:nth-of-type(2n)"), ["2", "4"]);
+        assert_eq!(matching(html, "li:nth-last-of-type(1)"), ["4"]);
+        assert_eq!(matching(html, "p:nth-of-type(3)"), Vec::<String>::new());
+        assert_eq!(matching(html, ":nth-child(-99999999999), li:nth-last-child(99999999999n+1)"), ["4"]);
+    }
+
+    #[test]
+    fn nth_child_of_a_selector_list() {
+        let html = r#"<ul><li id=1 class=x></li><li id=2></li><li id=3 class=x></li><li id=4 class=x></li></ul>"#;
+        // positions among the siblings that match S, and the element has to match S too
+        assert_eq!(matching(html, ":nth-child(even of .x)"), ["3"]);
+        assert_eq!(matching(html, ":nth-child(1 of li:not(.x), .x)"), ["1"]);
+        assert_eq!(matching(html, ":nth-last-child(1 of .x)"), ["4"]);
+        assert_eq!(matching(html, ":nth-child(2 of :not(.x))"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn not_is_and_where() {
+        let html = r#"<p id=a class=x></p><p id=b class=y></p><div id=c class=x></div>"#;
+        assert_eq!(matching(html, "p:not(.x)"), ["b"]);
+        assert_eq!(matching(html, "body > :not(p, .y)"), ["c"]);
+        assert_eq!(matching(html, ":is(p, div).x"), ["a", "c"]);
+        assert_eq!(matching(html, ":where(body) :where(.y, div)"), ["b", "c"]);
+        assert_eq!(matching(html, ":not(:is(p))[id]"), ["c"]);
+        assert_eq!(matching(html, ":is(:nope, div)"), ["c"]);
+        // a combinator inside can reach outside the argument
+        assert_eq!(matching(html, ":is(p + p)"), ["b"]);
+    }
+
+    #[test]
+    fn has_looks_down_and_forward() {
+        let html = concat!(
+            r#"<div id=a><p><img></p></div><div id=b><img></div><div id=c></div>"#,
+            r#"<h1 id=h1></h1><p id=p></p><h2 id=h2></h2><span></span><p></p>"#,
+        );
+        assert_eq!(matching(html, "div:has(img)"), ["a", "b"]);
+        assert_eq!(matching(html, "div:has(> img)"), ["b"]);
+        assert_eq!(matching(html, "div:has(> p img, span)"), ["a"]);
+        assert_eq!(matching(html, ":has(+ p)"), ["h1"]);
+        assert_eq!(matching(html, "h1:has(~ span), h2:has(~ h1)"), ["h1"]);
+        assert_eq!(matching(html, "div:not(:has(img))"), ["c"]);
+    }
+
+    #[test]
+    fn has_sees_the_dom_of_each_build() {
+        let mut document = parse_html("<div><p></p></div>".to_string());
+        let stylesheet = parse_css("div:has(p) { display: block }".to_string());
+        let div = document.query_selector("div").unwrap();
+        let display = |document: &dom::Document| {
+            let styled = StyledNode::build_styled_tree(document.get(document.root()), &stylesheet);
+            find(&styled, div).and_then(|node| node.value("display"))
+        };
+        assert_eq!(display(&document), keyword("block"));
+        let p = document.query_selector("p").unwrap();
+        document.remove_child(div, p).unwrap();
+        assert_eq!(display(&document), None);
+    }
+
+    // the text of every ::before and ::after, in document order
+    fn generated(html_input: &str, css_input: &str) -> Vec<String> {
+        fn walk(styled: &StyledNode, text: &mut Vec<String>) {
+            if let Some(generated) = &styled.generated {
+                text.push(generated.text.clone());
+            }
+            for child in &styled.children {
+                walk(child, text);
+            }
+        }
+        let document = parse_html(html_input.to_string());
+        let stylesheet = stylesheet(&document, css_input);
+        let styled = StyledNode::build_styled_tree(document.get(document.root()), &stylesheet);
+        let mut text = Vec::new();
+        walk(&styled, &mut text);
+        text
+    }
+
+    #[test]
+    fn before_and_after_wrap_the_children() {
+        let document = parse_html("<p>x</p>".to_string());
+        let stylesheet = parse_css("p::after { content: 'b' } p:before { content: 'a' }".to_string());
+        let styled = StyledNode::build_styled_tree(document.get(document.root()), &stylesheet);
+        let p = find(&styled, document.query_selector("p").unwrap()).unwrap();
+        let kinds: Vec<_> = p.children.iter().map(|child| child.generated.as_ref().map(|g| g.pseudo_element)).collect();
+        assert_eq!(kinds, [Some(PseudoElement::Before), None, Some(PseudoElement::After)]);
+    }
+
+    #[test]
+    fn content_decides_whether_there_is_a_box() {
+        let html = "<p>x</p>";
+        assert_eq!(generated(html, "p::before { content: none }"), Vec::<String>::new());
+        assert_eq!(generated(html, "p::before { content: normal }"), Vec::<String>::new());
+        assert_eq!(generated(html, "p::before { display: block }"), Vec::<String>::new());
+        assert_eq!(generated(html, "p::before { content: 'a'; display: none }"), Vec::<String>::new());
+        assert_eq!(generated(html, "p { display: none } p::before { content: 'a' }"), Vec::<String>::new());
+        assert_eq!(generated(html, "p::before { content: '' }"), [""]);
+        // the element's own style is not the pseudo-element's
+        assert_eq!(generated(html, "p { content: 'a' }"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn strings_and_attributes() {
+        let html = r#"<a href="/x" title=T>x</a><a>y</a>"#;
+        assert_eq!(generated(html, "a::after { content: ' (' attr(href) ')' }"), [" (/x)", " ()"]);
+        assert_eq!(generated(html, "a::before { content: attr(TITLE) }"), ["T", ""]);
+    }
+
+    #[test]
+    fn counters_number_in_document_order() {
+        let html = "<ol><li>a</li><li>b<ol><li>c</li><li>d</li></ol></li><li>e</li></ol>";
+        let css = "ol { counter-reset: item } li { counter-increment: item }";
+        assert_eq!(
+            generated(html, &format!("{} li::before {{ content: counter(item) '.' }}", css)),
+            ["1.", "2.", "1.", "2.", "3."]
+        );
+        assert_eq!(
+            generated(html, &format!("{} li::before {{ content: counters(item, '.') ' ' }}", css)),
+            ["1 ", "2 ", "2.1 ", "2.2 ", "3 "]
+        );
+        assert_eq!(
+            generated(
+                html,
+                &format!("{} li::before {{ content: counter(item, upper-roman) counter(item, lower-alpha) }}", css)
+            ),
+            ["Ia", "IIb", "Ia", "IIb", "IIIc"]
+        );
+    }
+
+    #[test]
+    fn counters_without_a_reset_and_siblings_in_scope() {
+        let html = "<h2>a</h2><p>x</p><h2>b</h2><h3>c</h3><h3>d</h3><h2>e</h2><h3>f</h3>";
+        let css = "body { counter-reset: h2 } h2 { counter-increment: h2; counter-reset: h3 } \
+                   h3 { counter-increment: h3 } h2::before { content: counter(h2) } \
+                   h3::before { content: counter(h2) '.' counter(h3) }";
+        assert_eq!(generated(html, css), ["1", "2", "2.1", "2.2", "3", "3.1"]);
+        // an increment without any reset makes the counter
+        let css = "p { counter-increment: n 5 } p::after { content: counter(n) }";
+        assert_eq!(generated("<p>a</p><p>b</p>", css), ["5", "10"]);
+    }
+
+    #[test]
+    fn quotes_nest() {
+        let html = "<q>a<q>b<q>c</q></q></q>";
+        let css = "q::before { content: open-quote } q::after { content: close-quote }";
+        assert_eq!(
+            generated(html, css),
+            ["\u{201c}", "\u{2018}", "\u{2018}", "\u{2019}", "\u{2019}", "\u{201d}"]
+        );
+        let custom = format!("{} q {{ quotes: '<' '>' '[' ']' }}", css);
+        assert_eq!(generated(html, &custom), ["<", "[", "[", "]", "]", ">"]);
+        assert_eq!(generated(html, &format!("{} q {{ quotes: none }}", css)), ["", "", "", "", "", ""]);
+        // no-open-quote only counts, and a close-quote with nothing open prints nothing
+        let css = "q::before { content: no-open-quote } q::after { content: close-quote }";
+        assert_eq!(generated("<q>a</q><q>b</q>", css), ["", "\u{201d}", "", "\u{201d}"]);
+        assert_eq!(generated("<p>a</p>", "p::after { content: close-quote }"), [""]);
+    }
+
+    #[test]
+    fn hidden_subtrees_neither_count_nor_quote() {
+        let html = r#"<div style="display:none"><p>x</p></div><p>y</p>"#;
+        let css = "body { counter-reset: n } p { counter-increment: n } p::before { content: counter(n) }";
+        assert_eq!(generated(html, css), ["1"]);
+        // the hidden <q> opens no quote, so the next one is still the outer
+        let html = r#"<div style="display:none"><q>a</q></div><q>b</q>"#;
+        assert_eq!(generated(html, "q::before { content: open-quote }"), ["\u{201c}"]);
+    }
+
+    #[test]
+    fn counter_styles() {
+        assert_eq!(format_counter(28, "lower-alpha"), "ab");
+        assert_eq!(format_counter(1994, "upper-roman"), "MCMXCIV");
+        assert_eq!(format_counter(4000, "lower-roman"), "4000");
+        assert_eq!(format_counter(0, "lower-alpha"), "0");
+        assert_eq!(format_counter(-3, "decimal"), "-3");
+        assert_eq!(format_counter(3, "disc"), "\u{2022}");
+        assert_eq!(format_counter(3, "none"), "");
+        assert_eq!(format_counter(3, "hebrew"), "3");
+    }
+}
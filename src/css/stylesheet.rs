@@ -1,10 +1,16 @@
+use std::collections::HashMap;
+
+use crate::html::dom::NodeId;
+
 // ---------------------
 // stylesheet
 // ---------------------
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Stylesheet {
     pub rules: Vec<Rule>,
+    // the declarations of each element's style="", parsed once with the document's stylesheets
+    pub inline_styles: HashMap<NodeId, Vec<Declaration>>,
 }
 
 #[derive(Debug)]
@@ -17,7 +23,7 @@ pub struct Rule {
 pub struct Declaration {
     pub name: String,
     pub value: Value,
-    // pub important: bool,
+    pub important: bool,
 }
 
 // ---------------------
@@ -40,6 +46,109 @@ pub struct SimpleSelector {
     pub tag: Option<String>,
     pub id: Option<String>,
     pub class: Vec<String>,
+    pub attributes: Vec<AttributeSelector>,
+    pub pseudo_classes: Vec<PseudoClass>,
+    // only on the last compound of a selector
+    pub pseudo_element: Option<PseudoElement>,
+}
+
+// https://www.w3.org/TR/selectors-4/#attribute-selectors
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AttributeSelector {
+    pub name: String,
+    pub operator: AttributeOperator,
+    // empty for [attr]
+    pub value: String,
+    pub case: AttributeCase,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeOperator {
+    Exists,     // [attr]
+    Equals,     // [attr=value]
+    Includes,   // [attr~=value]
+    DashMatch,  // [attr|=value]
+    Prefix,     // [attr^=value]
+    Suffix,     // [attr$=value]
+    Substring,  // [attr*=value]
+}
+
+// https://www.w3.org/TR/selectors-4/#attribute-case
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AttributeCase {
+    // whatever the document language says for the attribute
+    #[default]
+    Default,
+    Insensitive, // [attr=value i]
+    Sensitive,   // [attr=value s]
+}
+
+// https://www.w3.org/TR/selectors-4/#structural-pseudos
+// positions count element siblings only
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PseudoClass {
+    Root,
+    Empty,
+    FirstChild,
+    LastChild,
+    OnlyChild,
+    FirstOfType,
+    LastOfType,
+    OnlyOfType,
+    // the selectors of "of S", empty when there is none
+    NthChild(Nth, Vec<Selector>),
+    NthLastChild(Nth, Vec<Selector>),
+    NthOfType(Nth),
+    NthLastOfType(Nth),
+    // https://www.w3.org/TR/selectors-4/#logical-combination
+    Not(Vec<Selector>),
+    Is(Vec<Selector>),
+    Where(Vec<Selector>),
+    // https://www.w3.org/TR/selectors-4/#relational
+    Has(Vec<RelativeSelector>),
+}
+
+// https://www.w3.org/TR/css-pseudo-4/#generated-content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PseudoElement {
+    Before,
+    After,
+}
+
+// a selector of :has(), which starts from the element being matched,
+// e.g. the "> img" of a:has(> img)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelativeSelector {
+    pub combinator: Combinator,
+    pub selector: Selector,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Combinator {
+    Descendant,      // a b
+    Child,           // a > b
+    AdjacentSibling, // a + b
+    GeneralSibling,  // a ~ b
+}
+
+// https://www.w3.org/TR/css-syntax-3/#anb-microsyntax
+// matches the elements at positions a*n + b for some n >= 0, counting from 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nth {
+    pub a: i32,
+    pub b: i32,
+}
+
+impl Nth {
+    pub fn matches(&self, index: i32) -> bool {
+        // in i64, as A and B can be anything an i32 holds
+        let (a, diff) = (i64::from(self.a), i64::from(index) - i64::from(self.b));
+        if a == 0 {
+            diff == 0
+        } else {
+            diff % a == 0 && diff / a >= 0
+        }
+    }
 }
 
 impl Selector {
@@ -58,14 +167,47 @@ impl Selector {
             },
         }
     }
+
+    // the pseudo-element the selector is for, if it isn't for the element itself
+    pub fn pseudo_element(&self) -> Option<PseudoElement> {
+        match self {
+            Selector::Simple(simple) => simple.pseudo_element,
+            Selector::Descendant(_, b) |
+            Selector::Child(_, b) |
+            Selector::AdjacentSibling(_, b) |
+            Selector::GeneralSibling(_, b) => b.pseudo_element(),
+        }
+    }
 }
 
 impl SimpleSelector {
     fn specificity(&self) -> Specificity {
         let a = self.id.iter().count() as u32;
-        let b = self.class.len() as u32;
-        let c = self.tag.iter().count() as u32;
-        (a, b, c)
+        let b = (self.class.len() + self.attributes.len()) as u32;
+        // a pseudo-element counts like a tag
+        let c = (self.tag.iter().count() + self.pseudo_element.iter().count()) as u32;
+        self.pseudo_classes.iter()
+            .map(|pseudo| pseudo.specificity())
+            .fold((a, b, c), |(a1, b1, c1), (a2, b2, c2)| (a1 + a2, b1 + b2, c1 + c2))
+    }
+}
+
+impl PseudoClass {
+    // https://www.w3.org/TR/selectors-4/#specificity-rules
+    // like a class, and :nth-child(An+B of S) adds the most specific selector of S;
+    // :is(), :not() and :has() count as their most specific argument and :where() as nothing
+    fn specificity(&self) -> Specificity {
+        let most_specific = |selectors: &[Selector]| selectors.iter().map(|s| s.specificity()).max().unwrap_or_default();
+        match self {
+            PseudoClass::NthChild(_, selectors) | PseudoClass::NthLastChild(_, selectors) => {
+                let (a, b, c) = most_specific(selectors);
+                (a, b + 1, c)
+            }
+            PseudoClass::Not(selectors) | PseudoClass::Is(selectors) => most_specific(selectors),
+            PseudoClass::Where(_) => (0, 0, 0),
+            PseudoClass::Has(relatives) => relatives.iter().map(|r| r.selector.specificity()).max().unwrap_or_default(),
+            _ => (0, 1, 0),
+        }
     }
 }
 
@@ -79,6 +221,16 @@ pub enum Value {
     Length(f32, Unit),
     // Percentage(f32),
     ColorValue(Color),
+    // https://www.w3.org/TR/css-content-3/#content-property
+    Content(Vec<ContentItem>),
+    // counter-reset and counter-increment: each name with its value
+    Counters(Vec<(String, i32)>),
+    // https://www.w3.org/TR/css-content-3/#quotes-property
+    // the open and close quote of each nesting level
+    Quotes(Vec<(String, String)>),
+    // https://www.w3.org/TR/css-fonts-4/#unicode-range-desc
+    // the first and last code point of each range
+    UnicodeRange(Vec<(u32, u32)>),
     // Url(String),
     // Function(String, Vec<Value>),
     // String(String),
@@ -97,6 +249,21 @@ pub enum Unit {
     // Vmax,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContentItem {
+    String(String),
+    // attr(name)
+    Attr(String),
+    // counter(name, style)
+    Counter(String, String),
+    // counters(name, separator, style)
+    Counters(String, String, String),
+    OpenQuote,
+    CloseQuote,
+    NoOpenQuote,
+    NoCloseQuote,
+}
+
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct Color {
     pub r: u8,
@@ -255,4 +422,52 @@ impl Value {
 //             _ => None,
 //         }
 //     }
-// }
\ No newline at end of file
+// }
+#[cfg(test)]
+mod tests {
+    use crate::css::parser::CssParser;
+
+    fn specificity(css: &str) -> super::Specificity {
+        CssParser::parse_selector_list(css.to_string()).unwrap()[0].specificity()
+    }
+
+    #[test]
+    fn attribute_selectors_count_like_classes() {
+        assert_eq!(specificity("[href]"), (0, 1, 0));
+        assert_eq!(specificity("a[href^=http][target=_blank i]"), (0, 2, 1));
+        assert_eq!(specificity("#x.y[z]"), (1, 2, 0));
+    }
+
+    #[test]
+    fn structural_pseudo_classes_count_like_classes() {
+        assert_eq!(specificity("li:first-child"), (0, 1, 1));
+        assert_eq!(specificity(":nth-child(2n+1)"), (0, 1, 0));
+        // and "of S" adds its most specific selector
+        assert_eq!(specificity(":nth-child(odd of #a, .b)"), (1, 1, 0));
+        assert_eq!(specificity("li:nth-last-child(1 of p.b)"), (0, 2, 2));
+    }
+
+    #[test]
+    fn nth_positions() {
+        let positions = |a, b| (1..=10).filter(|&index| super::Nth { a, b }.matches(index)).collect::<Vec<_>>();
+        assert_eq!(positions(2, 1), [1, 3, 5, 7, 9]);
+        assert_eq!(positions(0, 3), [3]);
+        assert_eq!(positions(-1, 3), [1, 2, 3]);
+        assert_eq!(positions(3, -2), [1, 4, 7, 10]);
+        assert_eq!(positions(-2, 0), Vec::<i32>::new());
+        assert_eq!(positions(i32::MAX, 1), [1]);
+        assert_eq!(positions(-i32::MAX, i32::MAX), Vec::<i32>::new());
+        assert_eq!(positions(0, i32::MIN), Vec::<i32>::new());
+        assert_eq!(positions(i32::MIN, 1), [1]);
+    }
+
+    #[test]
+    fn logical_pseudo_classes() {
+        // :is() and :not() count as their most specific argument, :where() as nothing
+        assert_eq!(specificity(":is(p, #a .b)"), (1, 1, 0));
+        assert_eq!(specificity("p:not(.a, span)"), (0, 1, 1));
+        assert_eq!(specificity(":where(#a, .b) p"), (0, 0, 1));
+        assert_eq!(specificity(":is(:where(#a), p)"), (0, 0, 1));
+        assert_eq!(specificity("a:has(> img, #x)"), (1, 0, 1));
+    }
+}
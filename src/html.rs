@@ -1,6 +1,37 @@
+pub mod diagnostics;
+pub mod diff;
 pub mod dom;
+pub mod encoding;
+pub mod entities;
+pub mod foreign;
 pub mod parser;
+pub mod query;
+pub mod serializer;
+pub mod tokenizer;
+pub mod tree_builder;
 
-pub fn parse_html(html_input: String) -> dom::Node {
+pub fn parse_html(html_input: String) -> dom::Document {
     parser::build_dom_tree(html_input)
 }
+
+// like parse_html, but also hands back every parse error with its location
+pub fn parse_html_with_errors(html_input: String) -> (dom::Document, Vec<diagnostics::ParseError>) {
+    parser::build_dom_tree_with_errors(html_input)
+}
+
+// raw bytes are decoded with the encoding the page declares, or the fallback
+pub fn parse_html_bytes(bytes: &[u8], fallback: &'static encoding_rs::Encoding) -> dom::Document {
+    parser::build_dom_tree_from_bytes(bytes, fallback).0
+}
+
+pub fn parse_html_bytes_with_errors(
+    bytes: &[u8],
+    fallback: &'static encoding_rs::Encoding,
+) -> (dom::Document, Vec<diagnostics::ParseError>) {
+    parser::build_dom_tree_from_bytes(bytes, fallback)
+}
+
+// parses markup as if it were the content of the context element
+pub fn parse_fragment(html_input: String, context: dom::NodeRef) -> Result<dom::Document, dom::DomError> {
+    parser::build_fragment(html_input, context)
+}
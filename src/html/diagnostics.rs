@@ -0,0 +1,168 @@
+use std::fmt;
+
+// ---------------------
+// source locations
+// ---------------------
+
+// offset is in bytes of the original input, line and column count from 1;
+// the column counts characters and CRLF is a single line break
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Location {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    // self is in a piece of text that starts at base, e.g. the contents of a <style>
+    pub fn within(self, base: Location) -> Location {
+        Location {
+            offset: base.offset + self.offset,
+            line: base.line + self.line - 1,
+            column: if self.line == 1 { base.column + self.column - 1 } else { self.column },
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// the end is exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: Location,
+    pub end: Location,
+}
+
+impl Span {
+    pub fn to(self, other: Span) -> Span {
+        Span { start: self.start, end: other.end }
+    }
+}
+
+// ---------------------
+// parse errors
+// ---------------------
+
+// codes follow the spec where it names them, e.g. "eof-in-tag"
+// https://html.spec.whatwg.org/multipage/parsing.html#parse-errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub code: &'static str,
+    pub location: Location,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.code)
+    }
+}
+
+// ---------------------
+// source map
+// ---------------------
+
+// turns char indices of the tokenizer input into locations
+#[derive(Debug, Clone)]
+pub struct SourceMap {
+    byte_offsets: Vec<usize>,
+    line_starts: Vec<usize>,
+}
+
+impl SourceMap {
+    pub fn new(input: &[char]) -> Self {
+        let mut map = SourceMap { byte_offsets: vec![0], line_starts: vec![0] };
+        map.extend(input, 0);
+        map
+    }
+
+    // input is everything seen so far, of which the map covers the first `from` chars
+    pub fn extend(&mut self, input: &[char], from: usize) {
+        for index in from..input.len() {
+            let c = input[index];
+            self.byte_offsets.push(self.byte_offsets[index] + c.len_utf8());
+            // a CR at the end of an earlier call turned out to be half of a CRLF
+            if c == '\n' && index > 0 && input[index - 1] == '\r' && self.line_starts.last() == Some(&index) {
+                self.line_starts.pop();
+            }
+            // a CR only ends the line when no LF follows
+            let line_break = match c {
+                '\n' => true,
+                '\r' => input.get(index + 1) != Some(&'\n'),
+                _ => false,
+            };
+            if line_break {
+                self.line_starts.push(index + 1);
+            }
+        }
+    }
+
+    pub fn location(&self, index: usize) -> Location {
+        let index = index.min(self.byte_offsets.len() - 1);
+        let line = self.line_starts.partition_point(|&start| start <= index);
+        Location {
+            offset: self.byte_offsets[index],
+            line,
+            column: index - self.line_starts[line - 1] + 1,
+        }
+    }
+
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        Span { start: self.location(start), end: self.location(end) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    fn at(offset: usize, line: usize, column: usize) -> Location {
+        Location { offset, line, column }
+    }
+
+    #[test]
+    fn columns_count_characters_and_offsets_bytes() {
+        let map = SourceMap::new(&chars("aé€b\nc"));
+        assert_eq!(map.location(0), at(0, 1, 1));
+        assert_eq!(map.location(2), at(3, 1, 3));
+        assert_eq!(map.location(3), at(6, 1, 4));
+        assert_eq!(map.location(5), at(8, 2, 1));
+        // the end of the input, and anything past it
+        assert_eq!(map.location(6), at(9, 2, 2));
+        assert_eq!(map.location(60), at(9, 2, 2));
+    }
+
+    #[test]
+    fn cr_lf_and_crlf_each_break_a_line_once() {
+        let map = SourceMap::new(&chars("a\r\nb\rc\nd"));
+        assert_eq!(map.location(2), at(2, 1, 3));
+        assert_eq!(map.location(3), at(3, 2, 1));
+        assert_eq!(map.location(5), at(5, 3, 1));
+        assert_eq!(map.location(7), at(7, 4, 1));
+    }
+
+    #[test]
+    fn extending_across_a_split_crlf() {
+        let mut input = chars("a\r");
+        let mut map = SourceMap::new(&input);
+        assert_eq!(map.location(2), at(2, 2, 1));
+        input.extend(chars("\nb"));
+        map.extend(&input, 2);
+        assert_eq!(map.location(3), at(3, 2, 1));
+        assert_eq!(map.span(0, 4), Span { start: at(0, 1, 1), end: at(4, 2, 2) });
+    }
+
+    #[test]
+    fn locations_within_a_base() {
+        let base = at(10, 3, 5);
+        assert_eq!(at(2, 1, 3).within(base), at(12, 3, 7));
+        assert_eq!(at(8, 2, 3).within(base), at(18, 4, 3));
+    }
+}
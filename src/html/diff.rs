@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use super::dom::{self, DomError, NodeId, NodeRef, NodeType};
+
+// one step of an edit script. ids name nodes of the old tree, except the node
+// of an insert or a replace which is copied over from the new one; an index
+// is the position among the children of the parent once the edit is done
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    Insert { parent: NodeId, index: usize, node: NodeId },
+    Remove { parent: NodeId, node: NodeId },
+    Move { parent: NodeId, node: NodeId, index: usize },
+    Replace { node: NodeId, with: NodeId },
+    SetAttribute { node: NodeId, name: String, value: String },
+    RemoveAttribute { node: NodeId, name: String },
+    SetText { node: NodeId, text: String },
+}
+
+// ---------------------
+// diff
+// ---------------------
+
+// the hash of every subtree of both trees, worked out once bottom up
+struct Hashes {
+    old: HashMap<NodeId, u64>,
+    new: HashMap<NodeId, u64>,
+}
+
+// the edits that turn the old subtree into the new one. nodes that are kept
+// keep their ids, so whatever hangs off them (styles, boxes) can be reused
+pub fn diff(old: NodeRef, new: NodeRef) -> Vec<Edit> {
+    let mut edits = Vec::new();
+    if same_kind(old, new) {
+        let mut hashes = Hashes { old: HashMap::new(), new: HashMap::new() };
+        hash_subtrees(old, &mut hashes.old);
+        hash_subtrees(new, &mut hashes.new);
+        diff_node(old, new, &hashes, &mut edits);
+    } else {
+        edits.push(Edit::Replace { node: old.id, with: new.id });
+    }
+    edits
+}
+
+fn diff_node(old: NodeRef, new: NodeRef, hashes: &Hashes, edits: &mut Vec<Edit>) {
+    match (old.node_type(), new.node_type()) {
+        (NodeType::Element(a), NodeType::Element(b)) => {
+            let mut removed: Vec<&String> = a.attributes.keys()
+                .filter(|name| !b.attributes.contains_key(*name))
+                .collect();
+            removed.sort();
+            for name in removed {
+                edits.push(Edit::RemoveAttribute { node: old.id, name: name.clone() });
+            }
+
+            let mut changed: Vec<(&String, &String)> = b.attributes.iter()
+                .filter(|&(name, value)| a.attributes.get(name) != Some(value))
+                .collect();
+            changed.sort();
+            for (name, value) in changed {
+                edits.push(Edit::SetAttribute { node: old.id, name: name.clone(), value: value.clone() });
+            }
+        }
+        (NodeType::Text(a), NodeType::Text(b)) | (NodeType::Comment(a), NodeType::Comment(b)) if a != b => {
+            edits.push(Edit::SetText { node: old.id, text: b.clone() });
+        }
+        _ => {}
+    }
+    diff_children(old, new, hashes, edits);
+    if let (Some(old), Some(new)) = (old.template_contents(), new.template_contents()) {
+        diff_children(old, new, hashes, edits);
+    }
+}
+
+fn diff_children(old: NodeRef, new: NodeRef, hashes: &Hashes, edits: &mut Vec<Edit>) {
+    let old_children: Vec<NodeRef> = old.children().collect();
+    let new_children: Vec<NodeRef> = new.children().collect();
+
+    // for every new child, the old child it stands for
+    let mut matches: Vec<Option<usize>> = vec![None; new_children.len()];
+    let mut taken = vec![false; old_children.len()];
+
+    // elements with an id are the same element wherever they end up
+    for (i, &node) in new_children.iter().enumerate() {
+        let Some(id) = element_id(node) else { continue };
+        if let Some(j) = (0..old_children.len())
+            .find(|&j| !taken[j] && element_id(old_children[j]) == Some(id) && same_kind(old_children[j], node))
+        {
+            matches[i] = Some(j);
+            taken[j] = true;
+        }
+    }
+
+    // then the subtrees that did not change, as long as they stay in order
+    for (i, j) in unchanged_subtrees(&old_children, &new_children, &matches, &taken, hashes) {
+        matches[i] = Some(j);
+        taken[j] = true;
+    }
+
+    // whatever is left pairs up in order with a node of the same kind
+    for (i, &node) in new_children.iter().enumerate() {
+        if matches[i].is_some() { continue; }
+        if let Some(j) = (0..old_children.len()).find(|&j| !taken[j] && same_kind(old_children[j], node)) {
+            matches[i] = Some(j);
+            taken[j] = true;
+        }
+    }
+
+    for (j, &node) in old_children.iter().enumerate() {
+        if !taken[j] {
+            edits.push(Edit::Remove { parent: old.id, node: node.id });
+        }
+    }
+    reorder(old, &old_children, &new_children, &matches, edits);
+
+    for (i, &node) in new_children.iter().enumerate() {
+        if let Some(j) = matches[i] {
+            diff_node(old_children[j], node, hashes, edits);
+        }
+    }
+}
+
+// the longest common subsequence of equal subtrees among the children not matched yet
+fn unchanged_subtrees(
+    old_children: &[NodeRef],
+    new_children: &[NodeRef],
+    matches: &[Option<usize>],
+    taken: &[bool],
+    hashes: &Hashes,
+) -> Vec<(usize, usize)> {
+    // a matching hash is confirmed once, not at every step below
+    let (n, m) = (new_children.len(), old_children.len());
+    let equal_pairs: Vec<Vec<bool>> = (0..n)
+        .map(|i| (0..m).map(|j| {
+            matches[i].is_none() && !taken[j]
+                && hashes.new[&new_children[i].id] == hashes.old[&old_children[j].id]
+                && same_subtree(old_children[j], new_children[i])
+        }).collect())
+        .collect();
+    let equal = |i: usize, j: usize| equal_pairs[i][j];
+
+    // lengths[i][j] is the answer for new_children[i..] and old_children[j..]
+    let mut lengths = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if equal(i, j) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if equal(i, j) {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+// old children that keep their relative order stay put, the others move, and
+// new ones are inserted. going right to left, the next sibling of every node is
+// already where it belongs, so each step only has to put a node before it
+fn reorder(
+    old: NodeRef,
+    old_children: &[NodeRef],
+    new_children: &[NodeRef],
+    matches: &[Option<usize>],
+    edits: &mut Vec<Edit>,
+) {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Slot {
+        Old(usize),
+        New(usize),
+    }
+
+    let target: Vec<Slot> = matches.iter().enumerate()
+        .map(|(i, j)| j.map_or(Slot::New(i), Slot::Old))
+        .collect();
+    let order: Vec<usize> = matches.iter().flatten().copied().collect();
+    let stable = longest_increasing(&order);
+
+    // the children of the old parent as the edits so far leave them
+    let mut current: Vec<Slot> = (0..old_children.len())
+        .filter(|j| order.contains(j))
+        .map(Slot::Old)
+        .collect();
+
+    for i in (0..target.len()).rev() {
+        let slot = target[i];
+        let node = match slot {
+            Slot::Old(j) if stable.contains(&j) => continue,
+            Slot::Old(j) => {
+                current.retain(|&s| s != slot);
+                old_children[j].id
+            }
+            Slot::New(_) => new_children[i].id,
+        };
+        let index = match target.get(i + 1) {
+            Some(next) => current.iter().position(|s| s == next).unwrap(),
+            None => current.len(),
+        };
+        current.insert(index, slot);
+        edits.push(match slot {
+            Slot::Old(_) => Edit::Move { parent: old.id, node, index },
+            Slot::New(_) => Edit::Insert { parent: old.id, index, node },
+        });
+    }
+}
+
+// https://en.wikipedia.org/wiki/Longest_increasing_subsequence
+fn longest_increasing(values: &[usize]) -> Vec<usize> {
+    // tails[k] is the position of the smallest value ending a run of length k + 1
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; values.len()];
+    for (pos, &value) in values.iter().enumerate() {
+        let k = tails.partition_point(|&t| values[t] < value);
+        previous[pos] = k.checked_sub(1).map(|k| tails[k]);
+        if k == tails.len() {
+            tails.push(pos);
+        } else {
+            tails[k] = pos;
+        }
+    }
+
+    let mut run: Vec<usize> = std::iter::successors(tails.last().copied(), |&pos| previous[pos])
+        .map(|pos| values[pos])
+        .collect();
+    run.reverse();
+    run
+}
+
+// ---------------------
+// patch
+// ---------------------
+
+impl dom::Document {
+    // applies edits computed against this document; `from` is the new
+    // document, where inserted nodes are copied from
+    pub fn apply_edits(&mut self, from: &dom::Document, edits: &[Edit]) -> Result<(), DomError> {
+        for edit in edits {
+            match edit {
+                Edit::Insert { parent, index, node } => {
+                    let child = self.children(*parent).nth(*index);
+                    let node = self.import_node(from, *node, true);
+                    self.insert_before(*parent, node, child)?;
+                }
+                Edit::Remove { parent, node } => {
+                    self.remove_child(*parent, *node)?;
+                }
+                Edit::Move { parent, node, index } => {
+                    let child = self.children(*parent).filter(|child| child != node).nth(*index);
+                    self.insert_before(*parent, *node, child)?;
+                }
+                Edit::Replace { node, with } => {
+                    let parent = self.node(*node).parent().ok_or(DomError::HierarchyRequest)?;
+                    let copy = self.import_node(from, *with, true);
+                    self.replace_child(parent, copy, *node)?;
+                }
+                // the names come from a parsed tree, which takes more than set_attribute does
+                Edit::SetAttribute { node, name, value } => self.write_attribute(*node, name.clone(), value.clone())?,
+                Edit::RemoveAttribute { node, name } => self.remove_attribute(*node, name)?,
+                Edit::SetText { node, text } => self.set_text_content(*node, text.clone()),
+            }
+        }
+        Ok(())
+    }
+}
+
+// ---------------------
+// assistant functions
+// ---------------------
+
+// only nodes of the same kind can be turned into each other in place
+fn same_kind(a: NodeRef, b: NodeRef) -> bool {
+    match (a.node_type(), b.node_type()) {
+        (NodeType::Element(a), NodeType::Element(b)) => a.tag == b.tag && a.namespace == b.namespace,
+        (NodeType::Doctype(a), NodeType::Doctype(b)) => a == b,
+        (a, b) => std::mem::discriminant(a) == std::mem::discriminant(b),
+    }
+}
+
+fn element_id<'a>(node: NodeRef<'a>) -> Option<&'a str> {
+    node.as_element()?.attributes.get("id").map(|id| id.as_str())
+}
+
+fn same_subtree(a: NodeRef, b: NodeRef) -> bool {
+    let same_contents = match (a.template_contents(), b.template_contents()) {
+        (Some(a), Some(b)) => same_subtree(a, b),
+        (a, b) => a.is_none() && b.is_none(),
+    };
+    a.node_type() == b.node_type()
+        && a.children().count() == b.children().count()
+        && a.children().zip(b.children()).all(|(a, b)| same_subtree(a, b))
+        && same_contents
+}
+
+// a node's hash covers its own data and the hashes of its children, so
+// each node is hashed once however deep the tree is
+fn hash_subtrees(node: NodeRef, hashes: &mut HashMap<NodeId, u64>) -> u64 {
+    let mut state = DefaultHasher::new();
+    std::mem::discriminant(node.node_type()).hash(&mut state);
+    match node.node_type() {
+        NodeType::Document | NodeType::DocumentFragment => {}
+        NodeType::Doctype(doctype) => (&doctype.name, &doctype.public_id, &doctype.system_id).hash(&mut state),
+        NodeType::Text(data) | NodeType::Comment(data) => data.hash(&mut state),
+        NodeType::Element(elem) => {
+            (&elem.tag, elem.namespace.url()).hash(&mut state);
+            let mut attributes: Vec<_> = elem.attributes.iter().collect();
+            attributes.sort();
+            attributes.hash(&mut state);
+        }
+    }
+    node.children().count().hash(&mut state);
+    for child in node.children() {
+        hash_subtrees(child, hashes).hash(&mut state);
+    }
+    if let Some(contents) = node.template_contents() {
+        hash_subtrees(contents, hashes).hash(&mut state);
+    }
+    let hash = state.finish();
+    hashes.insert(node.id, hash);
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::parse_html;
+
+    // diffs the two documents, patches the first and checks it came out as the second
+    fn patch(old_html: &str, new_html: &str) -> Vec<Edit> {
+        let mut old = parse_html(old_html.to_string());
+        let new = parse_html(new_html.to_string());
+        let edits = diff(old.get(old.root()), new.get(new.root()));
+        old.apply_edits(&new, &edits).unwrap();
+        assert_eq!(old.get(old.root()).outer_html(), new.get(new.root()).outer_html());
+        edits
+    }
+
+    #[test]
+    fn unchanged() {
+        assert_eq!(patch("<ul><li>a</li><li>b</li></ul>", "<ul><li>a</li><li>b</li></ul>"), vec![]);
+    }
+
+    #[test]
+    fn insert() {
+        let edits = patch("<ul><li>a</li><li>c</li></ul>", "<ul><li>a</li><li>b</li><li>c</li></ul>");
+        assert!(matches!(edits.as_slice(), [Edit::Insert { index: 1, .. }]), "{:?}", edits);
+    }
+
+    #[test]
+    fn remove() {
+        let edits = patch("<ul><li>a</li><li>b</li><li>c</li></ul>", "<ul><li>a</li><li>c</li></ul>");
+        assert!(matches!(edits.as_slice(), [Edit::Remove { .. }]), "{:?}", edits);
+    }
+
+    #[test]
+    fn move_keeps_the_longest_ordered_run() {
+        let edits = patch(
+            "<ul><li>1</li><li>2</li><li>3</li><li>4</li><li>5</li></ul>",
+            "<ul><li>5</li><li>1</li><li>2</li><li>3</li><li>4</li></ul>",
+        );
+        assert!(matches!(edits.as_slice(), [Edit::Move { index: 0, .. }]), "{:?}", edits);
+    }
+
+    #[test]
+    fn move_by_id() {
+        let edits = patch("<div id=a>1</div><div id=b>2</div>", "<div id=b>2!</div><div id=a>1</div>");
+        assert!(edits.iter().any(|edit| matches!(edit, Edit::Move { .. })), "{:?}", edits);
+        assert!(edits.iter().any(|edit| matches!(edit, Edit::SetText { text, .. } if text == "2!")), "{:?}", edits);
+    }
+
+    #[test]
+    fn attribute_change() {
+        let edits = patch("<p class=a title=t>x</p>", "<p class=b lang=en>x</p>");
+        assert_eq!(edits.len(), 3, "{:?}", edits);
+        assert!(edits.iter().any(|edit| matches!(edit, Edit::RemoveAttribute { name, .. } if name == "title")));
+        assert!(edits.iter().any(|edit| matches!(edit, Edit::SetAttribute { name, value, .. } if name == "class" && value == "b")));
+    }
+
+    // names the parser takes but set_attribute would turn down
+    #[test]
+    fn attribute_names_from_the_parser() {
+        patch("<p>x</p>", "<p a\"b=1 c'd=2 e<f=3 =g=4>x</p>");
+    }
+
+    #[test]
+    fn text_change() {
+        let edits = patch("<p>old</p><!--a-->", "<p>new</p><!--b-->");
+        assert_eq!(edits.len(), 2, "{:?}", edits);
+        assert!(edits.iter().all(|edit| matches!(edit, Edit::SetText { .. })));
+    }
+
+    #[test]
+    fn replace_by_another_kind() {
+        let edits = patch("<div><p>x</p></div>", "<div><span>x</span></div>");
+        assert!(edits.iter().all(|edit| !matches!(edit, Edit::SetText { .. })), "{:?}", edits);
+    }
+
+    #[test]
+    fn nested_changes() {
+        patch(
+            "<div><ul><li>a</li><li>b</li></ul><p>t</p></div>",
+            "<div><p>t</p><ul><li>b</li><li>a<b>!</b></li></ul><hr></div>",
+        );
+    }
+
+    #[test]
+    fn subtree_hashes_follow_content() {
+        let document = parse_html("<p>a</p><p>a</p><p>b</p>".to_string());
+        let mut hashes = HashMap::new();
+        hash_subtrees(document.get(document.root()), &mut hashes);
+        let body = document.get(document.document_element().unwrap()).last_child().unwrap();
+        let paragraphs: Vec<u64> = body.children().map(|p| hashes[&p.id]).collect();
+        assert_eq!(paragraphs[0], paragraphs[1]);
+        assert_ne!(paragraphs[1], paragraphs[2]);
+        assert_eq!(hashes.len(), document.descendants(document.root()).count() + 1);
+    }
+
+    #[test]
+    fn template_contents_are_patched() {
+        let edits = patch("<template><p>a</p></template>", "<template><p>b</p><i></i></template>");
+        assert_eq!(edits.len(), 2);
+        // a template whose contents changed is not taken for an unchanged one
+        patch("<template>a</template><template>b</template>", "<template>b</template><template>a</template>");
+    }
+}
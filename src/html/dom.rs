@@ -1,52 +1,714 @@
 use std::collections::HashMap;
+use std::fmt;
+
+use encoding_rs::Encoding;
+
+use super::diagnostics::{Location, Span};
+use super::foreign;
 // use std::collections::HashSet;
 
+// nodes live in the arena of their Document and refer to each other by id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Node {
     pub node_type: NodeType,
-    pub children: Vec<Node>,
+    // where the parser found the node; elements cover their start tag and
+    // nodes made through the DOM API have none
+    pub span: Option<Span>,
+    // where the parser found the value of each attribute, e.g. to point into a style=""
+    pub attribute_locations: HashMap<String, Location>,
+    parent: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+    prev_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+    // the fragment that holds what a <template> contains; it is not a child
+    template_contents: Option<NodeId>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum NodeType {
+    Document,
+    // only used for the contents of templates
+    DocumentFragment,
+    Doctype(DoctypeData),
     Text(String),
     Element(ElementData),
     Comment(String),
 }
 
+// https://dom.spec.whatwg.org/#concept-document-mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuirksMode {
+    #[default]
+    NoQuirks,
+    LimitedQuirks,
+    Quirks,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DoctypeData {
+    pub name: String,
+    pub public_id: String,
+    pub system_id: String,
+}
+
+// attributes are keyed by their qualified name, e.g. "xlink:href"
 #[derive(Debug, Clone, PartialEq)]
 pub struct ElementData {
     pub tag: String,
+    pub namespace: Namespace,
     pub attributes: HashMap<String, String>,
 }
 
+// https://infra.spec.whatwg.org/#namespaces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Namespace {
+    #[default]
+    Html,
+    MathMl,
+    Svg,
+    XLink,
+    Xml,
+    Xmlns,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    nodes: Vec<Node>,
+    pub mode: QuirksMode,
+    // the character encoding the document was decoded with
+    pub encoding: &'static Encoding,
+    changes: Vec<Change>,
+}
+
+// what a mutation touched, so later stages know which nodes are dirty
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Inserted { parent: NodeId, node: NodeId },
+    Removed { parent: NodeId, node: NodeId },
+    Attribute { node: NodeId, name: String },
+    Text { node: NodeId },
+}
+
+// https://webidl.spec.whatwg.org/#idl-DOMException-error-names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomError {
+    HierarchyRequest,
+    NotFound,
+    InvalidCharacter,
+    InvalidNodeType,
+}
+
+// a node together with its owner document, for walking the tree
+#[derive(Clone, Copy)]
+pub struct NodeRef<'a> {
+    pub doc: &'a Document,
+    pub id: NodeId,
+}
+
 impl Node {
-    pub fn text(data: String) -> Node {
+    fn new(node_type: NodeType) -> Node {
         Node {
-            node_type: NodeType::Text(data),
-            children: Vec::new(),
+            node_type,
+            span: None,
+            attribute_locations: HashMap::new(),
+            parent: None,
+            first_child: None,
+            last_child: None,
+            prev_sibling: None,
+            next_sibling: None,
+            template_contents: None,
         }
     }
 
-    pub fn comment(data: String) -> Node {
-        Node {
-            node_type: NodeType::Comment(data),
-            children: Vec::new(),
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn first_child(&self) -> Option<NodeId> {
+        self.first_child
+    }
+
+    pub fn last_child(&self) -> Option<NodeId> {
+        self.last_child
+    }
+
+    pub fn prev_sibling(&self) -> Option<NodeId> {
+        self.prev_sibling
+    }
+
+    pub fn next_sibling(&self) -> Option<NodeId> {
+        self.next_sibling
+    }
+
+    pub fn as_element(&self) -> Option<&ElementData> {
+        match &self.node_type {
+            NodeType::Element(elem) => Some(elem),
+            _ => None,
         }
     }
 
-    pub fn element(tag: String, attrs: HashMap<String, String>, children: Vec<Node>) -> Node {
-        Node {
-            node_type: NodeType::Element(ElementData {
-                tag: tag,
-                attributes: attrs,
-            }),
-            children: children,
+    // https://html.spec.whatwg.org/multipage/scripting.html#template-contents
+    pub fn template_contents(&self) -> Option<NodeId> {
+        self.template_contents
+    }
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Document {
+    pub fn new() -> Document {
+        Document {
+            nodes: vec![Node::new(NodeType::Document)],
+            mode: QuirksMode::default(),
+            encoding: encoding_rs::UTF_8,
+            changes: Vec::new(),
+        }
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    pub fn get(&self, id: NodeId) -> NodeRef<'_> {
+        NodeRef { doc: self, id }
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    // raw access for the parser; edits made through it are not logged
+    pub(crate) fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    pub fn document_element(&self) -> Option<NodeId> {
+        self.children(self.root()).find(|&child| matches!(self.node(child).node_type, NodeType::Element(_)))
+    }
+
+// ---------------------
+// create nodes
+// ---------------------
+
+    // new nodes are detached until they are appended somewhere; a template
+    // gets an empty fragment for its contents along with it
+    pub fn create_node(&mut self, node_type: NodeType) -> NodeId {
+        let is_template = matches!(&node_type, NodeType::Element(elem) if elem.is_html() && elem.tag == "template");
+        self.nodes.push(Node::new(node_type));
+        let id = NodeId(self.nodes.len() - 1);
+        if is_template {
+            self.nodes.push(Node::new(NodeType::DocumentFragment));
+            self.node_mut(id).template_contents = Some(NodeId(self.nodes.len() - 1));
         }
+        id
+    }
+
+    pub fn create_text(&mut self, data: String) -> NodeId {
+        self.create_node(NodeType::Text(data))
+    }
+
+    pub fn create_comment(&mut self, data: String) -> NodeId {
+        self.create_node(NodeType::Comment(data))
+    }
+
+    pub fn create_element(&mut self, tag: String, attrs: HashMap<String, String>) -> NodeId {
+        self.create_element_ns(tag, Namespace::Html, attrs)
+    }
+
+    pub fn create_element_ns(&mut self, tag: String, namespace: Namespace, attrs: HashMap<String, String>) -> NodeId {
+        self.create_node(NodeType::Element(ElementData {
+            tag,
+            namespace,
+            attributes: attrs,
+        }))
     }
 
+    // a deep clone of a template clones its contents too
+    pub fn clone_node(&mut self, id: NodeId, deep: bool) -> NodeId {
+        let copy = self.create_node(self.node(id).node_type.clone());
+        if deep {
+            let children: Vec<NodeId> = self.children(id).collect();
+            for child in children {
+                let child_copy = self.clone_node(child, true);
+                self.append(copy, child_copy);
+            }
+            if let Some(contents) = self.node(id).template_contents
+                && let Some(copy_contents) = self.node(copy).template_contents
+            {
+                let children: Vec<NodeId> = self.children(contents).collect();
+                for child in children {
+                    let child_copy = self.clone_node(child, true);
+                    self.append(copy_contents, child_copy);
+                }
+            }
+        }
+        copy
+    }
+
+    // copies a node of another document into this one, detached
+    pub fn import_node(&mut self, from: &Document, id: NodeId, deep: bool) -> NodeId {
+        let copy = self.create_node(from.node(id).node_type.clone());
+        if deep {
+            for child in from.children(id) {
+                let child_copy = self.import_node(from, child, true);
+                self.append(copy, child_copy);
+            }
+            if let Some(contents) = from.node(id).template_contents
+                && let Some(copy_contents) = self.node(copy).template_contents
+            {
+                for child in from.children(contents) {
+                    let child_copy = self.import_node(from, child, true);
+                    self.append(copy_contents, child_copy);
+                }
+            }
+        }
+        copy
+    }
+
+// ---------------------
+// mutate the tree
+// ---------------------
+
+    pub fn append_child(&mut self, parent: NodeId, node: NodeId) -> Result<NodeId, DomError> {
+        self.insert_before(parent, node, None)
+    }
+
+    // https://dom.spec.whatwg.org/#concept-node-pre-insert
+    pub fn insert_before(&mut self, parent: NodeId, node: NodeId, child: Option<NodeId>) -> Result<NodeId, DomError> {
+        self.ensure_pre_insertion_validity(parent, node, child)?;
+
+        let before = if child == Some(node) { self.node(node).next_sibling } else { child };
+        self.remove_from_parent(node);
+        self.insert(parent, node, before);
+        self.changes.push(Change::Inserted { parent, node });
+        Ok(node)
+    }
+
+    pub fn remove_child(&mut self, parent: NodeId, child: NodeId) -> Result<NodeId, DomError> {
+        if self.node(child).parent != Some(parent) {
+            return Err(DomError::NotFound);
+        }
+        self.remove_from_parent(child);
+        Ok(child)
+    }
+
+    // https://dom.spec.whatwg.org/#concept-node-replace
+    pub fn replace_child(&mut self, parent: NodeId, node: NodeId, child: NodeId) -> Result<NodeId, DomError> {
+        self.ensure_insertable(parent, node)?;
+        if self.node(child).parent != Some(parent) {
+            return Err(DomError::NotFound);
+        }
+        if self.node(parent).node_type == NodeType::Document {
+            let conflict = match self.node(node).node_type {
+                NodeType::Element(_) => {
+                    self.children(parent).any(|n| n != child && self.is_element(n))
+                        || self.following_siblings(child).any(|n| self.is_doctype(n))
+                }
+                NodeType::Doctype(_) => {
+                    self.children(parent).any(|n| n != child && self.is_doctype(n))
+                        || self.preceding_siblings(child).any(|n| self.is_element(n))
+                }
+                _ => false,
+            };
+            if conflict {
+                return Err(DomError::HierarchyRequest);
+            }
+        }
+        if node == child {
+            return Ok(child);
+        }
+
+        let mut before = self.node(child).next_sibling;
+        if before == Some(node) {
+            before = self.node(node).next_sibling;
+        }
+        self.remove_from_parent(child);
+        self.remove_from_parent(node);
+        self.insert(parent, node, before);
+        self.changes.push(Change::Inserted { parent, node });
+        Ok(child)
+    }
+
+    pub fn set_attribute(&mut self, id: NodeId, name: &str, value: String) -> Result<(), DomError> {
+        // https://dom.spec.whatwg.org/#valid-attribute-local-name
+        if name.is_empty() || name.contains(|c: char| c.is_ascii_whitespace() || matches!(c, '\0' | '/' | '>' | '=')) {
+            return Err(DomError::InvalidCharacter);
+        }
+        let NodeType::Element(elem) = &mut self.node_mut(id).node_type else {
+            return Err(DomError::InvalidNodeType);
+        };
+
+        let name = if elem.namespace == Namespace::Html { name.to_ascii_lowercase() } else { name.to_string() };
+        elem.attributes.insert(name.clone(), value);
+        self.changes.push(Change::Attribute { node: id, name });
+        Ok(())
+    }
+
+    pub fn remove_attribute(&mut self, id: NodeId, name: &str) -> Result<(), DomError> {
+        let NodeType::Element(elem) = &mut self.node_mut(id).node_type else {
+            return Err(DomError::InvalidNodeType);
+        };
+
+        let name = if elem.namespace == Namespace::Html { name.to_ascii_lowercase() } else { name.to_string() };
+        if elem.attributes.remove(&name).is_some() {
+            self.changes.push(Change::Attribute { node: id, name });
+        }
+        Ok(())
+    }
+
+    // https://dom.spec.whatwg.org/#dom-node-textcontent
+    pub fn set_text_content(&mut self, id: NodeId, text: String) {
+        match &mut self.node_mut(id).node_type {
+            NodeType::Text(data) | NodeType::Comment(data) => {
+                *data = text;
+                self.changes.push(Change::Text { node: id });
+            }
+            NodeType::Element(_) | NodeType::DocumentFragment => {
+                let children: Vec<NodeId> = self.children(id).collect();
+                for child in children {
+                    self.remove_from_parent(child);
+                }
+                if !text.is_empty() {
+                    let node = self.create_text(text);
+                    self.insert(id, node, None);
+                    self.changes.push(Change::Inserted { parent: id, node });
+                }
+            }
+            NodeType::Document | NodeType::Doctype(_) => {}
+        }
+    }
+
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    pub fn take_changes(&mut self) -> Vec<Change> {
+        std::mem::take(&mut self.changes)
+    }
+
+    fn remove_from_parent(&mut self, id: NodeId) {
+        if let Some(parent) = self.node(id).parent {
+            self.detach(id);
+            self.changes.push(Change::Removed { parent, node: id });
+        }
+    }
+
+    // https://dom.spec.whatwg.org/#concept-node-ensure-pre-insertion-validity
+    fn ensure_pre_insertion_validity(&self, parent: NodeId, node: NodeId, child: Option<NodeId>) -> Result<(), DomError> {
+        self.ensure_insertable(parent, node)?;
+        if let Some(child) = child
+            && self.node(child).parent != Some(parent)
+        {
+            return Err(DomError::NotFound);
+        }
+        if self.node(parent).node_type != NodeType::Document {
+            return Ok(());
+        }
+
+        let conflict = match self.node(node).node_type {
+            NodeType::Element(_) => {
+                self.children(parent).any(|n| self.is_element(n))
+                    || child.is_some_and(|child| {
+                        self.is_doctype(child) || self.following_siblings(child).any(|n| self.is_doctype(n))
+                    })
+            }
+            NodeType::Doctype(_) => {
+                self.children(parent).any(|n| self.is_doctype(n))
+                    || match child {
+                        Some(child) => self.preceding_siblings(child).any(|n| self.is_element(n)),
+                        None => self.children(parent).any(|n| self.is_element(n)),
+                    }
+            }
+            _ => false,
+        };
+        if conflict {
+            return Err(DomError::HierarchyRequest);
+        }
+        Ok(())
+    }
+
+    // the checks shared by insertion and replacement. fragments only serve as
+    // template contents here, so one is never inserted itself
+    pub(crate) fn ensure_insertable(&self, parent: NodeId, node: NodeId) -> Result<(), DomError> {
+        let parent_is_document = match self.node(parent).node_type {
+            NodeType::Document => true,
+            NodeType::Element(_) | NodeType::DocumentFragment => false,
+            _ => return Err(DomError::HierarchyRequest),
+        };
+        if self.is_inclusive_ancestor(node, parent) {
+            return Err(DomError::HierarchyRequest);
+        }
+        match self.node(node).node_type {
+            NodeType::Document | NodeType::DocumentFragment => Err(DomError::HierarchyRequest),
+            NodeType::Text(_) if parent_is_document => Err(DomError::HierarchyRequest),
+            NodeType::Doctype(_) if !parent_is_document => Err(DomError::HierarchyRequest),
+            _ => Ok(()),
+        }
+    }
+
+    fn is_inclusive_ancestor(&self, ancestor: NodeId, id: NodeId) -> bool {
+        std::iter::successors(Some(id), |&n| self.node(n).parent).any(|n| n == ancestor)
+    }
+
+    fn is_element(&self, id: NodeId) -> bool {
+        matches!(self.node(id).node_type, NodeType::Element(_))
+    }
+
+    fn is_doctype(&self, id: NodeId) -> bool {
+        matches!(self.node(id).node_type, NodeType::Doctype(_))
+    }
+
+    fn following_siblings(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self.node(id).next_sibling, |&n| self.node(n).next_sibling)
+    }
+
+    fn preceding_siblings(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self.node(id).prev_sibling, |&n| self.node(n).prev_sibling)
+    }
+
+// ---------------------
+// link nodes
+// ---------------------
+
+    // the parser links nodes directly: no validity checks, nothing logged
+    pub(crate) fn append(&mut self, parent: NodeId, child: NodeId) {
+        self.insert(parent, child, None);
+    }
+
+    // an attribute as the parser reads it, whose names need not be valid
+    // for set_attribute; patches copy these over, so the change is logged
+    pub(crate) fn write_attribute(&mut self, id: NodeId, name: String, value: String) -> Result<(), DomError> {
+        let NodeType::Element(elem) = &mut self.node_mut(id).node_type else {
+            return Err(DomError::InvalidNodeType);
+        };
+        elem.attributes.insert(name.clone(), value);
+        self.changes.push(Change::Attribute { node: id, name });
+        Ok(())
+    }
+
+    // inserts child before `before`, or at the end when there is none
+    pub(crate) fn insert(&mut self, parent: NodeId, child: NodeId, before: Option<NodeId>) {
+        self.detach(child);
+
+        let prev = match before {
+            Some(before) => self.node(before).prev_sibling,
+            None => self.node(parent).last_child,
+        };
+        match prev {
+            Some(prev) => self.node_mut(prev).next_sibling = Some(child),
+            None => self.node_mut(parent).first_child = Some(child),
+        }
+        match before {
+            Some(before) => self.node_mut(before).prev_sibling = Some(child),
+            None => self.node_mut(parent).last_child = Some(child),
+        }
+
+        let node = self.node_mut(child);
+        node.parent = Some(parent);
+        node.prev_sibling = prev;
+        node.next_sibling = before;
+    }
+
+    // unlinks the node from its parent; it keeps its own children
+    pub(crate) fn detach(&mut self, id: NodeId) {
+        let node = self.node_mut(id);
+        let (Some(parent), prev, next) = (node.parent.take(), node.prev_sibling.take(), node.next_sibling.take()) else {
+            return;
+        };
+
+        match prev {
+            Some(prev) => self.node_mut(prev).next_sibling = next,
+            None => self.node_mut(parent).first_child = next,
+        }
+        match next {
+            Some(next) => self.node_mut(next).prev_sibling = prev,
+            None => self.node_mut(parent).last_child = prev,
+        }
+    }
+
+// ---------------------
+// walk the tree
+// ---------------------
+
+    pub fn children(&self, id: NodeId) -> Children<'_> {
+        Children {
+            doc: self,
+            next: self.node(id).first_child,
+        }
+    }
+
+    // every node below `id`, in tree order
+    pub fn descendants(&self, id: NodeId) -> Descendants<'_> {
+        Descendants {
+            doc: self,
+            root: id,
+            next: self.node(id).first_child,
+        }
+    }
+}
+
+pub struct Children<'a> {
+    doc: &'a Document,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Children<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let current = self.next?;
+        self.next = self.doc.node(current).next_sibling;
+        Some(current)
+    }
+}
+
+pub struct Descendants<'a> {
+    doc: &'a Document,
+    root: NodeId,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Descendants<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let current = self.next?;
+        let node = self.doc.node(current);
+        self.next = node.first_child.or_else(|| {
+            // climb until an ancestor below the root has a next sibling
+            let mut ancestor = current;
+            loop {
+                if ancestor == self.root {
+                    return None;
+                }
+                let node = self.doc.node(ancestor);
+                if let Some(next) = node.next_sibling {
+                    return Some(next);
+                }
+                ancestor = node.parent?;
+            }
+        });
+        Some(current)
+    }
+}
+
+impl<'a> NodeRef<'a> {
+    pub fn node(&self) -> &'a Node {
+        self.doc.node(self.id)
+    }
+
+    pub fn node_type(&self) -> &'a NodeType {
+        &self.node().node_type
+    }
+
+    pub fn as_element(&self) -> Option<&'a ElementData> {
+        self.node().as_element()
+    }
+
+    pub fn owner_document(&self) -> &'a Document {
+        self.doc
+    }
+
+    pub fn parent(&self) -> Option<NodeRef<'a>> {
+        self.node().parent.map(|id| self.doc.get(id))
+    }
+
+    pub fn first_child(&self) -> Option<NodeRef<'a>> {
+        self.node().first_child.map(|id| self.doc.get(id))
+    }
+
+    pub fn last_child(&self) -> Option<NodeRef<'a>> {
+        self.node().last_child.map(|id| self.doc.get(id))
+    }
+
+    pub fn prev_sibling(&self) -> Option<NodeRef<'a>> {
+        self.node().prev_sibling.map(|id| self.doc.get(id))
+    }
+
+    pub fn next_sibling(&self) -> Option<NodeRef<'a>> {
+        self.node().next_sibling.map(|id| self.doc.get(id))
+    }
+
+    pub fn template_contents(&self) -> Option<NodeRef<'a>> {
+        self.node().template_contents.map(|id| self.doc.get(id))
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = NodeRef<'a>> + use<'a> {
+        let doc = self.doc;
+        doc.children(self.id).map(move |id| doc.get(id))
+    }
+
+    pub fn descendants(&self) -> impl Iterator<Item = NodeRef<'a>> + use<'a> {
+        let doc = self.doc;
+        doc.descendants(self.id).map(move |id| doc.get(id))
+    }
+}
+
+impl PartialEq for NodeRef<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.doc, other.doc) && self.id == other.id
+    }
+}
+
+// printing the owner document with every node would be far too noisy
+impl fmt::Debug for NodeRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NodeRef")
+            .field("id", &self.id)
+            .field("node_type", self.node_type())
+            .finish()
+    }
+}
+
+impl ElementData {
+    pub fn is_html(&self) -> bool {
+        self.namespace == Namespace::Html
+    }
+
+    // only the attributes of svg and math elements can be namespaced
+    pub fn attribute_namespace(&self, name: &str) -> Option<Namespace> {
+        if self.is_html() || !self.attributes.contains_key(name) {
+            return None;
+        }
+        foreign::attribute_namespace(name)
+    }
+}
+
+impl Namespace {
+    pub fn url(&self) -> &'static str {
+        match self {
+            Namespace::Html => "http://www.w3.org/1999/xhtml",
+            Namespace::MathMl => "http://www.w3.org/1998/Math/MathML",
+            Namespace::Svg => "http://www.w3.org/2000/svg",
+            Namespace::XLink => "http://www.w3.org/1999/xlink",
+            Namespace::Xml => "http://www.w3.org/XML/1998/namespace",
+            Namespace::Xmlns => "http://www.w3.org/2000/xmlns/",
+        }
+    }
+}
+
+impl fmt::Display for DomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            DomError::HierarchyRequest => "the operation would yield an incorrect node tree",
+            DomError::NotFound => "the node is not a child of the given parent",
+            DomError::InvalidCharacter => "the name contains an invalid character",
+            DomError::InvalidNodeType => "the operation is not supported on this node type",
+        };
+        f.write_str(message)
+    }
 }
 
+impl std::error::Error for DomError {}
+
 // impl ElementData {
     // pub fn id(&self) -> Option<&str> {
     //     self.attributes.get("id").map(|s| s.as_str())
@@ -59,3 +721,238 @@ impl Node {
     //     }
     // }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_come_with_contents() {
+        let mut document = Document::new();
+        let template = document.create_element("template".to_string(), HashMap::new());
+        let contents = document.node(template).template_contents().unwrap();
+        assert_eq!(document.node(contents).node_type, NodeType::DocumentFragment);
+        assert!(document.node(contents).parent().is_none());
+
+        let svg = document.create_element_ns("template".to_string(), Namespace::Svg, HashMap::new());
+        assert!(document.node(svg).template_contents().is_none());
+    }
+
+    #[test]
+    fn clones_of_templates_copy_the_contents() {
+        let mut document = Document::new();
+        let template = document.create_element("template".to_string(), HashMap::new());
+        let contents = document.node(template).template_contents().unwrap();
+        let text = document.create_text("a".to_string());
+        document.append_child(contents, text).unwrap();
+
+        let deep = document.clone_node(template, true);
+        let deep_contents = document.node(deep).template_contents().unwrap();
+        assert_ne!(deep_contents, contents);
+        assert_eq!(document.children(deep_contents).count(), 1);
+        let shallow = document.clone_node(template, false);
+        assert_eq!(document.children(document.node(shallow).template_contents().unwrap()).count(), 0);
+
+        let mut other = Document::new();
+        let imported = other.import_node(&document, template, true);
+        assert_eq!(other.children(other.node(imported).template_contents().unwrap()).count(), 1);
+    }
+
+    #[test]
+    fn fragments_are_not_inserted() {
+        let mut document = Document::new();
+        let template = document.create_element("template".to_string(), HashMap::new());
+        let div = document.create_element("div".to_string(), HashMap::new());
+        let contents = document.node(template).template_contents().unwrap();
+        assert_eq!(document.append_child(div, contents), Err(DomError::HierarchyRequest));
+    }
+
+    fn element(document: &mut Document, tag: &str) -> NodeId {
+        document.create_element(tag.to_string(), HashMap::new())
+    }
+
+    // a <ul> with <li> children a, b and c, under the root
+    fn list(document: &mut Document) -> (NodeId, [NodeId; 3]) {
+        let ul = element(document, "ul");
+        document.append_child(document.root(), ul).unwrap();
+        let items = ["a", "b", "c"].map(|class| {
+            let attrs = HashMap::from([("class".to_string(), class.to_string())]);
+            let li = document.create_element("li".to_string(), attrs);
+            document.append_child(ul, li).unwrap()
+        });
+        (ul, items)
+    }
+
+    #[test]
+    fn children_are_linked_both_ways() {
+        let mut document = Document::new();
+        let (ul, [a, b, c]) = list(&mut document);
+        assert_eq!(document.node(ul).first_child(), Some(a));
+        assert_eq!(document.node(ul).last_child(), Some(c));
+        assert_eq!(document.node(b).prev_sibling(), Some(a));
+        assert_eq!(document.node(b).next_sibling(), Some(c));
+        assert_eq!(document.node(a).prev_sibling(), None);
+        assert_eq!(document.node(c).next_sibling(), None);
+        assert!([a, b, c].iter().all(|&li| document.node(li).parent() == Some(ul)));
+        assert_eq!(document.children(ul).collect::<Vec<_>>(), [a, b, c]);
+        assert_eq!(document.document_element(), Some(ul));
+    }
+
+    #[test]
+    fn detached_nodes_are_unlinked() {
+        let mut document = Document::new();
+        let (ul, [a, b, c]) = list(&mut document);
+        document.detach(b);
+        assert_eq!(document.node(b).parent(), None);
+        assert_eq!((document.node(b).prev_sibling(), document.node(b).next_sibling()), (None, None));
+        assert_eq!(document.node(a).next_sibling(), Some(c));
+        assert_eq!(document.node(c).prev_sibling(), Some(a));
+
+        document.detach(a);
+        document.detach(c);
+        assert_eq!((document.node(ul).first_child(), document.node(ul).last_child()), (None, None));
+    }
+
+    #[test]
+    fn descendants_in_tree_order_stay_below_the_root() {
+        let mut document = Document::new();
+        let (ul, [a, b, c]) = list(&mut document);
+        let span = element(&mut document, "span");
+        document.append_child(a, span).unwrap();
+        let after = element(&mut document, "p");
+        document.append_child(b, after).unwrap();
+        assert_eq!(document.descendants(ul).collect::<Vec<_>>(), [a, span, b, after, c]);
+        assert_eq!(document.descendants(a).collect::<Vec<_>>(), [span]);
+        assert_eq!(document.descendants(c).count(), 0);
+    }
+
+    #[test]
+    fn node_refs_walk_the_tree() {
+        let mut document = Document::new();
+        let (ul, [a, b, c]) = list(&mut document);
+        let b = document.get(b);
+        assert_eq!(b.parent().map(|n| n.id), Some(ul));
+        assert_eq!(b.prev_sibling().map(|n| n.id), Some(a));
+        assert_eq!(b.next_sibling().map(|n| n.id), Some(c));
+        assert_eq!(b.as_element().map(|elem| elem.tag.as_str()), Some("li"));
+        assert!(std::ptr::eq(b.owner_document(), &document));
+
+        let root = document.get(document.root());
+        assert_eq!(root.first_child().map(|n| n.id), Some(ul));
+        assert_eq!(root.parent().map(|n| n.id), None);
+        assert_eq!(root.descendants().count(), 4);
+        assert_eq!(root.first_child().unwrap().last_child().map(|n| n.id), Some(c));
+    }
+
+    #[test]
+    fn insertion_moves_nodes_and_logs_it() {
+        let mut document = Document::new();
+        let (ul, [a, b, c]) = list(&mut document);
+        document.take_changes();
+        assert_eq!(document.insert_before(ul, c, Some(a)), Ok(c));
+        assert_eq!(document.children(ul).collect::<Vec<_>>(), [c, a, b]);
+        assert_eq!(document.take_changes(), [
+            Change::Removed { parent: ul, node: c },
+            Change::Inserted { parent: ul, node: c },
+        ]);
+        // before itself: stays where it is
+        assert_eq!(document.insert_before(ul, a, Some(a)), Ok(a));
+        assert_eq!(document.children(ul).collect::<Vec<_>>(), [c, a, b]);
+    }
+
+    #[test]
+    fn invalid_insertions_are_refused() {
+        let mut document = Document::new();
+        let (ul, [a, b, _]) = list(&mut document);
+        let root = document.root();
+        let text = document.create_text("x".to_string());
+        let stray = element(&mut document, "li");
+        let doctype = document.create_node(NodeType::Doctype(DoctypeData {
+            name: "html".to_string(),
+            public_id: String::new(),
+            system_id: String::new(),
+        }));
+        assert_eq!(document.append_child(a, ul), Err(DomError::HierarchyRequest));
+        assert_eq!(document.append_child(a, a), Err(DomError::HierarchyRequest));
+        assert_eq!(document.append_child(text, stray), Err(DomError::HierarchyRequest));
+        assert_eq!(document.insert_before(a, stray, Some(b)), Err(DomError::NotFound));
+        assert_eq!(document.append_child(root, text), Err(DomError::HierarchyRequest));
+        assert_eq!(document.append_child(root, stray), Err(DomError::HierarchyRequest));
+        assert_eq!(document.append_child(root, doctype), Err(DomError::HierarchyRequest));
+        assert_eq!(document.append_child(ul, doctype), Err(DomError::HierarchyRequest));
+        assert_eq!(document.insert_before(root, doctype, Some(ul)), Ok(doctype));
+        assert_eq!(document.remove_child(a, b), Err(DomError::NotFound));
+        assert_eq!(document.children(ul).count(), 3);
+    }
+
+    #[test]
+    fn replace_and_remove() {
+        let mut document = Document::new();
+        let (ul, [a, b, c]) = list(&mut document);
+        let root = document.root();
+        let ol = element(&mut document, "ol");
+        document.take_changes();
+        assert_eq!(document.replace_child(ul, c, a), Ok(a));
+        assert_eq!(document.children(ul).collect::<Vec<_>>(), [c, b]);
+        assert_eq!(document.node(a).parent(), None);
+        assert_eq!(document.remove_child(ul, b), Ok(b));
+        assert_eq!(document.take_changes(), [
+            Change::Removed { parent: ul, node: a },
+            Change::Removed { parent: ul, node: c },
+            Change::Inserted { parent: ul, node: c },
+            Change::Removed { parent: ul, node: b },
+        ]);
+
+        // the one document element can be swapped, but not joined by another
+        assert_eq!(document.append_child(root, ol), Err(DomError::HierarchyRequest));
+        assert_eq!(document.replace_child(root, ol, ul), Ok(ul));
+        assert_eq!(document.document_element(), Some(ol));
+    }
+
+    #[test]
+    fn attributes() {
+        let mut document = Document::new();
+        let (ul, _) = list(&mut document);
+        let text = document.create_text("x".to_string());
+        let svg = document.create_element_ns("svg".to_string(), Namespace::Svg, HashMap::new());
+        document.take_changes();
+        assert_eq!(document.set_attribute(ul, "Data-X", "1".to_string()), Ok(()));
+        assert_eq!(document.node(ul).as_element().unwrap().attributes["data-x"], "1");
+        assert_eq!(document.set_attribute(svg, "viewBox", "0 0 1 1".to_string()), Ok(()));
+        assert!(document.node(svg).as_element().unwrap().attributes.contains_key("viewBox"));
+        assert_eq!(document.set_attribute(ul, "a b", String::new()), Err(DomError::InvalidCharacter));
+        assert_eq!(document.set_attribute(ul, "", String::new()), Err(DomError::InvalidCharacter));
+        assert_eq!(document.set_attribute(text, "a", String::new()), Err(DomError::InvalidNodeType));
+
+        assert_eq!(document.remove_attribute(ul, "DATA-X"), Ok(()));
+        assert_eq!(document.remove_attribute(ul, "data-x"), Ok(()));
+        assert_eq!(document.take_changes(), [
+            Change::Attribute { node: ul, name: "data-x".to_string() },
+            Change::Attribute { node: svg, name: "viewBox".to_string() },
+            Change::Attribute { node: ul, name: "data-x".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn text_content_replaces_the_children() {
+        let mut document = Document::new();
+        let (ul, [a, b, c]) = list(&mut document);
+        document.take_changes();
+        document.set_text_content(ul, "x".to_string());
+        let text = document.node(ul).first_child().unwrap();
+        assert_eq!(document.node(text).node_type, NodeType::Text("x".to_string()));
+        assert_eq!(document.children(ul).count(), 1);
+        assert_eq!(document.take_changes(), [
+            Change::Removed { parent: ul, node: a },
+            Change::Removed { parent: ul, node: b },
+            Change::Removed { parent: ul, node: c },
+            Change::Inserted { parent: ul, node: text },
+        ]);
+
+        document.set_text_content(text, "y".to_string());
+        assert_eq!(document.node(text).node_type, NodeType::Text("y".to_string()));
+        document.set_text_content(ul, String::new());
+        assert_eq!(document.children(ul).count(), 0);
+        assert_eq!(document.changes().len(), 2);
+    }
+}
@@ -0,0 +1,288 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252, X_USER_DEFINED};
+
+// https://html.spec.whatwg.org/multipage/parsing.html#concept-encoding-confidence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confidence {
+    Tentative,
+    Certain,
+}
+
+// ---------------------
+// sniffing
+// ---------------------
+
+// https://html.spec.whatwg.org/multipage/parsing.html#encoding-sniffing-algorithm
+pub fn sniff(bytes: &[u8], fallback: &'static Encoding) -> (&'static Encoding, Confidence) {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return (encoding, Confidence::Certain);
+    }
+    if let Some(encoding) = prescan(&bytes[..bytes.len().min(1024)]) {
+        return (encoding, Confidence::Tentative);
+    }
+    (fallback, Confidence::Tentative)
+}
+
+// https://html.spec.whatwg.org/multipage/parsing.html#prescan-a-byte-stream-to-determine-its-encoding
+pub fn prescan(bytes: &[u8]) -> Option<&'static Encoding> {
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let rest = &bytes[pos..];
+        if rest.starts_with(b"<!--") {
+            // the "-->" may share its dashes with the "<!--"
+            pos += 2 + find(&rest[2..], b"-->")? + 3;
+            continue;
+        }
+        if starts_with_ignore_case(rest, b"<meta") && rest.get(5).is_some_and(|&b| is_space(b) || b == b'/') {
+            pos += 5;
+            if let Some(encoding) = meta_prescan(bytes, &mut pos) {
+                return Some(encoding);
+            }
+            continue;
+        }
+        let tag_offset = match rest {
+            [b'<', b, ..] if b.is_ascii_alphabetic() => Some(1),
+            [b'<', b'/', b, ..] if b.is_ascii_alphabetic() => Some(2),
+            _ => None,
+        };
+        if let Some(offset) = tag_offset {
+            pos += offset;
+            while pos < bytes.len() && !is_space(bytes[pos]) && bytes[pos] != b'>' {
+                pos += 1;
+            }
+            while get_attribute(bytes, &mut pos).is_some() {}
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            pos += find(rest, b">")?;
+        }
+        pos += 1;
+    }
+    None
+}
+
+// the attributes of a <meta> tag, starting right after its name
+fn meta_prescan(bytes: &[u8], pos: &mut usize) -> Option<&'static Encoding> {
+    let mut seen: Vec<Vec<u8>> = Vec::new();
+    let mut got_pragma = false;
+    let mut need_pragma = None;
+    let mut charset = None;
+
+    while let Some((name, value)) = get_attribute(bytes, pos) {
+        if seen.contains(&name) {
+            continue;
+        }
+        match name.as_slice() {
+            b"http-equiv" if value == b"content-type" => got_pragma = true,
+            b"content" if charset.is_none() => {
+                if let Some(encoding) = extract_from_content(&value) {
+                    charset = Some(encoding);
+                    need_pragma = Some(true);
+                }
+            }
+            b"charset" if charset.is_none() => {
+                charset = Encoding::for_label(&value);
+                need_pragma = Some(false);
+            }
+            _ => {}
+        }
+        seen.push(name);
+    }
+
+    match need_pragma {
+        None => None,
+        Some(true) if !got_pragma => None,
+        _ => charset.map(adjust),
+    }
+}
+
+// https://html.spec.whatwg.org/multipage/parsing.html#concept-get-attributes-when-sniffing
+fn get_attribute(bytes: &[u8], pos: &mut usize) -> Option<(Vec<u8>, Vec<u8>)> {
+    while *pos < bytes.len() && (is_space(bytes[*pos]) || bytes[*pos] == b'/') {
+        *pos += 1;
+    }
+    if *bytes.get(*pos)? == b'>' {
+        return None;
+    }
+
+    let mut name = Vec::new();
+    let mut value = Vec::new();
+    loop {
+        let b = *bytes.get(*pos)?;
+        match b {
+            b'=' if !name.is_empty() => break,
+            b if is_space(b) => {
+                while bytes.get(*pos).is_some_and(|&b| is_space(b)) {
+                    *pos += 1;
+                }
+                if bytes.get(*pos) != Some(&b'=') {
+                    return Some((name, value));
+                }
+                break;
+            }
+            b'/' | b'>' => return Some((name, value)),
+            _ => name.push(b.to_ascii_lowercase()),
+        }
+        *pos += 1;
+    }
+
+    // skip the '=' and the spaces after it
+    *pos += 1;
+    while bytes.get(*pos).is_some_and(|&b| is_space(b)) {
+        *pos += 1;
+    }
+    match *bytes.get(*pos)? {
+        quote @ (b'"' | b'\'') => loop {
+            *pos += 1;
+            let b = *bytes.get(*pos)?;
+            if b == quote {
+                *pos += 1;
+                return Some((name, value));
+            }
+            value.push(b.to_ascii_lowercase());
+        },
+        b'>' => Some((name, value)),
+        b => {
+            value.push(b.to_ascii_lowercase());
+            loop {
+                *pos += 1;
+                match bytes.get(*pos) {
+                    Some(&b) if !is_space(b) && b != b'>' => value.push(b.to_ascii_lowercase()),
+                    _ => return Some((name, value)),
+                }
+            }
+        }
+    }
+}
+
+// https://html.spec.whatwg.org/multipage/urls-and-fetching.html#algorithm-for-extracting-a-character-encoding-from-a-meta-element
+pub fn extract_from_content(content: &[u8]) -> Option<&'static Encoding> {
+    let mut pos = 0;
+    loop {
+        pos += find_ignore_case(&content[pos..], b"charset")? + 7;
+        while content.get(pos).is_some_and(|&b| is_space(b)) {
+            pos += 1;
+        }
+        if content.get(pos) == Some(&b'=') {
+            break;
+        }
+    }
+
+    pos += 1;
+    while content.get(pos).is_some_and(|&b| is_space(b)) {
+        pos += 1;
+    }
+    match *content.get(pos)? {
+        quote @ (b'"' | b'\'') => {
+            let rest = &content[pos + 1..];
+            let end = rest.iter().position(|&b| b == quote)?;
+            Encoding::for_label(&rest[..end])
+        }
+        _ => {
+            let rest = &content[pos..];
+            let end = rest.iter().position(|&b| is_space(b) || b == b';').unwrap_or(rest.len());
+            Encoding::for_label(&rest[..end])
+        }
+    }
+}
+
+// the charset of a <meta> element seen by the tree builder
+pub fn meta_charset(attributes: &[(String, String)]) -> Option<&'static Encoding> {
+    let attribute = |name: &str| attributes.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_bytes());
+    if let Some(encoding) = attribute("charset").and_then(Encoding::for_label) {
+        return Some(encoding);
+    }
+    let is_pragma = attribute("http-equiv").is_some_and(|value| value.eq_ignore_ascii_case(b"content-type"));
+    match attribute("content") {
+        Some(content) if is_pragma => extract_from_content(content),
+        _ => None,
+    }
+}
+
+// a page can't declare itself UTF-16 from the inside, and x-user-defined
+// means windows-1252 when it comes from markup
+pub fn adjust(encoding: &'static Encoding) -> &'static Encoding {
+    if encoding == UTF_16BE || encoding == UTF_16LE {
+        UTF_8
+    } else if encoding == X_USER_DEFINED {
+        WINDOWS_1252
+    } else {
+        encoding
+    }
+}
+
+// ---------------------
+// assistant functions
+// ---------------------
+
+fn is_space(b: u8) -> bool {
+    matches!(b, b'\t' | b'\n' | b'\x0C' | b'\r' | b' ')
+}
+
+fn starts_with_ignore_case(bytes: &[u8], prefix: &[u8]) -> bool {
+    bytes.len() >= prefix.len() && bytes[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+fn find(bytes: &[u8], needle: &[u8]) -> Option<usize> {
+    bytes.windows(needle.len()).position(|window| window == needle)
+}
+
+fn find_ignore_case(bytes: &[u8], needle: &[u8]) -> Option<usize> {
+    bytes.windows(needle.len()).position(|window| window.eq_ignore_ascii_case(needle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{ISO_8859_2, SHIFT_JIS, WINDOWS_1251};
+
+    #[test]
+    fn bom_wins_over_meta() {
+        let bytes = b"\xEF\xBB\xBF<meta charset=windows-1251>";
+        assert_eq!(sniff(bytes, WINDOWS_1252), (UTF_8, Confidence::Certain));
+        let bytes = b"\xFF\xFE<\x00m\x00";
+        assert_eq!(sniff(bytes, WINDOWS_1252), (UTF_16LE, Confidence::Certain));
+    }
+
+    #[test]
+    fn meta_charset() {
+        assert_eq!(prescan(b"<meta charset=\"windows-1251\">"), Some(WINDOWS_1251));
+        assert_eq!(prescan(b"<META CHARSET='Shift_JIS'>"), Some(SHIFT_JIS));
+        assert_eq!(sniff(b"<meta charset=iso-8859-2>", UTF_8), (ISO_8859_2, Confidence::Tentative));
+        assert_eq!(sniff(b"<p>no meta</p>", WINDOWS_1251), (WINDOWS_1251, Confidence::Tentative));
+    }
+
+    #[test]
+    fn pragma() {
+        let bytes = b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=windows-1251\">";
+        assert_eq!(prescan(bytes), Some(WINDOWS_1251));
+        // content only counts together with http-equiv=content-type
+        assert_eq!(prescan(b"<meta content=\"text/html; charset=windows-1251\">"), None);
+        assert_eq!(prescan(b"<meta http-equiv=refresh content=\"text/html; charset=windows-1251\">"), None);
+    }
+
+    // the first of charset and content to give an encoding is the one used
+    #[test]
+    fn first_charset_wins() {
+        let bytes = b"<meta http-equiv=content-type content=\"text/html; charset=windows-1251\" charset=shift_jis>";
+        assert_eq!(prescan(bytes), Some(WINDOWS_1251));
+        let bytes = b"<meta charset=shift_jis http-equiv=content-type content=\"text/html; charset=windows-1251\">";
+        assert_eq!(prescan(bytes), Some(SHIFT_JIS));
+    }
+
+    #[test]
+    fn comments_and_other_tags_are_skipped() {
+        assert_eq!(prescan(b"<!-- <meta charset=windows-1251> --><meta charset=shift_jis>"), Some(SHIFT_JIS));
+        assert_eq!(prescan(b"<a title='<meta charset=windows-1251>'><meta charset=shift_jis>"), Some(SHIFT_JIS));
+    }
+
+    #[test]
+    fn utf16_and_x_user_defined_are_adjusted() {
+        assert_eq!(prescan(b"<meta charset=utf-16le>"), Some(UTF_8));
+        assert_eq!(prescan(b"<meta charset=x-user-defined>"), Some(WINDOWS_1252));
+    }
+
+    #[test]
+    fn extract_from_content_attribute() {
+        assert_eq!(extract_from_content(b"text/html; charset = \"shift_jis\""), Some(SHIFT_JIS));
+        assert_eq!(extract_from_content(b"text/html;charset=windows-1251;x"), Some(WINDOWS_1251));
+        assert_eq!(extract_from_content(b"text/html"), None);
+    }
+}
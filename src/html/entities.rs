@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// "CounterClockwiseContourIntegral;" is the longest name in the table
+const LONGEST_NAME: usize = 32;
+
+// entity names without the leading '&', e.g. "amp;" and the legacy "amp"
+fn named_table() -> &'static HashMap<&'static str, &'static str> {
+    static TABLE: OnceLock<HashMap<&'static str, &'static str>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        ::entities::ENTITIES.iter()
+            .map(|entity| (&entity.entity[1..], entity.characters))
+            .collect()
+    })
+}
+
+// finds the longest entity name at the start of input,
+// returns its length in chars and the characters it stands for
+pub fn longest_match(input: &[char]) -> Option<(usize, &'static str)> {
+    let mut name = String::new();
+    let mut longest = None;
+    for &c in input.iter().take(LONGEST_NAME) {
+        if !c.is_ascii_alphanumeric() && c != ';' {
+            break;
+        }
+        name.push(c);
+        if let Some(characters) = named_table().get(name.as_str()) {
+            longest = Some((name.len(), *characters));
+        }
+        if c == ';' {
+            break;
+        }
+    }
+    longest
+}
+
+// https://html.spec.whatwg.org/multipage/parsing.html#numeric-character-reference-end-state
+pub fn numeric_reference(code: u32) -> char {
+    match code {
+        0 | 0xD800..=0xDFFF => '\u{FFFD}',
+        0x80..=0x9F => c1_replacement(code).unwrap_or_else(|| char::from_u32(code).unwrap()),
+        _ => char::from_u32(code).unwrap_or('\u{FFFD}'),
+    }
+}
+
+// windows-1252 characters that legacy pages refer to by their C1 control code
+fn c1_replacement(code: u32) -> Option<char> {
+    let c = match code {
+        0x80 => '\u{20AC}',
+        0x82 => '\u{201A}',
+        0x83 => '\u{0192}',
+        0x84 => '\u{201E}',
+        0x85 => '\u{2026}',
+        0x86 => '\u{2020}',
+        0x87 => '\u{2021}',
+        0x88 => '\u{02C6}',
+        0x89 => '\u{2030}',
+        0x8A => '\u{0160}',
+        0x8B => '\u{2039}',
+        0x8C => '\u{0152}',
+        0x8E => '\u{017D}',
+        0x91 => '\u{2018}',
+        0x92 => '\u{2019}',
+        0x93 => '\u{201C}',
+        0x94 => '\u{201D}',
+        0x95 => '\u{2022}',
+        0x96 => '\u{2013}',
+        0x97 => '\u{2014}',
+        0x98 => '\u{02DC}',
+        0x99 => '\u{2122}',
+        0x9A => '\u{0161}',
+        0x9B => '\u{203A}',
+        0x9C => '\u{0153}',
+        0x9E => '\u{017E}',
+        0x9F => '\u{0178}',
+        _ => return None,
+    };
+    Some(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn longest(input: &str) -> Option<(usize, &'static str)> {
+        longest_match(&input.chars().collect::<Vec<char>>())
+    }
+
+    #[test]
+    fn longest_name_wins() {
+        assert_eq!(longest("notin; x"), Some((6, "\u{2209}")));
+        // the legacy name without a semicolon is a prefix of the longer one
+        assert_eq!(longest("notit;"), Some((3, "\u{AC}")));
+        assert_eq!(longest("CounterClockwiseContourIntegral;"), Some((32, "\u{2233}")));
+        assert_eq!(longest("zzz;"), None);
+        assert_eq!(longest("amp"), Some((3, "&")));
+    }
+
+    #[test]
+    fn numeric_references() {
+        assert_eq!(numeric_reference(0x41), 'A');
+        assert_eq!(numeric_reference(0x1F600), '\u{1F600}');
+        assert_eq!(numeric_reference(0), '\u{FFFD}');
+        assert_eq!(numeric_reference(0xD800), '\u{FFFD}');
+        assert_eq!(numeric_reference(0x110000), '\u{FFFD}');
+        assert_eq!(numeric_reference(0x80), '\u{20AC}');
+        // C1 codes without a windows-1252 character stay as they are
+        assert_eq!(numeric_reference(0x81), '\u{81}');
+    }
+}
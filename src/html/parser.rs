@@ -1,175 +1,427 @@
-use super::dom;
-use std::collections::HashMap;
-
-use nom::{
-    branch::alt,
-    bytes::complete::{tag, take_until, take_while, take_while1},
-    character::complete::{alpha1, char, multispace0, multispace1, space0},
-    combinator::{opt, recognize, verify},
-    multi::{many0, fold_many0},
-    sequence::{delimited, pair, preceded, tuple},
-    IResult,
-};
-
-pub fn build_dom_tree(html_input: String) -> dom::Node {
-    let result: IResult<&str, Vec<dom::Node>> = many0(parse_node)(&html_input);
-
-    match result {
-        Ok((_, mut nodes)) => {
-            if nodes.len() == 1 {
-                nodes.remove(0)
-            } else {
-                dom::Node::element("root".to_string(), HashMap::new(), nodes)
-            }
-        },
-        Err(e) => {
-            eprintln!("Detailed Parse Error: {:?}", e);
-            dom::Node::text("Parse Error".to_string())
+use encoding_rs::{CoderResult, Decoder, Encoding};
+
+use super::diagnostics::ParseError;
+use super::dom::{self, DomError, NodeId};
+use super::encoding::{self, Confidence};
+use super::tokenizer::{Tokenizer, Token};
+use super::tree_builder::TreeBuilder;
+
+pub fn build_dom_tree(html_input: String) -> dom::Document {
+    build_dom_tree_with_errors(html_input).0
+}
+
+// the errors are sorted by where they occur in the input
+pub fn build_dom_tree_with_errors(html_input: String) -> (dom::Document, Vec<ParseError>) {
+    run(Tokenizer::new(&html_input), TreeBuilder::new())
+}
+
+// the encoding comes from a BOM, a <meta> or the fallback, and the
+// document records the one that was used; locations refer to the decoded text
+pub fn build_dom_tree_from_bytes(bytes: &[u8], fallback: &'static Encoding) -> (dom::Document, Vec<ParseError>) {
+    let mut parser = StreamingParser::new(fallback);
+    parser.feed(bytes);
+    parser.finish()
+}
+
+// the nodes of the fragment end up under the document element of the result;
+// only an element can be the context
+pub fn build_fragment(html_input: String, context: dom::NodeRef) -> Result<dom::Document, DomError> {
+    let elem = context.as_element().ok_or(DomError::InvalidNodeType)?;
+    let in_form = std::iter::successors(Some(context), |node| node.parent())
+        .any(|node| node.as_element().is_some_and(|elem| elem.tag == "form"));
+    Ok(run(Tokenizer::new(&html_input), TreeBuilder::new_fragment(elem, context.doc.mode, in_form)).0)
+}
+
+fn run(mut tokenizer: Tokenizer, mut builder: TreeBuilder) -> (dom::Document, Vec<ParseError>) {
+    pump(&mut tokenizer, &mut builder);
+    complete(tokenizer, builder)
+}
+
+// hands tokens to the tree builder until the tokenizer waits for input or the
+// page asks for another encoding; true once the end of file has been processed
+fn pump(tokenizer: &mut Tokenizer, builder: &mut TreeBuilder) -> bool {
+    loop {
+        if let Some(state) = builder.take_tokenizer_state() {
+            tokenizer.set_state(state);
         }
+        tokenizer.set_cdata_allowed(builder.in_foreign_content());
+        let Some((token, span)) = tokenizer.next_token() else {
+            return false;
+        };
+        let eof = token == Token::Eof;
+        builder.process_token(token, span);
+        if eof { return true; }
+        if builder.needs_restart() { return false; }
     }
 }
 
+fn complete(mut tokenizer: Tokenizer, mut builder: TreeBuilder) -> (dom::Document, Vec<ParseError>) {
+    let mut errors = tokenizer.take_errors();
+    errors.extend(builder.take_errors());
+    errors.sort_by_key(|error| error.location.offset);
+    (builder.finish(), errors)
+}
+
 // ---------------------
-// parse attributes
+// streaming
 // ---------------------
 
-fn parse_attributes(input: &str) -> IResult<&str, HashMap<String, String>> {
-    // class="foo" id="bar" ...
-    fold_many0(
-        preceded(multispace1, parse_attribute),
-        HashMap::new,
-        |mut acc: HashMap<String, String>, (k, v)| {
-            acc.insert(k, v);
-            acc
+// a push parser: bytes go in as they arrive and the tree grows with them,
+// so the part that is already there can be styled and painted early
+pub struct StreamingParser {
+    fallback: &'static Encoding,
+    // everything fed so far, in case the page asks for another encoding
+    bytes: Vec<u8>,
+    // none until enough bytes have arrived to pick an encoding
+    decoder: Option<Decoder>,
+    tokenizer: Tokenizer,
+    builder: TreeBuilder,
+    closed: bool,
+}
+
+impl StreamingParser {
+    pub fn new(fallback: &'static Encoding) -> Self {
+        StreamingParser {
+            fallback,
+            bytes: Vec::new(),
+            decoder: None,
+            tokenizer: Tokenizer::new_streaming(),
+            builder: TreeBuilder::new(),
+            closed: false,
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) {
+        self.bytes.extend_from_slice(chunk);
+        match &mut self.decoder {
+            Some(decoder) => {
+                let html_input = decode(decoder, chunk, false);
+                self.tokenizer.feed(&html_input);
+            }
+            // the prescan looks at the first 1024 bytes, unless there is a BOM
+            None if self.bytes.len() >= 1024 || Encoding::for_bom(&self.bytes).is_some() => self.start(),
+            None => return,
+        }
+        self.pump();
+    }
+
+    // the tree as far as the input has been parsed
+    pub fn document(&self) -> &dom::Document {
+        self.builder.document()
+    }
+
+    pub fn finish(mut self) -> (dom::Document, Vec<ParseError>) {
+        self.closed = true;
+        match &mut self.decoder {
+            Some(decoder) => {
+                let html_input = decode(decoder, &[], true);
+                self.tokenizer.feed(&html_input);
+                self.tokenizer.close();
+            }
+            None => self.start(),
         }
-    )(input)
+        self.pump();
+        complete(self.tokenizer, self.builder)
+    }
+
+    fn start(&mut self) {
+        let (encoding, confidence) = encoding::sniff(&self.bytes, self.fallback);
+        self.begin(encoding, confidence);
+    }
+
+    // parses everything fed so far from the start
+    fn begin(&mut self, encoding: &'static Encoding, confidence: Confidence) {
+        self.tokenizer = Tokenizer::new_streaming();
+        self.builder = TreeBuilder::new();
+        self.builder.set_encoding(encoding, confidence);
+
+        let mut decoder = encoding.new_decoder_with_bom_removal();
+        let html_input = decode(&mut decoder, &self.bytes, self.closed);
+        self.tokenizer.feed(&html_input);
+        if self.closed {
+            self.tokenizer.close();
+        }
+        self.decoder = Some(decoder);
+    }
+
+    fn pump(&mut self) {
+        while !pump(&mut self.tokenizer, &mut self.builder) && self.builder.needs_restart() {
+            // a <meta> disagreed with the guess, start over with what it declared
+            let encoding = self.builder.document().encoding;
+            self.begin(encoding, Confidence::Certain);
+        }
+    }
 }
 
-fn parse_attribute(input: &str) -> IResult<&str, (String, String)> {
-    // key="value" ...
-    let (input, key) = parse_identifier(input)?;
-    let (input, _) = space0(input)?;
-    let (input, val) = opt(preceded(
-        tuple((char('='), space0)), 
-        alt((
-            delimited(char('"'), take_until("\""), char('"')),
-            delimited(char('\''), take_until("'"), char('\'')),
-        ))
-    ))(input)?;
-
-    // or true as default value
-    let value = val.unwrap_or("").to_string();
-    
-    Ok((input, (key.to_string(), value)))
+// a chunk may end inside a multi-byte sequence; the decoder keeps that part for the next one
+fn decode(decoder: &mut Decoder, bytes: &[u8], last: bool) -> String {
+    let mut html_input = String::new();
+    let mut rest = bytes;
+    loop {
+        html_input.reserve(decoder.max_utf8_buffer_length(rest.len()).unwrap_or(rest.len()));
+        let (result, read, _) = decoder.decode_to_string(rest, &mut html_input, last);
+        rest = &rest[read..];
+        if result == CoderResult::InputEmpty {
+            return html_input;
+        }
+    }
+}
+
+impl dom::Document {
+    // replaces the children of an element with the parsed markup, like innerHTML;
+    // for a template it is the contents that are replaced
+    pub fn set_inner_html(&mut self, id: NodeId, html_input: String) -> Result<(), DomError> {
+        let fragment = build_fragment(html_input, self.get(id))?;
+        let root = fragment.document_element().ok_or(DomError::NotFound)?;
+        let id = self.node(id).template_contents().unwrap_or(id);
+
+        // everything is copied over and checked before the old children go,
+        // so a failure leaves the element as it was
+        let nodes: Vec<NodeId> = fragment.children(root)
+            .map(|child| self.import_node(&fragment, child, true))
+            .collect();
+        for &node in &nodes {
+            self.ensure_insertable(id, node)?;
+        }
+
+        let children: Vec<NodeId> = self.children(id).collect();
+        for child in children {
+            self.remove_child(id, child)?;
+        }
+        for node in nodes {
+            self.append_child(id, node)?;
+        }
+        Ok(())
+    }
 }
 
 // ---------------------
-// parse tags
+// assistant functions
 // ---------------------
 
-fn is_void_tag(tag_name: &str) -> bool {
-    matches!(tag_name.to_lowercase().as_str(), 
-        "area"   |
-        "base"   |
-        "br"     |
-        "col"    |
-        "embed"  |
-        "hr"     |
-        "img"    |
-        "input"  | 
-        "link"   |
-        "meta"   |
-        "param"  |
-        "source" |
-        "track"  |
+pub fn is_void_tag(tag_name: &str) -> bool {
+    matches!(tag_name.to_lowercase().as_str(),
+        "area"     |
+        "base"     |
+        "basefont" |
+        "bgsound"  |
+        "br"       |
+        "col"      |
+        "embed"    |
+        "frame"    |
+        "hr"       |
+        "img"      |
+        "input"    |
+        "keygen"   |
+        "link"     |
+        "meta"     |
+        "param"    |
+        "source"   |
+        "track"    |
         "wbr"
     )
 }
 
-fn parse_open_tag(input: &str) -> IResult<&str, (String, HashMap<String, String>, bool)> {
-    let (input, _) = char('<')(input)?;
-    let (input, tag_name) = parse_identifier(input)?;
-    let (input, attrs) = parse_attributes(input)?;
-    let (input, _) = multispace0(input)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::diagnostics::Location;
 
-    let (input, self_closing) = opt(char('/'))(input)?; 
-    let (input, _) = char('>')(input)?;
+    // the fragment parsed in the context of the first element with that tag
+    fn fragment(html_input: &str, context_html: &str, context_tag: &str) -> String {
+        let document = build_dom_tree(context_html.to_string());
+        let context = document.descendants(document.root())
+            .find(|&id| document.node(id).as_element().is_some_and(|elem| elem.tag == context_tag))
+            .unwrap();
+        let fragment = build_fragment(html_input.to_string(), document.get(context)).unwrap();
+        fragment.get(fragment.document_element().unwrap()).inner_html()
+    }
 
-    let is_void = is_void_tag(tag_name) || self_closing.is_some();
+    #[test]
+    fn fragment_in_body() {
+        assert_eq!(fragment("<p>a<b>b</p>c", "<div></div>", "div"), "<p>a<b>b</b></p><b>c</b>");
+    }
 
-    Ok((input, (tag_name.to_string(), attrs, is_void)))
-}
+    #[test]
+    fn fragment_in_table_contexts() {
+        assert_eq!(fragment("<td>a", "<table><tr></tr></table>", "tr"), "<td>a</td>");
+        assert_eq!(fragment("<tr><td>a", "<table></table>", "table"), "<tbody><tr><td>a</td></tr></tbody>");
+    }
 
-fn parse_close_tag(expected_name: String) -> impl FnMut(&str) -> IResult<&str, &str> {
-    move |input: &str| {
-        let (input, _) = tag("</")(input)?;
-        let (input, name) = verify(parse_identifier, |s: &str| s == expected_name)(input)?;
-        let (input, _) = preceded(multispace0, char('>'))(input)?;
-        Ok((input, name))
+    #[test]
+    fn fragment_in_raw_text_contexts() {
+        assert_eq!(fragment("<b>&amp;</b>", "<textarea></textarea>", "textarea"), "&lt;b&gt;&amp;&lt;/b&gt;");
+        assert_eq!(fragment("a<b>", "<script></script>", "script"), "a&lt;b&gt;");
     }
-}
 
-// ---------------------
-// parse text
-// ---------------------
+    #[test]
+    fn fragment_in_template() {
+        assert_eq!(fragment("<td>a</td><td>b", "<template></template>", "template"), "<td>a</td><td>b</td>");
+        assert_eq!(fragment("<col><p>", "<template></template>", "template"), "<col>");
+    }
 
-fn parse_text(input: &str) -> IResult<&str, dom::Node> {
-    let (input, text) = take_while1(|c| c != '<')(input)?;
-    Ok((input, dom::Node::text(text.to_string())))
-}
+    #[test]
+    fn fragment_inside_a_form_drops_nested_forms() {
+        assert_eq!(fragment("<form><input></form>", "<form><div></div></form>", "div"), "<input>");
+    }
 
-fn parse_comment(input: &str) -> IResult<&str, dom::Node> {
-    let (input, content) = tag("")(input)?;
-    let (input, _) = tag("-->")(input)?;
-    Ok((input, dom::Node::comment(content.to_string())))
-}
+    #[test]
+    fn fragment_needs_an_element() {
+        let document = build_dom_tree("<p>text</p>".to_string());
+        let text = document.descendants(document.root())
+            .find(|&id| matches!(document.node(id).node_type, dom::NodeType::Text(_)))
+            .unwrap();
+        assert_eq!(build_fragment("<b>".to_string(), document.get(text)).err(), Some(DomError::InvalidNodeType));
+        assert_eq!(build_fragment("<b>".to_string(), document.get(document.root())).err(), Some(DomError::InvalidNodeType));
+    }
 
-// ---------------------
-// parse elements
-// ---------------------
+    // the text of the body, after parsing the bytes in chunks of the given size
+    fn streamed_body(bytes: &[u8], chunk: usize, fallback: &'static Encoding) -> (String, &'static Encoding) {
+        let mut parser = StreamingParser::new(fallback);
+        for part in bytes.chunks(chunk) {
+            parser.feed(part);
+        }
+        let (document, _) = parser.finish();
+        let body = document.get(document.document_element().unwrap()).last_child().unwrap();
+        let text = body.descendants()
+            .filter_map(|node| match node.node_type() {
+                dom::NodeType::Text(text) => Some(text.clone()),
+                _ => None,
+            })
+            .collect();
+        (text, document.encoding)
+    }
 
-fn parse_element(input: &str) -> IResult<&str, dom::Node> {
-    let (input, (tag_name, attrs, is_void)) = parse_open_tag(input)?;
+    // a <meta> past the first 1024 bytes is only seen by the tree builder,
+    // which starts over with the encoding it declares
+    fn late_meta(charset: &str) -> Vec<u8> {
+        let mut bytes = format!("<head><!--{}--><meta charset={}></head><body>", "a".repeat(1100), charset).into_bytes();
+        bytes.extend_from_slice(b"\xC0\xE9</body>");
+        bytes
+    }
 
-    if is_void {
-        // void_tag no children
-        return Ok((input, dom::Node::element(tag_name, attrs, Vec::new())));
+    #[test]
+    fn restart_on_late_meta() {
+        for chunk in [1, 7, 512, 4096] {
+            let (text, encoding) = streamed_body(&late_meta("windows-1251"), chunk, encoding_rs::UTF_8);
+            assert_eq!(encoding, encoding_rs::WINDOWS_1251);
+            assert_eq!(text, "\u{410}\u{439}", "chunks of {}", chunk);
+        }
+        let (document, _) = build_dom_tree_from_bytes(&late_meta("windows-1251"), encoding_rs::UTF_8);
+        assert_eq!(document.encoding, encoding_rs::WINDOWS_1251);
     }
 
-    // many0 to repeat until fail
-    let (input, children) = many0(parse_node)(input)?;
+    #[test]
+    fn no_restart_when_meta_agrees() {
+        let (text, encoding) = streamed_body(&late_meta("windows-1252"), 100, encoding_rs::WINDOWS_1252);
+        assert_eq!(encoding, encoding_rs::WINDOWS_1252);
+        assert_eq!(text, "\u{c0}\u{e9}");
+    }
 
-    let (input, _) = multispace0(input)?;
-    let (input, _) = parse_close_tag(tag_name.clone())(input)?;
+    // a BOM is certain, so the <meta> is not followed
+    #[test]
+    fn no_restart_after_bom() {
+        let mut bytes = b"\xEF\xBB\xBF".to_vec();
+        bytes.extend(late_meta("windows-1251"));
+        let (text, encoding) = streamed_body(&bytes, 100, encoding_rs::WINDOWS_1252);
+        assert_eq!(encoding, encoding_rs::UTF_8);
+        assert_eq!(text, "\u{FFFD}\u{FFFD}");
+    }
 
-    Ok((input, dom::Node::element(tag_name, attrs, children)))
-}
+    #[test]
+    fn set_inner_html() {
+        let mut document = build_dom_tree("<div id=a><span>old</span></div>".to_string());
+        let div = document.get_element_by_id("a").unwrap();
+        document.set_inner_html(div, "<i>new</i> text".to_string()).unwrap();
+        assert_eq!(document.get(div).outer_html(), "<div id=\"a\"><i>new</i> text</div>");
+    }
 
-fn parse_node(input: &str) -> IResult<&str, dom::Node> {
-    // consum whitespaces
-    let (input, _) = multispace0(input)?; 
-    
-    if input.is_empty() {
-        return Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Eof)));
+    #[test]
+    fn set_inner_html_on_a_template_fills_its_contents() {
+        let mut document = build_dom_tree("<template id=t><b>old</b></template>".to_string());
+        let template = document.get_element_by_id("t").unwrap();
+        document.set_inner_html(template, "<tr><td>new".to_string()).unwrap();
+        assert_eq!(document.get(template).children().count(), 0);
+        assert_eq!(document.get(template).inner_html(), "<tr><td>new</td></tr>");
     }
 
-    alt((
-        parse_comment,
-        parse_element,
-        parse_text,
-    ))(input)
-}
+    #[test]
+    fn set_inner_html_on_text_keeps_the_tree() {
+        let mut document = build_dom_tree("<p>text</p>".to_string());
+        let text = document.descendants(document.root())
+            .find(|&id| matches!(document.node(id).node_type, dom::NodeType::Text(_)))
+            .unwrap();
+        let before = document.get(document.root()).outer_html();
+        assert_eq!(document.set_inner_html(text, "<b>".to_string()), Err(DomError::InvalidNodeType));
+        assert_eq!(document.get(document.root()).outer_html(), before);
+    }
 
-// ---------------------
-// assistant functions
-// ---------------------
+    #[test]
+    fn errors_are_located_and_sorted() {
+        let (_, errors) = build_dom_tree_with_errors("<!DOCTYPE html><p>\n</x>é<b a=1 a=2>\r\n</i>".to_string());
+        let located: Vec<_> = errors.iter().map(|error| (error.code, error.location.to_string())).collect();
+        assert_eq!(
+            located,
+            [
+                ("unexpected-end-tag", "2:1"),
+                ("duplicate-attribute", "2:16"),
+                ("unexpected-end-tag", "3:1"),
+                ("eof-in-element", "3:5"),
+            ].map(|(code, location)| (code, location.to_string()))
+        );
+        assert!(errors.is_sorted_by_key(|error| error.location.offset));
+    }
+
+    #[test]
+    fn nodes_keep_their_source_spans() {
+        let document = build_dom_tree("<!DOCTYPE html>\n<p class=a>one\ntwo</p>".to_string());
+        let p = document.query_selector("p").unwrap();
+        let span = document.node(p).span.unwrap();
+        assert_eq!((span.start, span.end.offset), (Location { offset: 16, line: 2, column: 1 }, 27));
+        let text = document.node(p).first_child().unwrap();
+        let span = document.node(text).span.unwrap();
+        // the characters are merged into one node
+        assert_eq!((span.start.to_string(), span.end.to_string()), ("2:12".to_string(), "3:4".to_string()));
+        // an implied element gets the span of the token that implied it
+        assert_eq!(document.node(document.query_selector("body").unwrap()).span, document.node(p).span);
+    }
+
+    #[test]
+    fn the_tree_grows_as_bytes_arrive() {
+        let mut parser = StreamingParser::new(encoding_rs::UTF_8);
+        // a BOM settles the encoding, so parsing starts right away
+        parser.feed(b"\xEF\xBB\xBF<!DOCTYPE html><ul>");
+        let items = |parser: &StreamingParser| parser.document().query_selector_all("li").len();
+        assert_eq!(items(&parser), 0);
+        for _ in 0..20 {
+            parser.feed("<li>caf\u{e9}</li>".as_bytes());
+        }
+        let early = items(&parser);
+        assert!(early > 0 && early < 20, "{} items", early);
+        let (document, errors) = parser.finish();
+        assert_eq!(document.query_selector_all("li").len(), 20);
+        assert_eq!(errors.iter().map(|error| error.code).collect::<Vec<_>>(), ["eof-in-element"]);
+    }
 
-fn parse_identifier(input: &str) -> IResult<&str, &str> {
-    // div, class, data-id, ...
-    recognize(
-        pair(
-            alpha1,
-            take_while(|c: char| c.is_alphanumeric() || c == '-' || c == '_')
-        )
-    )(input)
+    #[test]
+    fn chunks_make_no_difference() {
+        let html = "<!DOCTYPE html><meta charset=utf-8><title>\u{3b1}\u{3b2}</title>\
+                    <p class=x>caf\u{e9} &amp; \u{1f600}<b>\r\n</p>";
+        let (whole, whole_errors) = build_dom_tree_from_bytes(html.as_bytes(), encoding_rs::WINDOWS_1252);
+        for chunk in [1, 2, 3, 13, 1024] {
+            let mut parser = StreamingParser::new(encoding_rs::WINDOWS_1252);
+            for part in html.as_bytes().chunks(chunk) {
+                parser.feed(part);
+            }
+            let (document, errors) = parser.finish();
+            assert_eq!(
+                document.get(document.root()).outer_html(),
+                whole.get(whole.root()).outer_html(),
+                "chunks of {}", chunk
+            );
+            assert_eq!(errors, whole_errors, "chunks of {}", chunk);
+        }
+        assert_eq!(whole.encoding, encoding_rs::UTF_8);
+        assert!(whole.get(whole.root()).outer_html().contains("caf\u{e9} &amp; \u{1f600}"));
+    }
 }
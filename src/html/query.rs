@@ -0,0 +1,132 @@
+use super::dom::{Document, NodeId, NodeRef};
+use crate::css::parser::CssParser;
+use crate::css::style;
+
+// https://dom.spec.whatwg.org/#scope-match-a-selectors-string
+impl<'a> NodeRef<'a> {
+    pub fn query_selector(&self, selectors: &str) -> Option<NodeRef<'a>> {
+        // an invalid selector matches nothing
+        let selectors = CssParser::parse_selector_list(selectors.to_string()).unwrap_or_default();
+        self.descendants()
+            .find(|&node| selectors.iter().any(|selector| style::matches(node, selector)))
+    }
+
+    pub fn query_selector_all(&self, selectors: &str) -> Vec<NodeRef<'a>> {
+        // an invalid selector matches nothing
+        let selectors = CssParser::parse_selector_list(selectors.to_string()).unwrap_or_default();
+        self.descendants()
+            .filter(|&node| selectors.iter().any(|selector| style::matches(node, selector)))
+            .collect()
+    }
+
+    // elements carrying every class of the space-separated list
+    pub fn get_elements_by_class_name(&self, class_names: &str) -> Vec<NodeRef<'a>> {
+        let wanted: Vec<&str> = class_names.split_ascii_whitespace().collect();
+        if wanted.is_empty() {
+            return Vec::new();
+        }
+        self.descendants()
+            .filter(|node| {
+                let classes: Vec<&str> = node.as_element()
+                    .and_then(|elem| elem.attributes.get("class"))
+                    .map(|s| s.split_ascii_whitespace().collect())
+                    .unwrap_or_default();
+                !classes.is_empty() && wanted.iter().all(|class| classes.contains(class))
+            })
+            .collect()
+    }
+}
+
+impl Document {
+    pub fn query_selector(&self, selectors: &str) -> Option<NodeId> {
+        self.get(self.root()).query_selector(selectors).map(|node| node.id)
+    }
+
+    pub fn query_selector_all(&self, selectors: &str) -> Vec<NodeId> {
+        self.get(self.root()).query_selector_all(selectors).iter().map(|node| node.id).collect()
+    }
+
+    pub fn get_element_by_id(&self, id: &str) -> Option<NodeId> {
+        if id.is_empty() {
+            return None;
+        }
+        self.descendants(self.root())
+            .find(|&node| self.node(node).as_element().and_then(|elem| elem.attributes.get("id")).is_some_and(|v| v == id))
+    }
+
+    pub fn get_elements_by_class_name(&self, class_names: &str) -> Vec<NodeId> {
+        self.get(self.root()).get_elements_by_class_name(class_names).iter().map(|node| node.id).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::dom::NodeType;
+    use crate::html::parse_html;
+
+    const PAGE: &str = concat!(
+        r#"<div id="main" class="box wide"><p class="intro">a</p><p id="main">b</p></div>"#,
+        r#"<section class="box"><p>c</p></section>"#,
+    );
+
+    // the text each of the nodes contains
+    fn text_of(document: &Document, ids: Vec<NodeId>) -> Vec<String> {
+        ids.into_iter()
+            .map(|id| {
+                document.descendants(id)
+                    .filter_map(|n| match &document.node(n).node_type {
+                        NodeType::Text(text) => Some(text.as_str()),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn first_match_in_document_order() {
+        let document = parse_html(PAGE.to_string());
+        assert_eq!(text_of(&document, document.query_selector("p").into_iter().collect()), ["a"]);
+        assert_eq!(text_of(&document, document.query_selector("section p, .intro").into_iter().collect()), ["a"]);
+        assert_eq!(document.query_selector("table"), None);
+    }
+
+    #[test]
+    fn all_matches_in_document_order_once() {
+        let document = parse_html(PAGE.to_string());
+        assert_eq!(text_of(&document, document.query_selector_all("section p, div > p, p")), ["a", "b", "c"]);
+        assert_eq!(document.query_selector_all(".box").len(), 2);
+    }
+
+    #[test]
+    fn invalid_selectors_match_nothing() {
+        let document = parse_html(PAGE.to_string());
+        assert_eq!(document.query_selector("p:nope"), None);
+        assert!(document.query_selector_all("p, [").is_empty());
+    }
+
+    #[test]
+    fn scoped_to_descendants_but_matched_against_the_whole_tree() {
+        let document = parse_html(PAGE.to_string());
+        let div = document.get(document.query_selector("div").unwrap());
+        let ids = |nodes: Vec<NodeRef>| nodes.iter().map(|node| node.id).collect();
+        assert_eq!(text_of(&document, ids(div.query_selector_all("p"))), ["a", "b"]);
+        assert!(div.query_selector("div").is_none());
+        assert_eq!(text_of(&document, ids(div.query_selector_all("body div p"))), ["a", "b"]);
+    }
+
+    #[test]
+    fn by_id_and_class_name() {
+        let document = parse_html(PAGE.to_string());
+        let main = document.get_element_by_id("main").unwrap();
+        assert_eq!(document.node(main).as_element().unwrap().tag, "div");
+        assert_eq!(document.get_element_by_id(""), None);
+        assert_eq!(document.get_element_by_id("Main"), None);
+
+        assert_eq!(document.get_elements_by_class_name("box").len(), 2);
+        assert_eq!(document.get_elements_by_class_name("  wide box ").len(), 1);
+        assert!(document.get_elements_by_class_name("box missing").is_empty());
+        assert!(document.get_elements_by_class_name(" ").is_empty());
+    }
+}
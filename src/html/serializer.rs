@@ -0,0 +1,258 @@
+use super::dom::{NodeRef, NodeType};
+use super::parser::is_void_tag;
+
+// https://html.spec.whatwg.org/multipage/parsing.html#serialising-html-fragments
+impl NodeRef<'_> {
+    pub fn outer_html(&self) -> String {
+        let mut out = String::new();
+        serialize_node(*self, &mut out);
+        out
+    }
+
+    pub fn inner_html(&self) -> String {
+        let mut out = String::new();
+        serialize_children(*self, &mut out);
+        out
+    }
+}
+
+fn serialize_children(node: NodeRef, out: &mut String) {
+    if node.as_element().is_some_and(|elem| elem.is_html() && is_void_tag(&elem.tag)) {
+        return;
+    }
+    // what a template holds lives in its contents, not among its children
+    let node = node.template_contents().unwrap_or(node);
+    for child in node.children() {
+        serialize_node(child, out);
+    }
+}
+
+fn serialize_node(node: NodeRef, out: &mut String) {
+    match node.node_type() {
+        NodeType::Document | NodeType::DocumentFragment => serialize_children(node, out),
+        NodeType::Element(elem) => {
+            out.push('<');
+            out.push_str(&elem.tag);
+            // attribute order is not kept by the DOM, sort it for stable output
+            let mut attributes: Vec<_> = elem.attributes.iter().collect();
+            attributes.sort();
+            for (name, value) in attributes {
+                out.push(' ');
+                out.push_str(name);
+                out.push_str("=\"");
+                escape(value, true, out);
+                out.push('"');
+            }
+            out.push('>');
+            if !elem.is_html() {
+                serialize_children(node, out);
+                out.push_str("</");
+                out.push_str(&elem.tag);
+                out.push('>');
+                return;
+            }
+            if is_void_tag(&elem.tag) {
+                return;
+            }
+
+            // the parser drops a newline right after these start tags
+            if matches!(elem.tag.as_str(), "pre" | "textarea" | "listing")
+                && let Some(NodeType::Text(text)) = node.first_child().map(|child| child.node_type())
+                && text.starts_with('\n')
+            {
+                out.push('\n');
+            }
+            serialize_children(node, out);
+            out.push_str("</");
+            out.push_str(&elem.tag);
+            out.push('>');
+        }
+        NodeType::Text(text) => {
+            let raw = node.parent()
+                .and_then(|parent| parent.as_element())
+                .is_some_and(|elem| elem.is_html() && is_raw_text(&elem.tag));
+            if raw {
+                out.push_str(text);
+            } else {
+                escape(text, false, out);
+            }
+        }
+        NodeType::Comment(data) => {
+            out.push_str("<!--");
+            out.push_str(data);
+            out.push_str("-->");
+        }
+        NodeType::Doctype(doctype) => {
+            out.push_str("<!DOCTYPE ");
+            out.push_str(&doctype.name);
+            // the identifiers decide the quirks mode, so keep them when present
+            if !doctype.public_id.is_empty() {
+                out.push_str(&format!(" PUBLIC \"{}\"", doctype.public_id));
+                if !doctype.system_id.is_empty() {
+                    out.push_str(&format!(" \"{}\"", doctype.system_id));
+                }
+            } else if !doctype.system_id.is_empty() {
+                out.push_str(&format!(" SYSTEM \"{}\"", doctype.system_id));
+            }
+            out.push('>');
+        }
+    }
+}
+
+// https://html.spec.whatwg.org/multipage/parsing.html#escapingString
+fn escape(text: &str, attribute_mode: bool, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '\u{a0}' => out.push_str("&nbsp;"),
+            '"' if attribute_mode => out.push_str("&quot;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+// text inside these elements is written out verbatim
+fn is_raw_text(tag_name: &str) -> bool {
+    matches!(tag_name,
+        "style"    |
+        "script"   |
+        "xmp"      |
+        "iframe"   |
+        "noembed"  |
+        "noframes" |
+        "plaintext"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::html::dom::{Document, NodeRef};
+    use crate::html::parse_html;
+
+    fn same_tree(a: NodeRef, b: NodeRef) -> bool {
+        let same_contents = match (a.template_contents(), b.template_contents()) {
+            (Some(a), Some(b)) => same_tree(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        a.node_type() == b.node_type()
+            && a.children().count() == b.children().count()
+            && a.children().zip(b.children()).all(|(a, b)| same_tree(a, b))
+            && same_contents
+    }
+
+    // parse, serialize, parse again: the two trees must be the same
+    fn round_trip(html_input: &str) -> Document {
+        let document = parse_html(html_input.to_string());
+        let serialized = document.get(document.root()).outer_html();
+        let reparsed = parse_html(serialized.clone());
+        assert!(
+            same_tree(document.get(document.root()), reparsed.get(reparsed.root())),
+            "{:?} was written as {:?}, which parses as {:?}",
+            html_input, serialized, reparsed.get(reparsed.root()).outer_html(),
+        );
+        document
+    }
+
+    #[test]
+    fn escaped_text_and_attributes() {
+        let document = round_trip("<p title='a&amp;b \"c\" &lt;d&gt;&nbsp;'>a &amp; b &lt; c &gt; d&nbsp;e \"q\" 'r'</p>");
+        let html = document.get(document.root()).outer_html();
+        assert!(html.contains("title=\"a&amp;b &quot;c&quot; &lt;d&gt;&nbsp;\""), "{}", html);
+        assert!(html.contains(">a &amp; b &lt; c &gt; d&nbsp;e \"q\" 'r'<"), "{}", html);
+    }
+
+    #[test]
+    fn void_elements() {
+        let document = round_trip(
+            "<head><base href=a><link rel=x><meta charset=utf-8></head>\
+             <body><area><br><col><embed><hr><img src=a><input value=b><param><source><track><wbr>",
+        );
+        let html = document.get(document.root()).outer_html();
+        assert!(!html.contains("</br>") && !html.contains("</img>") && !html.contains("</input>"), "{}", html);
+        let document = round_trip("<frameset><frame src=a><frame src=b></frameset>");
+        let html = document.get(document.root()).outer_html();
+        assert!(html.contains("<frame src=\"a\"><frame src=\"b\"></frameset>"), "{}", html);
+    }
+
+    #[test]
+    fn template_contents() {
+        let document = round_trip("<template><tr><td>a</td></tr></template><table><template><col></template></table>");
+        let html = document.get(document.root()).outer_html();
+        assert!(html.contains("<template><tr><td>a</td></tr></template>"), "{}", html);
+    }
+
+    #[test]
+    fn raw_text_elements() {
+        let document = round_trip(
+            "<title>a < b &amp; c</title><style>p > a { content: \"&amp;\" }</style>\
+             <script>if (a < b && c > d) { x = '</p>' }</script><textarea><b>&amp;</b></textarea>",
+        );
+        let html = document.get(document.root()).outer_html();
+        assert!(html.contains("<style>p > a { content: \"&amp;\" }</style>"), "{}", html);
+        assert!(html.contains("<script>if (a < b && c > d) { x = '</p>' }</script>"), "{}", html);
+        assert!(html.contains("<title>a &lt; b &amp; c</title>"), "{}", html);
+        assert!(html.contains("<textarea>&lt;b&gt;&amp;&lt;/b&gt;</textarea>"), "{}", html);
+    }
+
+    // the newline the parser drops after these start tags is written back
+    #[test]
+    fn leading_newlines() {
+        round_trip("<pre>\n\nx</pre><textarea>\n\ny</textarea><listing>\n\nz</listing>");
+        round_trip("<pre>\nx</pre><textarea>\ny</textarea>");
+    }
+
+    #[test]
+    fn doctypes() {
+        let document = round_trip(
+            "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01//EN\" \"http://www.w3.org/TR/html4/strict.dtd\"><p>x",
+        );
+        assert_eq!(document.mode, crate::html::dom::QuirksMode::NoQuirks);
+        round_trip("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\"><p>x");
+        round_trip("<!DOCTYPE html SYSTEM \"about:legacy-compat\"><p>x");
+        round_trip("<!doctype html><p>x");
+    }
+
+    #[test]
+    fn foreign_content() {
+        round_trip(
+            "<svg viewBox=\"0 0 10 10\"><foreignObject><p>html &amp; text</p></foreignObject>\
+             <path d=\"M0 0\"/><circle r=\"1\"></circle><title>t</title></svg>\
+             <math><mi>x</mi><annotation-xml encoding=\"text/html\"><div>y</div></annotation-xml></math>\
+             <svg><style>a &amp; b</style><![CDATA[x<y]]></svg>",
+        );
+    }
+
+    #[test]
+    fn comments() {
+        round_trip("<!-- a -- b --><p>x<!---->y</p>");
+    }
+
+    // https://en.wikipedia.org/wiki/Xorshift
+    fn pieces(seed: u64, count: usize) -> String {
+        const PIECES: [&str; 28] = [
+            "<p>", "</p>", "<div class=\"a b\">", "</div>", "<b>", "</b>", "<i>", "</i>", "<ul><li>", "<li>",
+            "</ul>", "text", " &amp; ", "&lt;", "&gt;", "&nbsp;", "\"", "<br>", "<img alt='x\"y'>",
+            "<pre>\n", "</pre>", "<textarea>\na<b></textarea>", "<script>a<b</script>", "<!--c-->",
+            "<svg><rect width=\"1\"/></svg>", "<math><mi>x</mi></math>", "<table><tr><td>c</td></tr></table>",
+            "<span title=\"&amp;&quot;\">",
+        ];
+        let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+        let mut html = String::new();
+        for _ in 0..count {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            html.push_str(PIECES[(state % PIECES.len() as u64) as usize]);
+        }
+        html
+    }
+
+    #[test]
+    fn generated_markup() {
+        for seed in 0..500 {
+            round_trip(&pieces(seed, 1 + (seed % 30) as usize));
+        }
+    }
+}
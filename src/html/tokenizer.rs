@@ -0,0 +1,1837 @@
+use std::collections::VecDeque;
+
+use super::diagnostics::{Location, ParseError, SourceMap, Span};
+use super::entities;
+
+// ---------------------
+// tokens
+// ---------------------
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Doctype(Doctype),
+    StartTag(Tag),
+    EndTag(Tag),
+    Comment(String),
+    Character(char),
+    Eof,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Tag {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    // where the value of each attribute starts, or its name if it has none
+    pub value_locations: Vec<Location>,
+    pub self_closing: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Doctype {
+    pub name: Option<String>,
+    pub public_id: Option<String>,
+    pub system_id: Option<String>,
+    pub force_quirks: bool,
+}
+
+// ---------------------
+// states
+// ---------------------
+
+// https://html.spec.whatwg.org/multipage/parsing.html#tokenization
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Data,
+    RcData,
+    RawText,
+    ScriptData,
+    PlainText,
+    TagOpen,
+    EndTagOpen,
+    TagName,
+    RcDataLessThanSign,
+    RcDataEndTagOpen,
+    RcDataEndTagName,
+    RawTextLessThanSign,
+    RawTextEndTagOpen,
+    RawTextEndTagName,
+    ScriptDataLessThanSign,
+    ScriptDataEndTagOpen,
+    ScriptDataEndTagName,
+    ScriptDataEscapeStart,
+    ScriptDataEscapeStartDash,
+    ScriptDataEscaped,
+    ScriptDataEscapedDash,
+    ScriptDataEscapedDashDash,
+    ScriptDataEscapedLessThanSign,
+    ScriptDataEscapedEndTagOpen,
+    ScriptDataEscapedEndTagName,
+    ScriptDataDoubleEscapeStart,
+    ScriptDataDoubleEscaped,
+    ScriptDataDoubleEscapedDash,
+    ScriptDataDoubleEscapedDashDash,
+    ScriptDataDoubleEscapedLessThanSign,
+    ScriptDataDoubleEscapeEnd,
+    BeforeAttributeName,
+    AttributeName,
+    AfterAttributeName,
+    BeforeAttributeValue,
+    AttributeValueDoubleQuoted,
+    AttributeValueSingleQuoted,
+    AttributeValueUnquoted,
+    AfterAttributeValueQuoted,
+    SelfClosingStartTag,
+    BogusComment,
+    MarkupDeclarationOpen,
+    CommentStart,
+    CommentStartDash,
+    Comment,
+    CommentLessThanSign,
+    CommentLessThanSignBang,
+    CommentLessThanSignBangDash,
+    CommentLessThanSignBangDashDash,
+    CommentEndDash,
+    CommentEnd,
+    CommentEndBang,
+    Doctype,
+    BeforeDoctypeName,
+    DoctypeName,
+    AfterDoctypeName,
+    AfterDoctypePublicKeyword,
+    BeforeDoctypePublicIdentifier,
+    DoctypePublicIdentifierDoubleQuoted,
+    DoctypePublicIdentifierSingleQuoted,
+    AfterDoctypePublicIdentifier,
+    BetweenDoctypePublicAndSystemIdentifiers,
+    AfterDoctypeSystemKeyword,
+    BeforeDoctypeSystemIdentifier,
+    DoctypeSystemIdentifierDoubleQuoted,
+    DoctypeSystemIdentifierSingleQuoted,
+    AfterDoctypeSystemIdentifier,
+    BogusDoctype,
+    CdataSection,
+    CdataSectionBracket,
+    CdataSectionEnd,
+    CharacterReference,
+    NamedCharacterReference,
+    AmbiguousAmpersand,
+    NumericCharacterReference,
+    HexadecimalCharacterReferenceStart,
+    DecimalCharacterReferenceStart,
+    HexadecimalCharacterReference,
+    DecimalCharacterReference,
+}
+
+// ---------------------
+// tokenizer
+// ---------------------
+
+// the furthest a single step looks ahead, enough for the longest named character reference
+const LOOKAHEAD: usize = 64;
+
+pub struct Tokenizer {
+    input: Vec<char>,
+    pos: usize,
+    last_pos: usize,
+    state: State,
+    return_state: State,
+
+    current_tag: Tag,
+    is_end_tag: bool,
+    current_comment: String,
+    current_doctype: Doctype,
+    temp_buffer: String,
+    char_ref_code: u32,
+    last_start_tag: Option<String>,
+    cdata_allowed: bool,
+
+    // tokens wait here with the char range they came from
+    pending: VecDeque<(Token, usize, usize)>,
+    token_start: usize,
+    done: bool,
+    // more input may still be fed
+    open: bool,
+
+    source_map: SourceMap,
+    errors: Vec<ParseError>,
+}
+
+impl Tokenizer {
+    pub fn new(html_input: &str) -> Self {
+        let mut tokenizer = Self::new_streaming();
+        tokenizer.feed(html_input);
+        tokenizer.close();
+        tokenizer
+    }
+
+    // starts without input, see feed() and close()
+    pub fn new_streaming() -> Self {
+        let input = Vec::new();
+        Tokenizer {
+            source_map: SourceMap::new(&input),
+            input,
+            pos: 0,
+            last_pos: 0,
+            state: State::Data,
+            return_state: State::Data,
+            current_tag: Tag::default(),
+            is_end_tag: false,
+            current_comment: String::new(),
+            current_doctype: Doctype::default(),
+            temp_buffer: String::new(),
+            char_ref_code: 0,
+            last_start_tag: None,
+            cdata_allowed: false,
+            pending: VecDeque::new(),
+            token_start: 0,
+            done: false,
+            open: true,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn feed(&mut self, html_input: &str) {
+        let from = self.input.len();
+        self.input.extend(html_input.chars());
+        self.source_map.extend(&self.input, from);
+    }
+
+    // no more input will come, so the end of what we have is the end of file
+    pub fn close(&mut self) {
+        self.open = false;
+    }
+
+    // the tree builder switches states for raw text elements
+    pub fn set_state(&mut self, state: State) {
+        self.state = state;
+    }
+
+    // CDATA sections are only recognized in foreign content
+    pub fn set_cdata_allowed(&mut self, allowed: bool) {
+        self.cdata_allowed = allowed;
+    }
+
+    // None means the tokenizer is waiting for more input
+    pub fn next_token(&mut self) -> Option<(Token, Span)> {
+        while self.pending.is_empty() {
+            if self.done {
+                let end = self.input.len();
+                return Some((Token::Eof, self.source_map.span(end, end)));
+            }
+            // a step may look ahead, so it only runs when that can't reach the end of an open input
+            if self.open && self.input.len() - self.pos < LOOKAHEAD {
+                return None;
+            }
+            self.step();
+        }
+        let (token, start, end) = self.pending.pop_front().unwrap();
+        Some((token, self.source_map.span(start, end)))
+    }
+
+    // the parse errors found so far, in input order
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    fn step(&mut self) {
+        if self.state == State::MarkupDeclarationOpen {
+            self.last_pos = self.pos;
+            self.markup_declaration_open();
+            return;
+        }
+        if self.state == State::NamedCharacterReference {
+            self.last_pos = self.pos;
+            self.named_character_reference();
+            return;
+        }
+
+        let c = self.consume_next();
+        // every token starts in one of the text states
+        if matches!(
+            self.state,
+            State::Data
+                | State::RcData
+                | State::RawText
+                | State::ScriptData
+                | State::PlainText
+                | State::ScriptDataEscaped
+                | State::ScriptDataDoubleEscaped
+                | State::CdataSection
+        ) {
+            self.token_start = self.last_pos;
+        }
+        match self.state {
+            State::Data => match c {
+                Some('&') => self.start_character_reference(),
+                Some('<') => self.state = State::TagOpen,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.emit_char('\0');
+                }
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::RcData => self.text_data(c, State::RcDataLessThanSign),
+            State::RawText => self.text_data(c, State::RawTextLessThanSign),
+            State::ScriptData => self.text_data(c, State::ScriptDataLessThanSign),
+            State::PlainText => match c {
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.emit_char('\u{FFFD}');
+                }
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+
+            // ---------------------
+            // tags
+            // ---------------------
+
+            State::TagOpen => match c {
+                Some('!') => self.state = State::MarkupDeclarationOpen,
+                Some('/') => self.state = State::EndTagOpen,
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(false);
+                    self.reconsume_in(State::TagName);
+                }
+                Some('?') => {
+                    self.error("unexpected-question-mark-instead-of-tag-name");
+                    self.current_comment.clear();
+                    self.reconsume_in(State::BogusComment);
+                }
+                None => {
+                    self.error("eof-before-tag-name");
+                    self.emit_char('<');
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.error("invalid-first-character-of-tag-name");
+                    self.emit_char('<');
+                    self.reconsume_in(State::Data);
+                }
+            },
+            State::EndTagOpen => match c {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.new_tag(true);
+                    self.reconsume_in(State::TagName);
+                }
+                Some('>') => {
+                    self.error("missing-end-tag-name");
+                    self.state = State::Data;
+                    self.token_start = self.pos;
+                }
+                None => {
+                    self.error("eof-before-tag-name");
+                    self.emit_str("</");
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.error("invalid-first-character-of-tag-name");
+                    self.current_comment.clear();
+                    self.reconsume_in(State::BogusComment);
+                }
+            },
+            State::TagName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.current_tag.name.push('\u{FFFD}');
+                }
+                Some(c) => self.current_tag.name.push(c.to_ascii_lowercase()),
+                None => self.eof_in_tag(),
+            },
+
+            // ---------------------
+            // rcdata, rawtext and script data end tags
+            // ---------------------
+
+            State::RcDataLessThanSign => self.text_less_than_sign(c, State::RcData, State::RcDataEndTagOpen),
+            State::RcDataEndTagOpen => self.text_end_tag_open(c, State::RcData, State::RcDataEndTagName),
+            State::RcDataEndTagName => self.text_end_tag_name(c, State::RcData),
+            State::RawTextLessThanSign => self.text_less_than_sign(c, State::RawText, State::RawTextEndTagOpen),
+            State::RawTextEndTagOpen => self.text_end_tag_open(c, State::RawText, State::RawTextEndTagName),
+            State::RawTextEndTagName => self.text_end_tag_name(c, State::RawText),
+            State::ScriptDataLessThanSign => match c {
+                Some('!') => {
+                    self.state = State::ScriptDataEscapeStart;
+                    self.emit_str("<!");
+                }
+                _ => self.text_less_than_sign(c, State::ScriptData, State::ScriptDataEndTagOpen),
+            },
+            State::ScriptDataEndTagOpen => self.text_end_tag_open(c, State::ScriptData, State::ScriptDataEndTagName),
+            State::ScriptDataEndTagName => self.text_end_tag_name(c, State::ScriptData),
+
+            // ---------------------
+            // script data escapes: <!-- ... -->
+            // ---------------------
+
+            State::ScriptDataEscapeStart => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapeStartDash;
+                    self.emit_char('-');
+                }
+                _ => self.reconsume_in(State::ScriptData),
+            },
+            State::ScriptDataEscapeStartDash => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapedDashDash;
+                    self.emit_char('-');
+                }
+                _ => self.reconsume_in(State::ScriptData),
+            },
+            State::ScriptDataEscaped => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapedDash;
+                    self.emit_char('-');
+                }
+                Some('<') => self.state = State::ScriptDataEscapedLessThanSign,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.emit_char('\u{FFFD}');
+                }
+                Some(c) => self.emit_char(c),
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataEscapedDash => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataEscapedDashDash;
+                    self.emit_char('-');
+                }
+                Some('<') => self.state = State::ScriptDataEscapedLessThanSign,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.state = State::ScriptDataEscaped;
+                    self.emit_char('\u{FFFD}');
+                }
+                Some(c) => {
+                    self.state = State::ScriptDataEscaped;
+                    self.emit_char(c);
+                }
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataEscapedDashDash => match c {
+                Some('-') => self.emit_char('-'),
+                Some('<') => self.state = State::ScriptDataEscapedLessThanSign,
+                Some('>') => {
+                    self.state = State::ScriptData;
+                    self.emit_char('>');
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.state = State::ScriptDataEscaped;
+                    self.emit_char('\u{FFFD}');
+                }
+                Some(c) => {
+                    self.state = State::ScriptDataEscaped;
+                    self.emit_char(c);
+                }
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataEscapedLessThanSign => match c {
+                Some('/') => {
+                    self.temp_buffer.clear();
+                    self.state = State::ScriptDataEscapedEndTagOpen;
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.temp_buffer.clear();
+                    self.emit_char('<');
+                    self.reconsume_in(State::ScriptDataDoubleEscapeStart);
+                }
+                _ => {
+                    self.emit_char('<');
+                    self.reconsume_in(State::ScriptDataEscaped);
+                }
+            },
+            State::ScriptDataEscapedEndTagOpen => {
+                self.text_end_tag_open(c, State::ScriptDataEscaped, State::ScriptDataEscapedEndTagName)
+            }
+            State::ScriptDataEscapedEndTagName => self.text_end_tag_name(c, State::ScriptDataEscaped),
+            State::ScriptDataDoubleEscapeStart => {
+                self.double_escape_boundary(c, State::ScriptDataDoubleEscaped, State::ScriptDataEscaped)
+            }
+            State::ScriptDataDoubleEscaped => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataDoubleEscapedDash;
+                    self.emit_char('-');
+                }
+                Some('<') => {
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    self.emit_char('<');
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.emit_char('\u{FFFD}');
+                }
+                Some(c) => self.emit_char(c),
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataDoubleEscapedDash => match c {
+                Some('-') => {
+                    self.state = State::ScriptDataDoubleEscapedDashDash;
+                    self.emit_char('-');
+                }
+                Some('<') => {
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    self.emit_char('<');
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.emit_char('\u{FFFD}');
+                }
+                Some(c) => {
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.emit_char(c);
+                }
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataDoubleEscapedDashDash => match c {
+                Some('-') => self.emit_char('-'),
+                Some('<') => {
+                    self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                    self.emit_char('<');
+                }
+                Some('>') => {
+                    self.state = State::ScriptData;
+                    self.emit_char('>');
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.emit_char('\u{FFFD}');
+                }
+                Some(c) => {
+                    self.state = State::ScriptDataDoubleEscaped;
+                    self.emit_char(c);
+                }
+                None => {
+                    self.error("eof-in-script-html-comment-like-text");
+                    self.emit_eof();
+                }
+            },
+            State::ScriptDataDoubleEscapedLessThanSign => match c {
+                Some('/') => {
+                    self.temp_buffer.clear();
+                    self.state = State::ScriptDataDoubleEscapeEnd;
+                    self.emit_char('/');
+                }
+                _ => self.reconsume_in(State::ScriptDataDoubleEscaped),
+            },
+            State::ScriptDataDoubleEscapeEnd => {
+                self.double_escape_boundary(c, State::ScriptDataEscaped, State::ScriptDataDoubleEscaped)
+            }
+
+            // ---------------------
+            // attributes
+            // ---------------------
+
+            State::BeforeAttributeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('/' | '>') | None => self.reconsume_in(State::AfterAttributeName),
+                Some('=') => {
+                    self.error("unexpected-equals-sign-before-attribute-name");
+                    self.new_attribute();
+                    self.current_attribute().0.push('=');
+                    self.state = State::AttributeName;
+                }
+                Some(_) => {
+                    self.new_attribute();
+                    self.reconsume_in(State::AttributeName);
+                }
+            },
+            State::AttributeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ' | '/' | '>') | None => {
+                    self.reconsume_in(State::AfterAttributeName)
+                }
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.current_attribute().0.push('\u{FFFD}');
+                }
+                Some(c) => {
+                    if matches!(c, '"' | '\'' | '<') {
+                        self.error("unexpected-character-in-attribute-name");
+                    }
+                    self.current_attribute().0.push(c.to_ascii_lowercase());
+                }
+            },
+            State::AfterAttributeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                None => self.eof_in_tag(),
+                Some(_) => {
+                    self.new_attribute();
+                    self.reconsume_in(State::AttributeName);
+                }
+            },
+            State::BeforeAttributeValue => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('"') => {
+                    self.state = State::AttributeValueDoubleQuoted;
+                    self.mark_attribute_value();
+                }
+                Some('\'') => {
+                    self.state = State::AttributeValueSingleQuoted;
+                    self.mark_attribute_value();
+                }
+                Some('>') => {
+                    self.error("missing-attribute-value");
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                _ => {
+                    self.reconsume_in(State::AttributeValueUnquoted);
+                    self.mark_attribute_value();
+                }
+            },
+            State::AttributeValueDoubleQuoted => self.quoted_attribute_value(c, '"'),
+            State::AttributeValueSingleQuoted => self.quoted_attribute_value(c, '\''),
+            State::AttributeValueUnquoted => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BeforeAttributeName,
+                Some('&') => self.start_character_reference(),
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.current_attribute().1.push('\u{FFFD}');
+                }
+                Some(c) => {
+                    if matches!(c, '"' | '\'' | '<' | '=' | '`') {
+                        self.error("unexpected-character-in-unquoted-attribute-value");
+                    }
+                    self.current_attribute().1.push(c);
+                }
+                None => self.eof_in_tag(),
+            },
+            State::AfterAttributeValueQuoted => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                None => self.eof_in_tag(),
+                Some(_) => {
+                    self.error("missing-whitespace-between-attributes");
+                    self.reconsume_in(State::BeforeAttributeName);
+                }
+            },
+            State::SelfClosingStartTag => match c {
+                Some('>') => {
+                    self.current_tag.self_closing = true;
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                None => self.eof_in_tag(),
+                Some(_) => {
+                    self.error("unexpected-solidus-in-tag");
+                    self.reconsume_in(State::BeforeAttributeName);
+                }
+            },
+
+            // ---------------------
+            // comments
+            // ---------------------
+
+            State::BogusComment => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.current_comment.push('\u{FFFD}');
+                }
+                Some(c) => self.current_comment.push(c),
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+            },
+            State::MarkupDeclarationOpen => unreachable!(),
+            State::CommentStart => match c {
+                Some('-') => self.state = State::CommentStartDash,
+                Some('>') => {
+                    self.error("abrupt-closing-of-empty-comment");
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                _ => self.reconsume_in(State::Comment),
+            },
+            State::CommentStartDash => match c {
+                Some('-') => self.state = State::CommentEnd,
+                Some('>') => {
+                    self.error("abrupt-closing-of-empty-comment");
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                None => self.eof_in_comment(),
+                Some(_) => {
+                    self.current_comment.push('-');
+                    self.reconsume_in(State::Comment);
+                }
+            },
+            State::Comment => match c {
+                Some('<') => {
+                    self.current_comment.push('<');
+                    self.state = State::CommentLessThanSign;
+                }
+                Some('-') => self.state = State::CommentEndDash,
+                Some('\0') => {
+                    self.error("unexpected-null-character");
+                    self.current_comment.push('\u{FFFD}');
+                }
+                Some(c) => self.current_comment.push(c),
+                None => self.eof_in_comment(),
+            },
+            State::CommentLessThanSign => match c {
+                Some('!') => {
+                    self.current_comment.push('!');
+                    self.state = State::CommentLessThanSignBang;
+                }
+                Some('<') => self.current_comment.push('<'),
+                _ => self.reconsume_in(State::Comment),
+            },
+            State::CommentLessThanSignBang => match c {
+                Some('-') => self.state = State::CommentLessThanSignBangDash,
+                _ => self.reconsume_in(State::Comment),
+            },
+            State::CommentLessThanSignBangDash => match c {
+                Some('-') => self.state = State::CommentLessThanSignBangDashDash,
+                _ => self.reconsume_in(State::CommentEndDash),
+            },
+            State::CommentLessThanSignBangDashDash => {
+                if !matches!(c, Some('>') | None) {
+                    self.error("nested-comment");
+                }
+                self.reconsume_in(State::CommentEnd);
+            }
+            State::CommentEndDash => match c {
+                Some('-') => self.state = State::CommentEnd,
+                None => self.eof_in_comment(),
+                Some(_) => {
+                    self.current_comment.push('-');
+                    self.reconsume_in(State::Comment);
+                }
+            },
+            State::CommentEnd => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                Some('!') => self.state = State::CommentEndBang,
+                Some('-') => self.current_comment.push('-'),
+                None => self.eof_in_comment(),
+                Some(_) => {
+                    self.current_comment.push_str("--");
+                    self.reconsume_in(State::Comment);
+                }
+            },
+            State::CommentEndBang => match c {
+                Some('-') => {
+                    self.current_comment.push_str("--!");
+                    self.state = State::CommentEndDash;
+                }
+                Some('>') => {
+                    self.error("incorrectly-closed-comment");
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                None => self.eof_in_comment(),
+                Some(_) => {
+                    self.current_comment.push_str("--!");
+                    self.reconsume_in(State::Comment);
+                }
+            },
+
+            // ---------------------
+            // doctype
+            // ---------------------
+
+            State::Doctype => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BeforeDoctypeName,
+                None => {
+                    self.current_doctype = Doctype::default();
+                    self.eof_in_doctype();
+                }
+                Some(c) => {
+                    if c != '>' {
+                        self.error("missing-whitespace-before-doctype-name");
+                    }
+                    self.reconsume_in(State::BeforeDoctypeName);
+                }
+            },
+            State::BeforeDoctypeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('>') => {
+                    self.error("missing-doctype-name");
+                    self.current_doctype = Doctype { force_quirks: true, ..Doctype::default() };
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                None => {
+                    self.current_doctype = Doctype::default();
+                    self.eof_in_doctype();
+                }
+                Some(c) => {
+                    let c = self.doctype_name_char(c);
+                    self.current_doctype = Doctype { name: Some(c.to_string()), ..Doctype::default() };
+                    self.state = State::DoctypeName;
+                }
+            },
+            State::DoctypeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::AfterDoctypeName,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                None => self.eof_in_doctype(),
+                Some(c) => {
+                    let c = self.doctype_name_char(c);
+                    self.current_doctype.name.get_or_insert_with(String::new).push(c);
+                }
+            },
+            State::AfterDoctypeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                None => self.eof_in_doctype(),
+                Some(_) => {
+                    self.pos = self.last_pos;
+                    if self.next_chars_are("public", true) {
+                        self.pos += 6;
+                        self.state = State::AfterDoctypePublicKeyword;
+                    } else if self.next_chars_are("system", true) {
+                        self.pos += 6;
+                        self.state = State::AfterDoctypeSystemKeyword;
+                    } else {
+                        self.error("invalid-character-sequence-after-doctype-name");
+                        self.current_doctype.force_quirks = true;
+                        self.state = State::BogusDoctype;
+                    }
+                }
+            },
+            State::AfterDoctypePublicKeyword => {
+                self.after_doctype_keyword(c, State::BeforeDoctypePublicIdentifier, true)
+            }
+            State::BeforeDoctypePublicIdentifier => self.before_doctype_identifier(c, true),
+            State::DoctypePublicIdentifierDoubleQuoted => self.doctype_identifier(c, '"', true),
+            State::DoctypePublicIdentifierSingleQuoted => self.doctype_identifier(c, '\'', true),
+            State::AfterDoctypePublicIdentifier => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BetweenDoctypePublicAndSystemIdentifiers,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some('"' | '\'') => {
+                    self.error("missing-whitespace-between-doctype-public-and-system-identifiers");
+                    self.start_doctype_identifier(c, false);
+                }
+                _ => self.bogus_doctype(c, "missing-quote-before-doctype-system-identifier"),
+            },
+            State::BetweenDoctypePublicAndSystemIdentifiers => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some('"' | '\'') => self.start_doctype_identifier(c, false),
+                _ => self.bogus_doctype(c, "missing-quote-before-doctype-system-identifier"),
+            },
+            State::AfterDoctypeSystemKeyword => {
+                self.after_doctype_keyword(c, State::BeforeDoctypeSystemIdentifier, false)
+            }
+            State::BeforeDoctypeSystemIdentifier => self.before_doctype_identifier(c, false),
+            State::DoctypeSystemIdentifierDoubleQuoted => self.doctype_identifier(c, '"', false),
+            State::DoctypeSystemIdentifierSingleQuoted => self.doctype_identifier(c, '\'', false),
+            State::AfterDoctypeSystemIdentifier => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                None => self.eof_in_doctype(),
+                // unlike other bogus doctypes this does not force quirks mode
+                Some(_) => {
+                    self.error("unexpected-character-after-doctype-system-identifier");
+                    self.reconsume_in(State::BogusDoctype);
+                }
+            },
+            State::BogusDoctype => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                None => {
+                    self.emit_doctype();
+                    self.emit_eof();
+                }
+                Some('\0') => self.error("unexpected-null-character"),
+                Some(_) => {}
+            },
+
+            // ---------------------
+            // cdata sections
+            // ---------------------
+
+            State::CdataSection => match c {
+                Some(']') => self.state = State::CdataSectionBracket,
+                Some(c) => self.emit_char(c),
+                None => {
+                    self.error("eof-in-cdata");
+                    self.emit_eof();
+                }
+            },
+            State::CdataSectionBracket => match c {
+                Some(']') => self.state = State::CdataSectionEnd,
+                _ => {
+                    self.emit_char(']');
+                    self.reconsume_in(State::CdataSection);
+                }
+            },
+            State::CdataSectionEnd => match c {
+                Some(']') => self.emit_char(']'),
+                Some('>') => self.state = State::Data,
+                _ => {
+                    self.emit_str("]]");
+                    self.reconsume_in(State::CdataSection);
+                }
+            },
+
+            // ---------------------
+            // character references
+            // ---------------------
+
+            State::CharacterReference => match c {
+                Some(c) if c.is_ascii_alphanumeric() => self.reconsume_in(State::NamedCharacterReference),
+                Some('#') => {
+                    self.temp_buffer.push('#');
+                    self.state = State::NumericCharacterReference;
+                }
+                _ => {
+                    self.flush_character_reference();
+                    self.reconsume_in(self.return_state);
+                }
+            },
+            State::NamedCharacterReference => unreachable!(),
+            State::AmbiguousAmpersand => match c {
+                Some(c) if c.is_ascii_alphanumeric() => {
+                    if self.in_attribute_value() {
+                        self.current_attribute().1.push(c);
+                    } else {
+                        self.emit_char(c);
+                    }
+                }
+                Some(';') => {
+                    self.error("unknown-named-character-reference");
+                    self.reconsume_in(self.return_state);
+                }
+                _ => self.reconsume_in(self.return_state),
+            },
+            State::NumericCharacterReference => {
+                self.char_ref_code = 0;
+                match c {
+                    Some(c @ ('x' | 'X')) => {
+                        self.temp_buffer.push(c);
+                        self.state = State::HexadecimalCharacterReferenceStart;
+                    }
+                    _ => self.reconsume_in(State::DecimalCharacterReferenceStart),
+                }
+            }
+            State::HexadecimalCharacterReferenceStart => match c {
+                Some(c) if c.is_ascii_hexdigit() => self.reconsume_in(State::HexadecimalCharacterReference),
+                _ => {
+                    self.error("absence-of-digits-in-numeric-character-reference");
+                    self.flush_character_reference();
+                    self.reconsume_in(self.return_state);
+                }
+            },
+            State::DecimalCharacterReferenceStart => match c {
+                Some(c) if c.is_ascii_digit() => self.reconsume_in(State::DecimalCharacterReference),
+                _ => {
+                    self.error("absence-of-digits-in-numeric-character-reference");
+                    self.flush_character_reference();
+                    self.reconsume_in(self.return_state);
+                }
+            },
+            State::HexadecimalCharacterReference => self.numeric_character_reference(c, 16),
+            State::DecimalCharacterReference => self.numeric_character_reference(c, 10),
+        }
+    }
+
+// ---------------------
+// shared states
+// ---------------------
+
+    fn text_data(&mut self, c: Option<char>, less_than_sign: State) {
+        match c {
+            Some('&') if self.state == State::RcData => self.start_character_reference(),
+            Some('<') => self.state = less_than_sign,
+            Some('\0') => {
+                self.error("unexpected-null-character");
+                self.emit_char('\u{FFFD}');
+            }
+            Some(c) => self.emit_char(c),
+            None => self.emit_eof(),
+        }
+    }
+
+    fn text_less_than_sign(&mut self, c: Option<char>, text_state: State, end_tag_open: State) {
+        match c {
+            Some('/') => {
+                self.temp_buffer.clear();
+                self.state = end_tag_open;
+            }
+            _ => {
+                self.emit_char('<');
+                self.reconsume_in(text_state);
+            }
+        }
+    }
+
+    fn text_end_tag_open(&mut self, c: Option<char>, text_state: State, end_tag_name: State) {
+        match c {
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.new_tag(true);
+                self.reconsume_in(end_tag_name);
+            }
+            _ => {
+                self.emit_str("</");
+                self.reconsume_in(text_state);
+            }
+        }
+    }
+
+    fn text_end_tag_name(&mut self, c: Option<char>, text_state: State) {
+        match c {
+            Some('\t' | '\n' | '\x0C' | ' ') if self.is_appropriate_end_tag() => {
+                self.state = State::BeforeAttributeName;
+            }
+            Some('/') if self.is_appropriate_end_tag() => {
+                self.state = State::SelfClosingStartTag;
+            }
+            Some('>') if self.is_appropriate_end_tag() => {
+                self.state = State::Data;
+                self.emit_tag();
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.current_tag.name.push(c.to_ascii_lowercase());
+                self.temp_buffer.push(c);
+            }
+            _ => {
+                let buffer = std::mem::take(&mut self.temp_buffer);
+                self.emit_str("</");
+                self.emit_str(&buffer);
+                self.reconsume_in(text_state);
+            }
+        }
+    }
+
+    fn double_escape_boundary(&mut self, c: Option<char>, script_state: State, otherwise: State) {
+        match c {
+            Some(c @ ('\t' | '\n' | '\x0C' | ' ' | '/' | '>')) => {
+                self.state = if self.temp_buffer == "script" { script_state } else { otherwise };
+                self.emit_char(c);
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.temp_buffer.push(c.to_ascii_lowercase());
+                self.emit_char(c);
+            }
+            _ => self.reconsume_in(otherwise),
+        }
+    }
+
+    fn quoted_attribute_value(&mut self, c: Option<char>, quote: char) {
+        match c {
+            Some(c) if c == quote => self.state = State::AfterAttributeValueQuoted,
+            Some('&') => self.start_character_reference(),
+            Some('\0') => {
+                self.error("unexpected-null-character");
+                self.current_attribute().1.push('\u{FFFD}');
+            }
+            Some(c) => self.current_attribute().1.push(c),
+            None => self.eof_in_tag(),
+        }
+    }
+
+    fn markup_declaration_open(&mut self) {
+        self.current_comment.clear();
+        if self.next_chars_are("--", false) {
+            self.pos += 2;
+            self.state = State::CommentStart;
+        } else if self.next_chars_are("doctype", true) {
+            self.pos += 7;
+            self.state = State::Doctype;
+        } else if self.next_chars_are("[CDATA[", false) {
+            self.pos += 7;
+            if self.cdata_allowed {
+                self.state = State::CdataSection;
+            } else {
+                self.error("cdata-in-html-content");
+                self.current_comment.push_str("[CDATA[");
+                self.state = State::BogusComment;
+            }
+        } else {
+            self.error("incorrectly-opened-comment");
+            self.state = State::BogusComment;
+        }
+    }
+
+    fn after_doctype_keyword(&mut self, c: Option<char>, before_identifier: State, is_public: bool) {
+        match c {
+            Some('\t' | '\n' | '\x0C' | ' ') => self.state = before_identifier,
+            Some('"' | '\'') => {
+                self.error(if is_public {
+                    "missing-whitespace-after-doctype-public-keyword"
+                } else {
+                    "missing-whitespace-after-doctype-system-keyword"
+                });
+                self.start_doctype_identifier(c, is_public);
+            }
+            _ => self.missing_doctype_identifier(c, is_public),
+        }
+    }
+
+    fn before_doctype_identifier(&mut self, c: Option<char>, is_public: bool) {
+        match c {
+            Some('\t' | '\n' | '\x0C' | ' ') => {}
+            Some('"' | '\'') => self.start_doctype_identifier(c, is_public),
+            _ => self.missing_doctype_identifier(c, is_public),
+        }
+    }
+
+    fn missing_doctype_identifier(&mut self, c: Option<char>, is_public: bool) {
+        let code = match (c, is_public) {
+            (Some('>'), true) => "missing-doctype-public-identifier",
+            (Some('>'), false) => "missing-doctype-system-identifier",
+            (_, true) => "missing-quote-before-doctype-public-identifier",
+            (_, false) => "missing-quote-before-doctype-system-identifier",
+        };
+        self.bogus_doctype(c, code);
+    }
+
+    fn start_doctype_identifier(&mut self, quote: Option<char>, is_public: bool) {
+        let (identifier, double_quoted, single_quoted) = if is_public {
+            (
+                &mut self.current_doctype.public_id,
+                State::DoctypePublicIdentifierDoubleQuoted,
+                State::DoctypePublicIdentifierSingleQuoted,
+            )
+        } else {
+            (
+                &mut self.current_doctype.system_id,
+                State::DoctypeSystemIdentifierDoubleQuoted,
+                State::DoctypeSystemIdentifierSingleQuoted,
+            )
+        };
+        *identifier = Some(String::new());
+        self.state = if quote == Some('"') { double_quoted } else { single_quoted };
+    }
+
+    fn doctype_identifier(&mut self, c: Option<char>, quote: char, is_public: bool) {
+        let (identifier, after_identifier) = if is_public {
+            (&mut self.current_doctype.public_id, State::AfterDoctypePublicIdentifier)
+        } else {
+            (&mut self.current_doctype.system_id, State::AfterDoctypeSystemIdentifier)
+        };
+        let identifier = identifier.get_or_insert_with(String::new);
+        match c {
+            Some(c) if c == quote => self.state = after_identifier,
+            Some('\0') => {
+                identifier.push('\u{FFFD}');
+                self.error("unexpected-null-character");
+            }
+            Some('>') => {
+                self.error(if is_public { "abrupt-doctype-public-identifier" } else { "abrupt-doctype-system-identifier" });
+                self.current_doctype.force_quirks = true;
+                self.state = State::Data;
+                self.emit_doctype();
+            }
+            Some(c) => identifier.push(c),
+            None => self.eof_in_doctype(),
+        }
+    }
+
+    // '>' and EOF end the doctype, anything else makes it bogus
+    fn bogus_doctype(&mut self, c: Option<char>, code: &'static str) {
+        self.current_doctype.force_quirks = true;
+        if c.is_some() {
+            self.error(code);
+        }
+        match c {
+            Some('>') => {
+                self.state = State::Data;
+                self.emit_doctype();
+            }
+            None => self.eof_in_doctype(),
+            Some(_) => self.reconsume_in(State::BogusDoctype),
+        }
+    }
+
+    fn doctype_name_char(&mut self, c: char) -> char {
+        if c == '\0' {
+            self.error("unexpected-null-character");
+            return '\u{FFFD}';
+        }
+        c.to_ascii_lowercase()
+    }
+
+    fn eof_in_doctype(&mut self) {
+        self.error("eof-in-doctype");
+        self.current_doctype.force_quirks = true;
+        self.emit_doctype();
+        self.emit_eof();
+    }
+
+// ---------------------
+// character references
+// ---------------------
+
+    fn start_character_reference(&mut self) {
+        self.return_state = self.state;
+        self.temp_buffer.clear();
+        self.temp_buffer.push('&');
+        self.state = State::CharacterReference;
+    }
+
+    fn named_character_reference(&mut self) {
+        let Some((len, characters)) = entities::longest_match(&self.input[self.pos..]) else {
+            self.flush_character_reference();
+            self.state = State::AmbiguousAmpersand;
+            return;
+        };
+
+        let name: String = self.input[self.pos..self.pos + len].iter().collect();
+        self.pos += len;
+
+        // legacy references without ';' are left alone in attributes when
+        // they are followed by '=' or an alphanumeric, e.g. href="?a=1&copy=2"
+        let next = self.input.get(self.pos).copied();
+        let historical = self.in_attribute_value()
+            && !name.ends_with(';')
+            && next.is_some_and(|c| c == '=' || c.is_ascii_alphanumeric());
+        if !historical && !name.ends_with(';') {
+            self.last_pos = self.pos;
+            self.error("missing-semicolon-after-character-reference");
+        }
+
+        if historical {
+            self.temp_buffer.push_str(&name);
+        } else {
+            self.temp_buffer.clear();
+            self.temp_buffer.push_str(characters);
+        }
+        self.flush_character_reference();
+        self.state = self.return_state;
+    }
+
+    fn numeric_character_reference(&mut self, c: Option<char>, radix: u32) {
+        match c.and_then(|c| c.to_digit(radix)) {
+            Some(digit) => {
+                // anything above 0x10FFFF is replaced anyway, so stop growing there
+                self.char_ref_code = (self.char_ref_code * radix + digit).min(0x110000);
+            }
+            None => {
+                if c != Some(';') {
+                    self.error("missing-semicolon-after-character-reference");
+                }
+                if let Some(code) = numeric_reference_error(self.char_ref_code) {
+                    self.error(code);
+                }
+                self.temp_buffer.clear();
+                self.temp_buffer.push(entities::numeric_reference(self.char_ref_code));
+                self.flush_character_reference();
+                if c == Some(';') {
+                    self.state = self.return_state;
+                } else {
+                    self.reconsume_in(self.return_state);
+                }
+            }
+        }
+    }
+
+    fn flush_character_reference(&mut self) {
+        let buffer = std::mem::take(&mut self.temp_buffer);
+        if self.in_attribute_value() {
+            self.current_attribute().1.push_str(&buffer);
+        } else {
+            self.emit_str(&buffer);
+        }
+    }
+
+    fn in_attribute_value(&self) -> bool {
+        matches!(
+            self.return_state,
+            State::AttributeValueDoubleQuoted | State::AttributeValueSingleQuoted | State::AttributeValueUnquoted
+        )
+    }
+
+// ---------------------
+// emit tokens
+// ---------------------
+
+    fn new_tag(&mut self, is_end_tag: bool) {
+        self.current_tag = Tag::default();
+        self.is_end_tag = is_end_tag;
+    }
+
+    fn new_attribute(&mut self) {
+        self.current_tag.attributes.push((String::new(), String::new()));
+        self.current_tag.value_locations.push(self.source_map.location(self.last_pos));
+    }
+
+    // the value starts at the next character to consume
+    fn mark_attribute_value(&mut self) {
+        *self.current_tag.value_locations.last_mut().unwrap() = self.source_map.location(self.pos);
+    }
+
+    fn current_attribute(&mut self) -> &mut (String, String) {
+        self.current_tag.attributes.last_mut().unwrap()
+    }
+
+    fn is_appropriate_end_tag(&self) -> bool {
+        self.last_start_tag.as_deref() == Some(self.current_tag.name.as_str())
+    }
+
+    fn emit_tag(&mut self) {
+        let mut tag = std::mem::take(&mut self.current_tag);
+
+        // later duplicates of an attribute are dropped
+        let mut seen: Vec<String> = Vec::new();
+        let before = tag.attributes.len();
+        let attributes = std::mem::take(&mut tag.attributes).into_iter().zip(std::mem::take(&mut tag.value_locations));
+        (tag.attributes, tag.value_locations) = attributes
+            .filter(|((name, _), _)| {
+                if seen.contains(name) {
+                    false
+                } else {
+                    seen.push(name.clone());
+                    true
+                }
+            })
+            .unzip();
+        if tag.attributes.len() != before {
+            self.error("duplicate-attribute");
+        }
+
+        if self.is_end_tag {
+            if !tag.attributes.is_empty() {
+                self.error("end-tag-with-attributes");
+            }
+            if tag.self_closing {
+                self.error("end-tag-with-trailing-solidus");
+            }
+            self.emit(Token::EndTag(tag));
+        } else {
+            self.last_start_tag = Some(tag.name.clone());
+            self.emit(Token::StartTag(tag));
+        }
+    }
+
+    fn emit_doctype(&mut self) {
+        let doctype = std::mem::take(&mut self.current_doctype);
+        self.emit(Token::Doctype(doctype));
+    }
+
+    fn emit_comment(&mut self) {
+        let comment = std::mem::take(&mut self.current_comment);
+        self.emit(Token::Comment(comment));
+    }
+
+    fn emit_char(&mut self, c: char) {
+        self.emit(Token::Character(c));
+    }
+
+    // all the characters share the span, e.g. those of a character reference
+    fn emit_str(&mut self, s: &str) {
+        for c in s.chars() {
+            self.pending.push_back((Token::Character(c), self.token_start, self.pos));
+        }
+        self.token_start = self.pos;
+    }
+
+    fn emit_eof(&mut self) {
+        self.token_start = self.pos;
+        self.emit(Token::Eof);
+        self.done = true;
+    }
+
+    fn emit(&mut self, token: Token) {
+        self.pending.push_back((token, self.token_start, self.pos));
+        self.token_start = self.pos;
+    }
+
+// ---------------------
+// parse errors
+// ---------------------
+
+    // errors point at the character that was consumed last
+    fn error(&mut self, code: &'static str) {
+        let location = self.source_map.location(self.last_pos);
+        self.errors.push(ParseError { code, location });
+    }
+
+    fn eof_in_tag(&mut self) {
+        self.error("eof-in-tag");
+        self.emit_eof();
+    }
+
+    fn eof_in_comment(&mut self) {
+        self.error("eof-in-comment");
+        self.emit_comment();
+        self.emit_eof();
+    }
+
+// ---------------------
+// assistant functions
+// ---------------------
+
+    fn consume_next(&mut self) -> Option<char> {
+        self.last_pos = self.pos;
+        let c = *self.input.get(self.pos)?;
+        self.pos += 1;
+
+        // normalize newlines: CRLF and CR become LF
+        if c == '\r' {
+            if self.input.get(self.pos) == Some(&'\n') {
+                self.pos += 1;
+            }
+            return Some('\n');
+        }
+        Some(c)
+    }
+
+    fn reconsume_in(&mut self, state: State) {
+        self.pos = self.last_pos;
+        self.state = state;
+
+        // tokens emitted just now end before the reconsumed character
+        self.token_start = self.token_start.min(self.pos);
+        for (_, start, end) in self.pending.iter_mut().rev() {
+            if *end <= self.pos {
+                break;
+            }
+            *end = self.pos;
+            *start = (*start).min(self.pos);
+        }
+    }
+
+    fn next_chars_are(&self, expected: &str, ignore_case: bool) -> bool {
+        expected.chars().enumerate().all(|(i, e)| match self.input.get(self.pos + i) {
+            Some(c) if ignore_case => c.eq_ignore_ascii_case(&e),
+            Some(&c) => c == e,
+            None => false,
+        })
+    }
+}
+
+// https://html.spec.whatwg.org/multipage/parsing.html#numeric-character-reference-end-state
+fn numeric_reference_error(code: u32) -> Option<&'static str> {
+    match code {
+        0 => Some("null-character-reference"),
+        0x110000.. => Some("character-reference-outside-unicode-range"),
+        0xD800..=0xDFFF => Some("surrogate-character-reference"),
+        0xFDD0..=0xFDEF => Some("noncharacter-character-reference"),
+        _ if code & 0xFFFE == 0xFFFE => Some("noncharacter-character-reference"),
+        0x0D | 0x80..=0x9F => Some("control-character-reference"),
+        0x01..=0x1F | 0x7F if !matches!(code, 0x09 | 0x0A | 0x0C | 0x20) => Some("control-character-reference"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every token before the end of file, and the codes of the errors found on the way
+    fn tokenize(html_input: &str) -> (Vec<Token>, Vec<&'static str>) {
+        let mut tokenizer = Tokenizer::new(html_input);
+        let mut tokens = Vec::new();
+        while let Some((mut token, _)) = tokenizer.next_token() {
+            if token == Token::Eof {
+                break;
+            }
+            // see value_locations for those
+            if let Token::StartTag(tag) | Token::EndTag(tag) = &mut token {
+                tag.value_locations.clear();
+            }
+            tokens.push(token);
+        }
+        (tokens, tokenizer.take_errors().iter().map(|error| error.code).collect())
+    }
+
+    fn value_locations(html_input: &str) -> Vec<(usize, usize)> {
+        let Some((Token::StartTag(tag), _)) = Tokenizer::new(html_input).next_token() else { panic!() };
+        tag.value_locations.iter().map(|location| (location.line, location.column)).collect()
+    }
+
+    fn tokens(html_input: &str) -> Vec<Token> {
+        tokenize(html_input).0
+    }
+
+    fn errors(html_input: &str) -> Vec<&'static str> {
+        tokenize(html_input).1
+    }
+
+    fn chars(text: &str) -> Vec<Token> {
+        text.chars().map(Token::Character).collect()
+    }
+
+    fn start(name: &str, attributes: &[(&str, &str)]) -> Token {
+        Token::StartTag(Tag {
+            name: name.to_string(),
+            attributes: attributes.iter().map(|&(name, value)| (name.to_string(), value.to_string())).collect(),
+            ..Tag::default()
+        })
+    }
+
+    fn end(name: &str) -> Token {
+        Token::EndTag(Tag { name: name.to_string(), ..Tag::default() })
+    }
+
+    #[test]
+    fn tags_and_attributes() {
+        assert_eq!(
+            tokens("<DIV Class=A id='b' title=\"c\" hidden>x</DiV >"),
+            [
+                vec![start("div", &[("class", "A"), ("id", "b"), ("title", "c"), ("hidden", "")])],
+                chars("x"),
+                vec![end("div")],
+            ].concat()
+        );
+        assert_eq!(errors("<DIV Class=A id='b' title=\"c\" hidden>x</DiV >"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn self_closing_tags() {
+        let Token::StartTag(br) = &tokens("<br/>")[0] else { panic!() };
+        assert!(br.self_closing);
+        // an unquoted value takes the slash
+        assert_eq!(tokens("<img src=a/>"), [start("img", &[("src", "a/")])]);
+        assert_eq!(errors("<a / b>"), ["unexpected-solidus-in-tag"]);
+        assert_eq!(errors("</a/>"), ["end-tag-with-trailing-solidus"]);
+    }
+
+    #[test]
+    fn where_attribute_values_start() {
+        assert_eq!(value_locations("<a b=\"1\" c='2' d=3 e>"), [(1, 7), (1, 13), (1, 18), (1, 20)]);
+        assert_eq!(value_locations("<a\n  b = \"x\">"), [(2, 8)]);
+        assert_eq!(value_locations("<a b=1 b=2 c=3>"), [(1, 6), (1, 14)]);
+    }
+
+    #[test]
+    fn duplicate_attributes_keep_the_first() {
+        assert_eq!(tokenize("<a b=1 B=2>"), (vec![start("a", &[("b", "1")])], vec!["duplicate-attribute"]));
+    }
+
+    #[test]
+    fn attribute_errors() {
+        assert_eq!(
+            tokenize("<a b=\"c\"d>"),
+            (vec![start("a", &[("b", "c"), ("d", "")])], vec!["missing-whitespace-between-attributes"])
+        );
+        assert_eq!(
+            tokenize("<a =b>"),
+            (vec![start("a", &[("=b", "")])], vec!["unexpected-equals-sign-before-attribute-name"])
+        );
+        assert_eq!(
+            tokenize("<a \"b>"),
+            (vec![start("a", &[("\"b", "")])], vec!["unexpected-character-in-attribute-name"])
+        );
+        assert_eq!(tokenize("<a b=>"), (vec![start("a", &[("b", "")])], vec!["missing-attribute-value"]));
+        assert_eq!(errors("</a b=1>"), ["end-tag-with-attributes"]);
+    }
+
+    #[test]
+    fn text_that_only_looks_like_a_tag() {
+        assert_eq!(tokenize("<>"), (chars("<>"), vec!["invalid-first-character-of-tag-name"]));
+        assert_eq!(tokenize("1 <3"), (chars("1 <3"), vec!["invalid-first-character-of-tag-name"]));
+        assert_eq!(tokenize("</>"), (vec![], vec!["missing-end-tag-name"]));
+        assert_eq!(
+            tokenize("</3>"),
+            (vec![Token::Comment("3".to_string())], vec!["invalid-first-character-of-tag-name"])
+        );
+        assert_eq!(
+            tokenize("<?php x?>"),
+            (vec![Token::Comment("?php x?".to_string())], vec!["unexpected-question-mark-instead-of-tag-name"])
+        );
+    }
+
+    #[test]
+    fn end_of_file_inside_a_tag_drops_it() {
+        assert_eq!(tokenize("a<b"), (chars("a"), vec!["eof-in-tag"]));
+        assert_eq!(tokenize("<b c='d"), (vec![], vec!["eof-in-tag"]));
+        assert_eq!(tokenize("a<"), (chars("a<"), vec!["eof-before-tag-name"]));
+    }
+
+    #[test]
+    fn null_characters() {
+        assert_eq!(tokenize("<a\0>"), (vec![start("a\u{FFFD}", &[])], vec!["unexpected-null-character"]));
+        // the tree builder drops the one in data
+        assert_eq!(tokenize("\0"), (chars("\0"), vec!["unexpected-null-character"]));
+    }
+
+    fn text(html_input: &str) -> String {
+        tokens(html_input).iter()
+            .filter_map(|token| match token {
+                Token::Character(c) => Some(*c),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn named_character_references() {
+        assert_eq!(text("&amp;&lt;&gt;&quot;&nbsp;"), "&<>\"\u{a0}");
+        assert_eq!(text("&amp &notit; &notin; &noti"), "& \u{ac}it; \u{2209} \u{ac}i");
+        assert_eq!(errors("&amp &notin;"), ["missing-semicolon-after-character-reference"]);
+        assert_eq!(tokenize("&unknown;"), (chars("&unknown;"), vec!["unknown-named-character-reference"]));
+        assert_eq!(tokenize("& &; &"), (chars("& &; &"), vec![]));
+    }
+
+    // in an attribute, a legacy name followed by '=' or an alphanumeric is left alone
+    #[test]
+    fn character_references_in_attributes() {
+        assert_eq!(
+            tokenize("<a href='?a=1&copy=2&amp=3&copyx&copy;' title=&lt;b&gt>"),
+            (
+                vec![start("a", &[("href", "?a=1&copy=2&amp=3&copyx\u{a9}"), ("title", "<b>")])],
+                vec!["missing-semicolon-after-character-reference"]
+            )
+        );
+    }
+
+    #[test]
+    fn numeric_character_references() {
+        assert_eq!(text("&#65;&#x42;&#X43;&#x1F600;&#65"), "ABC\u{1F600}A");
+        assert_eq!(errors("&#65"), ["missing-semicolon-after-character-reference"]);
+        assert_eq!(
+            tokenize("&#0;&#x110000;&#xD800;&#128;&#xFFFE;"),
+            (
+                chars("\u{FFFD}\u{FFFD}\u{FFFD}\u{20AC}\u{FFFE}"),
+                vec![
+                    "null-character-reference",
+                    "character-reference-outside-unicode-range",
+                    "surrogate-character-reference",
+                    "control-character-reference",
+                    "noncharacter-character-reference",
+                ]
+            )
+        );
+        assert_eq!(
+            tokenize("&#;&#x;"),
+            (chars("&#;&#x;"), vec!["absence-of-digits-in-numeric-character-reference"; 2])
+        );
+    }
+
+    fn comment(data: &str) -> Token {
+        Token::Comment(data.to_string())
+    }
+
+    fn doctype(name: Option<&str>, public_id: Option<&str>, system_id: Option<&str>, force_quirks: bool) -> Token {
+        Token::Doctype(Doctype {
+            name: name.map(str::to_string),
+            public_id: public_id.map(str::to_string),
+            system_id: system_id.map(str::to_string),
+            force_quirks,
+        })
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(tokenize("<!--a-->"), (vec![comment("a")], vec![]));
+        assert_eq!(tokenize("<!---->"), (vec![comment("")], vec![]));
+        assert_eq!(tokenize("<!-- a -- b -->"), (vec![comment(" a -- b ")], vec![]));
+        assert_eq!(tokenize("<!-->"), (vec![comment("")], vec!["abrupt-closing-of-empty-comment"]));
+        assert_eq!(tokenize("<!--->"), (vec![comment("")], vec!["abrupt-closing-of-empty-comment"]));
+        assert_eq!(tokenize("<!--a--!>"), (vec![comment("a")], vec!["incorrectly-closed-comment"]));
+        assert_eq!(tokenize("<!--a<!--b-->"), (vec![comment("a<!--b")], vec!["nested-comment"]));
+        assert_eq!(tokenize("<!--a"), (vec![comment("a")], vec!["eof-in-comment"]));
+    }
+
+    #[test]
+    fn bogus_comments() {
+        assert_eq!(tokenize("<!a>"), (vec![comment("a")], vec!["incorrectly-opened-comment"]));
+        assert_eq!(tokenize("<![CDATA[x]]>"), (vec![comment("[CDATA[x]]")], vec!["cdata-in-html-content"]));
+    }
+
+    #[test]
+    fn cdata_sections_in_foreign_content() {
+        let cdata = |html_input: &str| {
+            let mut tokenizer = Tokenizer::new(html_input);
+            tokenizer.set_cdata_allowed(true);
+            let mut text = String::new();
+            while let Some((Token::Character(c), _)) = tokenizer.next_token() {
+                text.push(c);
+            }
+            let errors: Vec<&str> = tokenizer.take_errors().iter().map(|error| error.code).collect();
+            (text, errors)
+        };
+        assert_eq!(cdata("<![CDATA[a<b>]]c]]>"), ("a<b>]]c".to_string(), vec![]));
+        assert_eq!(cdata("<![CDATA[x"), ("x".to_string(), vec!["eof-in-cdata"]));
+    }
+
+    #[test]
+    fn doctypes() {
+        assert_eq!(tokenize("<!DOCTYPE html>"), (vec![doctype(Some("html"), None, None, false)], vec![]));
+        assert_eq!(
+            tokens("<!doctype HTML PUBLIC \"-//W3C//DTD HTML 4.01//EN\" 'http://x'>"),
+            [doctype(Some("html"), Some("-//W3C//DTD HTML 4.01//EN"), Some("http://x"), false)]
+        );
+        assert_eq!(
+            tokens("<!DOCTYPE html SYSTEM \"about:legacy-compat\">"),
+            [doctype(Some("html"), None, Some("about:legacy-compat"), false)]
+        );
+    }
+
+    #[test]
+    fn broken_doctypes_force_quirks() {
+        assert_eq!(tokenize("<!DOCTYPE>"), (vec![doctype(None, None, None, true)], vec!["missing-doctype-name"]));
+        assert_eq!(
+            tokenize("<!DOCTYPEhtml>"),
+            (vec![doctype(Some("html"), None, None, false)], vec!["missing-whitespace-before-doctype-name"])
+        );
+        assert_eq!(
+            tokenize("<!DOCTYPE html SYSTEM>"),
+            (vec![doctype(Some("html"), None, None, true)], vec!["missing-doctype-system-identifier"])
+        );
+        assert_eq!(
+            tokenize("<!DOCTYPE html bogus>"),
+            (vec![doctype(Some("html"), None, None, true)], vec!["invalid-character-sequence-after-doctype-name"])
+        );
+        assert_eq!(tokenize("<!DOCTYPE html"), (vec![doctype(Some("html"), None, None, true)], vec!["eof-in-doctype"]));
+    }
+
+    // the tree builder switches the state once it has seen the start tag
+    fn raw_text(html_input: &str, state: State) -> Vec<Token> {
+        let mut tokenizer = Tokenizer::new(html_input);
+        let mut tokens = vec![tokenizer.next_token().unwrap().0];
+        tokenizer.set_state(state);
+        while let Some((token, _)) = tokenizer.next_token() {
+            if token == Token::Eof {
+                break;
+            }
+            tokens.push(token);
+        }
+        tokens
+    }
+
+    #[test]
+    fn raw_text_ends_at_the_matching_end_tag() {
+        assert_eq!(
+            raw_text("<style>a&amp;<p></styles></STYLE>b", State::RawText),
+            [vec![start("style", &[])], chars("a&amp;<p></styles>"), vec![end("style")], chars("b")].concat()
+        );
+        assert_eq!(
+            raw_text("<title>a&amp;<b></title>", State::RcData),
+            [vec![start("title", &[])], chars("a&<b>"), vec![end("title")]].concat()
+        );
+        assert_eq!(
+            raw_text("<plaintext>a</plaintext>", State::PlainText),
+            [vec![start("plaintext", &[])], chars("a</plaintext>")].concat()
+        );
+    }
+
+    #[test]
+    fn script_data_escapes() {
+        assert_eq!(
+            raw_text("<script>a<!--<script>x</script>y--></script>", State::ScriptData),
+            [vec![start("script", &[])], chars("a<!--<script>x</script>y-->"), vec![end("script")]].concat()
+        );
+        // without a nested <script> the escaped text ends at the first end tag
+        assert_eq!(
+            raw_text("<script><!--x</script>y", State::ScriptData),
+            [vec![start("script", &[])], chars("<!--x"), vec![end("script")], chars("y")].concat()
+        );
+    }
+
+    #[test]
+    fn end_of_file_repeats() {
+        let mut tokenizer = Tokenizer::new("");
+        assert_eq!(tokenizer.next_token().map(|(token, _)| token), Some(Token::Eof));
+        assert_eq!(tokenizer.next_token().map(|(token, _)| token), Some(Token::Eof));
+    }
+
+    // the tokens and spans when the input arrives `size` characters at a time
+    fn streamed(html_input: &str, size: usize) -> (Vec<(Token, Span)>, Vec<ParseError>) {
+        let mut tokenizer = Tokenizer::new_streaming();
+        let mut tokens = Vec::new();
+        let input: Vec<char> = html_input.chars().collect();
+        for part in input.chunks(size) {
+            tokenizer.feed(&part.iter().collect::<String>());
+            while let Some(token) = tokenizer.next_token() {
+                tokens.push(token);
+            }
+        }
+        tokenizer.close();
+        while let Some(token) = tokenizer.next_token() {
+            let eof = token.0 == Token::Eof;
+            tokens.push(token);
+            if eof {
+                break;
+            }
+        }
+        (tokens, tokenizer.take_errors())
+    }
+
+    #[test]
+    fn chunks_make_no_difference() {
+        let html = "<!DOCTYPE html><p class=\"a b\" id=x>caf\u{e9} &amp;&notin; &#x41\r\n<!-- c -->\
+                    <script>if (a<b) {}</script><textarea>&lt;\r</textarea><b/></p";
+        let whole = streamed(html, html.len());
+        assert!(whole.0.len() > 10);
+        for size in 1..=7 {
+            assert_eq!(streamed(html, size), whole, "chunks of {}", size);
+        }
+    }
+
+    // the tokens that are ready, up to the end of file
+    fn ready(tokenizer: &mut Tokenizer) -> Vec<Token> {
+        std::iter::from_fn(|| tokenizer.next_token().map(|(token, _)| token))
+            .take_while(|token| *token != Token::Eof)
+            .collect()
+    }
+
+    #[test]
+    fn holds_back_what_a_step_could_look_past() {
+        let mut tokenizer = Tokenizer::new_streaming();
+        tokenizer.feed("a<div>&am");
+        assert_eq!(tokenizer.next_token(), None);
+        // "&amp;" and what follows
+        tokenizer.feed(&"p;".repeat(LOOKAHEAD));
+        let tokens = ready(&mut tokenizer);
+        assert_eq!(tokens[..3], [Token::Character('a'), start("div", &[]), Token::Character('&')]);
+        assert_eq!(tokenizer.next_token(), None);
+        tokenizer.close();
+        assert_eq!(tokens.len() - 3 + ready(&mut tokenizer).len(), 2 * LOOKAHEAD - 2);
+    }
+}
@@ -0,0 +1,2618 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+use encoding_rs::Encoding;
+
+use super::diagnostics::{ParseError, Span};
+use super::dom::{self, Namespace, NodeId};
+use super::encoding::{self, Confidence};
+use super::foreign;
+use super::parser;
+use super::tokenizer::{self, Doctype, Tag, Token};
+
+// https://html.spec.whatwg.org/multipage/parsing.html#tree-construction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertionMode {
+    Initial,
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    InHeadNoscript,
+    AfterHead,
+    InBody,
+    Text,
+    InTable,
+    InTableText,
+    InCaption,
+    InColumnGroup,
+    InTableBody,
+    InRow,
+    InCell,
+    InSelect,
+    InSelectInTable,
+    InTemplate,
+    AfterBody,
+    InFrameset,
+    AfterFrameset,
+    AfterAfterBody,
+    AfterAfterFrameset,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Default,
+    ListItem,
+    Button,
+    Table,
+    Select,
+}
+
+enum FormattingEntry {
+    Marker,
+    Element(NodeId, Tag),
+}
+
+pub struct TreeBuilder {
+    document: dom::Document,
+    mode: InsertionMode,
+    original_mode: InsertionMode,
+    open_elements: Vec<NodeId>,
+    active_formatting: Vec<FormattingEntry>,
+    // the mode to go back to inside each open <template>
+    template_modes: Vec<InsertionMode>,
+    head: Option<NodeId>,
+    form: Option<NodeId>,
+    // cleared once the body has content that a <frameset> must not replace
+    frameset_ok: bool,
+    foster_parenting: bool,
+    pending_table_text: String,
+    pending_table_text_span: Span,
+    ignore_lf: bool,
+    tokenizer_state: Option<tokenizer::State>,
+    context: Option<dom::ElementData>,
+
+    // the span of the token being processed
+    span: Span,
+    errors: Vec<ParseError>,
+
+    confidence: Confidence,
+    restart: bool,
+}
+
+impl Default for TreeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TreeBuilder {
+    pub fn new() -> Self {
+        TreeBuilder {
+            document: dom::Document::new(),
+            mode: InsertionMode::Initial,
+            original_mode: InsertionMode::Initial,
+            open_elements: Vec::new(),
+            active_formatting: Vec::new(),
+            template_modes: Vec::new(),
+            head: None,
+            form: None,
+            frameset_ok: true,
+            foster_parenting: false,
+            pending_table_text: String::new(),
+            pending_table_text_span: Span::default(),
+            ignore_lf: false,
+            tokenizer_state: None,
+            context: None,
+            span: Span::default(),
+            errors: Vec::new(),
+            confidence: Confidence::Certain,
+            restart: false,
+        }
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#parsing-html-fragments
+    pub fn new_fragment(context: &dom::ElementData, mode: dom::QuirksMode, in_form: bool) -> Self {
+        let mut builder = Self::new();
+        builder.document.mode = mode;
+        builder.tokenizer_state = match context.tag.as_str() {
+            "title" | "textarea" => Some(tokenizer::State::RcData),
+            "style" | "xmp" | "iframe" | "noembed" | "noframes" => Some(tokenizer::State::RawText),
+            "script" => Some(tokenizer::State::ScriptData),
+            "plaintext" => Some(tokenizer::State::PlainText),
+            _ => None,
+        };
+
+        let root = builder.document.root();
+        let html = builder.document.create_element("html".to_string(), HashMap::new());
+        builder.document.append(root, html);
+        builder.open_elements.push(html);
+        builder.context = Some(context.clone());
+        if context.is_html() && context.tag == "template" {
+            builder.template_modes.push(InsertionMode::InTemplate);
+        }
+        if in_form {
+            // the real form belongs to another document; a detached stand-in
+            // is enough to keep nested <form> tags out
+            builder.form = Some(builder.document.create_element("form".to_string(), HashMap::new()));
+        }
+        builder.reset_insertion_mode();
+        builder
+    }
+
+    pub fn process_token(&mut self, token: Token, span: Span) {
+        self.span = span;
+        self.dispatch(token);
+    }
+
+    // a tentative encoding may still be corrected by a <meta> in the page
+    pub fn set_encoding(&mut self, encoding: &'static Encoding, confidence: Confidence) {
+        self.document.encoding = encoding;
+        self.confidence = confidence;
+    }
+
+    pub fn document(&self) -> &dom::Document {
+        &self.document
+    }
+
+    // the page declared another encoding; the input has to be decoded and parsed again
+    pub fn needs_restart(&self) -> bool {
+        self.restart
+    }
+
+    // the parse errors found so far, in input order
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    fn dispatch(&mut self, token: Token) {
+        // a newline right after <pre>, <listing> or <textarea> is dropped
+        if self.ignore_lf {
+            self.ignore_lf = false;
+            if token == Token::Character('\n') {
+                return;
+            }
+        }
+
+        // https://html.spec.whatwg.org/multipage/parsing.html#tree-construction-dispatcher
+        let html_content = match self.adjusted_current_node() {
+            None => true,
+            Some(node) if node.is_html() => true,
+            Some(node) => match &token {
+                Token::StartTag(tag) => {
+                    (is_mathml_text_integration_point(node) && !matches!(tag.name.as_str(), "mglyph" | "malignmark"))
+                        || (node.namespace == Namespace::MathMl && node.tag == "annotation-xml" && tag.name == "svg")
+                        || is_html_integration_point(node)
+                }
+                Token::Character(_) => is_mathml_text_integration_point(node) || is_html_integration_point(node),
+                Token::Eof => true,
+                _ => false,
+            },
+        };
+        if html_content {
+            if let Token::StartTag(tag) = &token
+                && tag.self_closing
+                && !acknowledges_self_closing(&tag.name)
+            {
+                self.error("non-void-html-element-start-tag-with-trailing-solidus");
+            }
+            self.process_in(self.mode, token);
+        } else {
+            self.foreign_content(token);
+        }
+    }
+
+    // raw text elements switch the tokenizer out of the data state
+    pub fn take_tokenizer_state(&mut self) -> Option<tokenizer::State> {
+        self.tokenizer_state.take()
+    }
+
+    // CDATA sections are only allowed inside svg and math
+    pub fn in_foreign_content(&self) -> bool {
+        self.adjusted_current_node().is_some_and(|node| !node.is_html())
+    }
+
+    pub fn finish(self) -> dom::Document {
+        self.document
+    }
+
+    fn process_in(&mut self, mode: InsertionMode, token: Token) {
+        match mode {
+            InsertionMode::Initial => self.initial(token),
+            InsertionMode::BeforeHtml => self.before_html(token),
+            InsertionMode::BeforeHead => self.before_head(token),
+            InsertionMode::InHead => self.in_head(token),
+            InsertionMode::InHeadNoscript => self.in_head_noscript(token),
+            InsertionMode::AfterHead => self.after_head(token),
+            InsertionMode::InBody => self.in_body(token),
+            InsertionMode::Text => self.text(token),
+            InsertionMode::InTable => self.in_table(token),
+            InsertionMode::InTableText => self.in_table_text(token),
+            InsertionMode::InCaption => self.in_caption(token),
+            InsertionMode::InColumnGroup => self.in_column_group(token),
+            InsertionMode::InTableBody => self.in_table_body(token),
+            InsertionMode::InRow => self.in_row(token),
+            InsertionMode::InCell => self.in_cell(token),
+            InsertionMode::InSelect => self.in_select(token),
+            InsertionMode::InSelectInTable => self.in_select_in_table(token),
+            InsertionMode::InTemplate => self.in_template(token),
+            InsertionMode::AfterBody => self.after_body(token),
+            InsertionMode::InFrameset => self.in_frameset(token),
+            InsertionMode::AfterFrameset => self.after_frameset(token),
+            InsertionMode::AfterAfterBody => self.after_after_body(token),
+            InsertionMode::AfterAfterFrameset => self.after_after_frameset(token),
+        }
+    }
+
+    fn reprocess_in(&mut self, mode: InsertionMode, token: Token) {
+        self.mode = mode;
+        self.process_in(mode, token);
+    }
+
+// ---------------------
+// document level modes
+// ---------------------
+
+    fn initial(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => {}
+            Token::Comment(data) => self.append_comment_to(self.document.root(), data),
+            Token::Doctype(doctype) => {
+                if !is_conforming_doctype(&doctype) {
+                    self.error("non-conforming-doctype");
+                }
+                let node = self.document.create_node(dom::NodeType::Doctype(dom::DoctypeData {
+                    name: doctype.name.clone().unwrap_or_default(),
+                    public_id: doctype.public_id.clone().unwrap_or_default(),
+                    system_id: doctype.system_id.clone().unwrap_or_default(),
+                }));
+                self.set_span(node);
+                self.document.append(self.document.root(), node);
+                self.document.mode = quirks_mode(&doctype);
+                self.mode = InsertionMode::BeforeHtml;
+            }
+            token => {
+                // no doctype at all
+                self.error("missing-doctype");
+                self.document.mode = dom::QuirksMode::Quirks;
+                self.reprocess_in(InsertionMode::BeforeHtml, token);
+            }
+        }
+    }
+
+    fn before_html(&mut self, token: Token) {
+        match token {
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::Character(c) if is_whitespace(c) => {}
+            Token::Comment(data) => self.append_comment_to(self.document.root(), data),
+            Token::StartTag(tag) if tag.name == "html" => {
+                let html = self.create_element(&tag);
+                self.document.append(self.document.root(), html);
+                self.open_elements.push(html);
+                self.mode = InsertionMode::BeforeHead;
+            }
+            Token::EndTag(tag) if !matches!(tag.name.as_str(), "head" | "body" | "html" | "br") => {
+                self.error("unexpected-end-tag")
+            }
+            token => {
+                let html = self.create_element(&implied_tag("html"));
+                self.document.append(self.document.root(), html);
+                self.open_elements.push(html);
+                self.reprocess_in(InsertionMode::BeforeHead, token);
+            }
+        }
+    }
+
+    fn before_head(&mut self, token: Token) {
+        match token {
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::Character(c) if is_whitespace(c) => {}
+            Token::Comment(data) => self.insert_comment(data),
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::StartTag(tag) if tag.name == "head" => {
+                self.head = Some(self.insert_html_element(&tag));
+                self.mode = InsertionMode::InHead;
+            }
+            Token::EndTag(tag) if !matches!(tag.name.as_str(), "head" | "body" | "html" | "br") => {
+                self.error("unexpected-end-tag")
+            }
+            token => {
+                self.head = Some(self.insert_html_element(&implied_tag("head")));
+                self.reprocess_in(InsertionMode::InHead, token);
+            }
+        }
+    }
+
+    fn in_head(&mut self, token: Token) {
+        match token {
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::Character(c) if is_whitespace(c) => self.insert_char(c),
+            Token::Comment(data) => self.insert_comment(data),
+            Token::StartTag(tag) => match tag.name.as_str() {
+                "html" => self.in_body(Token::StartTag(tag)),
+                "base" | "basefont" | "bgsound" | "link" | "meta" => {
+                    self.insert_html_element(&tag);
+                    self.open_elements.pop();
+                    if tag.name == "meta"
+                        && self.confidence == Confidence::Tentative
+                        && let Some(encoding) = encoding::meta_charset(&tag.attributes)
+                    {
+                        self.change_encoding(encoding);
+                    }
+                }
+                "title" => self.parse_text_element(&tag, tokenizer::State::RcData),
+                "noframes" | "style" => self.parse_text_element(&tag, tokenizer::State::RawText),
+                "script" => self.parse_text_element(&tag, tokenizer::State::ScriptData),
+                "noscript" => {
+                    self.insert_html_element(&tag);
+                    self.mode = InsertionMode::InHeadNoscript;
+                }
+                "template" => {
+                    self.insert_html_element(&tag);
+                    self.active_formatting.push(FormattingEntry::Marker);
+                    self.frameset_ok = false;
+                    self.mode = InsertionMode::InTemplate;
+                    self.template_modes.push(InsertionMode::InTemplate);
+                }
+                "head" => self.error("unexpected-start-tag"),
+                _ => self.anything_else_in_head(Token::StartTag(tag)),
+            },
+            Token::EndTag(tag) => match tag.name.as_str() {
+                "head" => {
+                    self.open_elements.pop();
+                    self.mode = InsertionMode::AfterHead;
+                }
+                "template" if self.has_open_element("template") => {
+                    self.generate_all_implied_end_tags();
+                    self.check_current_node("template");
+                    self.close_template();
+                }
+                "body" | "html" | "br" => self.anything_else_in_head(Token::EndTag(tag)),
+                _ => self.error("unexpected-end-tag"),
+            },
+            token => self.anything_else_in_head(token),
+        }
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#changing-the-encoding-while-parsing
+    fn change_encoding(&mut self, encoding: &'static Encoding) {
+        let current = self.document.encoding;
+        self.confidence = Confidence::Certain;
+        if current == encoding_rs::UTF_16BE || current == encoding_rs::UTF_16LE {
+            return;
+        }
+        let encoding = encoding::adjust(encoding);
+        if encoding != current {
+            self.document.encoding = encoding;
+            self.restart = true;
+        }
+    }
+
+    fn anything_else_in_head(&mut self, token: Token) {
+        self.open_elements.pop();
+        self.reprocess_in(InsertionMode::AfterHead, token);
+    }
+
+    fn in_head_noscript(&mut self, token: Token) {
+        match token {
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::EndTag(ref tag) if tag.name == "noscript" => {
+                self.open_elements.pop();
+                self.mode = InsertionMode::InHead;
+            }
+            Token::Character(c) if is_whitespace(c) => self.in_head(token),
+            Token::Comment(_) => self.in_head(token),
+            Token::StartTag(ref tag)
+                if matches!(tag.name.as_str(), "basefont" | "bgsound" | "link" | "meta" | "noframes" | "style") =>
+            {
+                self.in_head(token)
+            }
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(), "head" | "noscript") => {
+                self.error("unexpected-start-tag")
+            }
+            Token::EndTag(ref tag) if tag.name != "br" => self.error("unexpected-end-tag"),
+            token => {
+                self.error("unexpected-token-in-noscript");
+                self.open_elements.pop();
+                self.reprocess_in(InsertionMode::InHead, token);
+            }
+        }
+    }
+
+    fn after_head(&mut self, token: Token) {
+        match token {
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::Character(c) if is_whitespace(c) => self.insert_char(c),
+            Token::Comment(data) => self.insert_comment(data),
+            Token::StartTag(tag) => match tag.name.as_str() {
+                "html" => self.in_body(Token::StartTag(tag)),
+                "body" => {
+                    self.insert_html_element(&tag);
+                    self.frameset_ok = false;
+                    self.mode = InsertionMode::InBody;
+                }
+                "frameset" => {
+                    self.insert_html_element(&tag);
+                    self.mode = InsertionMode::InFrameset;
+                }
+                "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style" | "template"
+                | "title" => {
+                    // the head element is put back on the stack while these are inserted
+                    self.error("unexpected-start-tag");
+                    let head = self.head.unwrap();
+                    self.open_elements.push(head);
+                    self.in_head(Token::StartTag(tag));
+                    self.remove_from_stack(head);
+                }
+                "head" => self.error("unexpected-start-tag"),
+                _ => self.anything_else_after_head(Token::StartTag(tag)),
+            },
+            Token::EndTag(tag) => match tag.name.as_str() {
+                "template" => self.in_head(Token::EndTag(tag)),
+                "body" | "html" | "br" => self.anything_else_after_head(Token::EndTag(tag)),
+                _ => self.error("unexpected-end-tag"),
+            },
+            token => self.anything_else_after_head(token),
+        }
+    }
+
+    fn anything_else_after_head(&mut self, token: Token) {
+        self.insert_html_element(&implied_tag("body"));
+        self.reprocess_in(InsertionMode::InBody, token);
+    }
+
+    fn after_body(&mut self, token: Token) {
+        match token {
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::Character(c) if is_whitespace(c) => self.in_body(token),
+            Token::Comment(data) => {
+                let html = self.open_elements[0];
+                self.append_comment_to(html, data);
+            }
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::EndTag(ref tag) if tag.name == "html" => self.mode = InsertionMode::AfterAfterBody,
+            Token::Eof => {}
+            token => {
+                self.error("unexpected-content-after-body");
+                self.reprocess_in(InsertionMode::InBody, token);
+            }
+        }
+    }
+
+    fn after_after_body(&mut self, token: Token) {
+        match token {
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::Comment(data) => self.append_comment_to(self.document.root(), data),
+            Token::Character(c) if is_whitespace(c) => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::Eof => {}
+            token => {
+                self.error("unexpected-content-after-body");
+                self.reprocess_in(InsertionMode::InBody, token);
+            }
+        }
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inframeset
+    fn in_frameset(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.insert_char(c),
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(tag) => match tag.name.as_str() {
+                "html" => self.in_body(Token::StartTag(tag)),
+                "frameset" => {
+                    self.insert_html_element(&tag);
+                }
+                "frame" => {
+                    self.insert_html_element(&tag);
+                    self.open_elements.pop();
+                }
+                "noframes" => self.in_head(Token::StartTag(tag)),
+                _ => self.error("unexpected-start-tag"),
+            },
+            Token::EndTag(tag) if tag.name == "frameset" => {
+                // the root of a fragment is never popped
+                if self.open_elements.len() == 1 {
+                    self.error("unexpected-end-tag");
+                    return;
+                }
+                self.open_elements.pop();
+                if self.context.is_none() && self.current_tag_name() != "frameset" {
+                    self.mode = InsertionMode::AfterFrameset;
+                }
+            }
+            Token::EndTag(_) => self.error("unexpected-end-tag"),
+            Token::Eof => {
+                if self.open_elements.len() > 1 {
+                    self.error("eof-in-element");
+                }
+            }
+            Token::Character(_) => self.error("unexpected-character-in-frameset"),
+        }
+    }
+
+    fn after_frameset(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => self.insert_char(c),
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::EndTag(ref tag) if tag.name == "html" => self.mode = InsertionMode::AfterAfterFrameset,
+            Token::StartTag(ref tag) if tag.name == "noframes" => self.in_head(token),
+            Token::Eof => {}
+            _ => self.error("unexpected-content-after-frameset"),
+        }
+    }
+
+    fn after_after_frameset(&mut self, token: Token) {
+        match token {
+            Token::Comment(data) => self.append_comment_to(self.document.root(), data),
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::Character(c) if is_whitespace(c) => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "noframes" => self.in_head(token),
+            Token::Eof => {}
+            _ => self.error("unexpected-content-after-frameset"),
+        }
+    }
+
+    fn text(&mut self, token: Token) {
+        match token {
+            Token::Character(c) => self.insert_char(c),
+            Token::EndTag(_) => {
+                self.open_elements.pop();
+                self.mode = self.original_mode;
+            }
+            Token::Eof => {
+                self.error("eof-in-element");
+                self.open_elements.pop();
+                self.reprocess_in(self.original_mode, token);
+            }
+            _ => {}
+        }
+    }
+
+// ---------------------
+// in body
+// ---------------------
+
+    fn in_body(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => self.error("unexpected-null-character"),
+            Token::Character(c) => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_char(c);
+                if !is_whitespace(c) {
+                    self.frameset_ok = false;
+                }
+            }
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(tag) => self.in_body_start_tag(tag),
+            Token::EndTag(tag) => self.in_body_end_tag(tag),
+            Token::Eof if !self.template_modes.is_empty() => self.in_template(token),
+            Token::Eof => {
+                let unclosed = self.open_elements.iter().any(|&node| {
+                    !matches!(
+                        self.tag_name(node),
+                        "dd" | "dt" | "li" | "optgroup" | "option" | "p" | "rb" | "rp" | "rt" | "rtc" | "tbody" | "td"
+                            | "tfoot" | "th" | "thead" | "tr" | "body" | "html"
+                    )
+                });
+                if unclosed {
+                    self.error("eof-in-element");
+                }
+            }
+        }
+    }
+
+    fn in_body_start_tag(&mut self, mut tag: Tag) {
+        match tag.name.as_str() {
+            "html" => {
+                self.error("unexpected-start-tag");
+                if !self.has_open_element("template") {
+                    let html = self.open_elements[0];
+                    self.merge_attributes(html, tag);
+                }
+            }
+            "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style" | "template"
+            | "title" => self.in_head(Token::StartTag(tag)),
+            "body" => {
+                self.error("unexpected-start-tag");
+                if let Some(&body) = self.open_elements.get(1)
+                    && self.tag_name(body) == "body"
+                    && !self.has_open_element("template")
+                {
+                    self.frameset_ok = false;
+                    self.merge_attributes(body, tag);
+                }
+            }
+            "frameset" => {
+                // a frameset replaces the body, as long as nothing has been put in it
+                self.error("unexpected-start-tag");
+                let Some(&body) = self.open_elements.get(1) else { return };
+                if self.tag_name(body) != "body" || !self.frameset_ok {
+                    return;
+                }
+                self.document.detach(body);
+                self.open_elements.truncate(1);
+                self.insert_html_element(&tag);
+                self.mode = InsertionMode::InFrameset;
+            }
+            "address" | "article" | "aside" | "blockquote" | "center" | "details" | "dialog" | "dir" | "div"
+            | "dl" | "fieldset" | "figcaption" | "figure" | "footer" | "header" | "hgroup" | "main" | "menu"
+            | "nav" | "ol" | "p" | "search" | "section" | "summary" | "ul" => {
+                self.close_p_in_button_scope();
+                self.insert_html_element(&tag);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.close_p_in_button_scope();
+                if is_heading(self.current_tag_name()) {
+                    self.error("unexpected-start-tag");
+                    self.open_elements.pop();
+                }
+                self.insert_html_element(&tag);
+            }
+            "pre" | "listing" => {
+                self.close_p_in_button_scope();
+                self.insert_html_element(&tag);
+                self.ignore_lf = true;
+                self.frameset_ok = false;
+            }
+            "form" => {
+                let in_template = self.has_open_element("template");
+                if self.form.is_some() && !in_template {
+                    self.error("unexpected-start-tag");
+                    return;
+                }
+                self.close_p_in_button_scope();
+                let form = self.insert_html_element(&tag);
+                // a form inside a template is not associated with the document
+                if !in_template {
+                    self.form = Some(form);
+                }
+            }
+            "li" => self.start_list_item(&tag, &["li"]),
+            "dd" | "dt" => self.start_list_item(&tag, &["dd", "dt"]),
+            "plaintext" => {
+                self.close_p_in_button_scope();
+                self.insert_html_element(&tag);
+                self.tokenizer_state = Some(tokenizer::State::PlainText);
+            }
+            "button" => {
+                if self.in_scope("button", Scope::Default) {
+                    self.error("unexpected-start-tag-implies-end-tag");
+                    self.generate_implied_end_tags(None);
+                    self.pop_until("button");
+                }
+                self.reconstruct_active_formatting_elements();
+                self.insert_html_element(&tag);
+                self.frameset_ok = false;
+            }
+            "a" => {
+                if let Some(a) = self.formatting_element_after_marker("a") {
+                    self.error("unexpected-start-tag-implies-end-tag");
+                    self.adoption_agency("a");
+                    self.remove_from_active_formatting(a);
+                    self.remove_from_stack(a);
+                }
+                self.reconstruct_active_formatting_elements();
+                let id = self.insert_html_element(&tag);
+                self.push_active_formatting(id, tag);
+            }
+            "b" | "big" | "code" | "em" | "font" | "i" | "s" | "small" | "strike" | "strong" | "tt" | "u" => {
+                self.reconstruct_active_formatting_elements();
+                let id = self.insert_html_element(&tag);
+                self.push_active_formatting(id, tag);
+            }
+            "nobr" => {
+                self.reconstruct_active_formatting_elements();
+                if self.in_scope("nobr", Scope::Default) {
+                    self.error("unexpected-start-tag-implies-end-tag");
+                    self.adoption_agency("nobr");
+                    self.reconstruct_active_formatting_elements();
+                }
+                let id = self.insert_html_element(&tag);
+                self.push_active_formatting(id, tag);
+            }
+            "applet" | "marquee" | "object" => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_html_element(&tag);
+                self.active_formatting.push(FormattingEntry::Marker);
+                self.frameset_ok = false;
+            }
+            "table" => {
+                if self.document.mode != dom::QuirksMode::Quirks {
+                    self.close_p_in_button_scope();
+                }
+                self.insert_html_element(&tag);
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InTable;
+            }
+            "area" | "br" | "embed" | "img" | "keygen" | "wbr" | "input" => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_html_element(&tag);
+                self.open_elements.pop();
+                if !is_hidden_input(&tag) {
+                    self.frameset_ok = false;
+                }
+            }
+            "param" | "source" | "track" => {
+                self.insert_html_element(&tag);
+                self.open_elements.pop();
+            }
+            "hr" => {
+                self.close_p_in_button_scope();
+                self.insert_html_element(&tag);
+                self.open_elements.pop();
+                self.frameset_ok = false;
+            }
+            "image" => {
+                self.error("unexpected-start-tag");
+                tag.name = "img".to_string();
+                self.in_body_start_tag(tag);
+            }
+            "textarea" => {
+                self.parse_text_element(&tag, tokenizer::State::RcData);
+                self.ignore_lf = true;
+                self.frameset_ok = false;
+            }
+            "xmp" => {
+                self.close_p_in_button_scope();
+                self.reconstruct_active_formatting_elements();
+                self.frameset_ok = false;
+                self.parse_text_element(&tag, tokenizer::State::RawText);
+            }
+            "iframe" => {
+                self.frameset_ok = false;
+                self.parse_text_element(&tag, tokenizer::State::RawText);
+            }
+            "noembed" => self.parse_text_element(&tag, tokenizer::State::RawText),
+            "select" => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_html_element(&tag);
+                self.frameset_ok = false;
+                self.mode = match self.mode {
+                    InsertionMode::InTable
+                    | InsertionMode::InCaption
+                    | InsertionMode::InTableBody
+                    | InsertionMode::InRow
+                    | InsertionMode::InCell => InsertionMode::InSelectInTable,
+                    _ => InsertionMode::InSelect,
+                };
+            }
+            "optgroup" | "option" => {
+                if self.current_tag_name() == "option" {
+                    self.open_elements.pop();
+                }
+                self.reconstruct_active_formatting_elements();
+                self.insert_html_element(&tag);
+            }
+            "rb" | "rtc" => {
+                if self.in_scope("ruby", Scope::Default) {
+                    self.generate_implied_end_tags(None);
+                }
+                self.insert_html_element(&tag);
+            }
+            "rp" | "rt" => {
+                if self.in_scope("ruby", Scope::Default) {
+                    self.generate_implied_end_tags(Some("rtc"));
+                }
+                self.insert_html_element(&tag);
+            }
+            "math" => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_foreign_element(tag, Namespace::MathMl);
+            }
+            "svg" => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_foreign_element(tag, Namespace::Svg);
+            }
+            "caption" | "col" | "colgroup" | "frame" | "head" | "tbody" | "td" | "tfoot" | "th" | "thead"
+            | "tr" => self.error("unexpected-start-tag"),
+            _ => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_html_element(&tag);
+            }
+        }
+    }
+
+    fn in_body_end_tag(&mut self, tag: Tag) {
+        let name = tag.name.as_str();
+        match name {
+            "template" => self.in_head(Token::EndTag(tag)),
+            "body" => {
+                if self.in_scope("body", Scope::Default) {
+                    self.mode = InsertionMode::AfterBody;
+                } else {
+                    self.error("unexpected-end-tag");
+                }
+            }
+            "html" => {
+                if self.in_scope("body", Scope::Default) {
+                    self.reprocess_in(InsertionMode::AfterBody, Token::EndTag(tag));
+                } else {
+                    self.error("unexpected-end-tag");
+                }
+            }
+            "address" | "article" | "aside" | "blockquote" | "button" | "center" | "details" | "dialog"
+            | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer" | "header" | "hgroup"
+            | "listing" | "main" | "menu" | "nav" | "ol" | "pre" | "search" | "section" | "summary" | "ul" => {
+                if self.in_scope(name, Scope::Default) {
+                    self.generate_implied_end_tags(None);
+                    self.check_current_node(name);
+                    self.pop_until(name);
+                } else {
+                    self.error("unexpected-end-tag");
+                }
+            }
+            "form" if self.has_open_element("template") => {
+                if self.in_scope("form", Scope::Default) {
+                    self.generate_implied_end_tags(None);
+                    self.check_current_node("form");
+                    self.pop_until("form");
+                } else {
+                    self.error("unexpected-end-tag");
+                }
+            }
+            "form" => {
+                let form = self.form.take();
+                let Some(form) = form.filter(|&form| self.node_in_scope(form)) else {
+                    self.error("unexpected-end-tag");
+                    return;
+                };
+                self.generate_implied_end_tags(None);
+                if self.open_elements.last() != Some(&form) {
+                    self.error("end-tag-too-early");
+                }
+                self.remove_from_stack(form);
+            }
+            "p" => {
+                if !self.in_scope("p", Scope::Button) {
+                    self.error("unexpected-end-tag");
+                    self.insert_html_element(&implied_tag("p"));
+                }
+                self.close_p_in_button_scope();
+            }
+            "li" => {
+                if self.in_scope("li", Scope::ListItem) {
+                    self.generate_implied_end_tags(Some("li"));
+                    self.check_current_node("li");
+                    self.pop_until("li");
+                } else {
+                    self.error("unexpected-end-tag");
+                }
+            }
+            "dd" | "dt" => {
+                if self.in_scope(name, Scope::Default) {
+                    self.generate_implied_end_tags(Some(name));
+                    self.check_current_node(name);
+                    self.pop_until(name);
+                } else {
+                    self.error("unexpected-end-tag");
+                }
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let in_scope = ["h1", "h2", "h3", "h4", "h5", "h6"]
+                    .iter()
+                    .any(|heading| self.in_scope(heading, Scope::Default));
+                if !in_scope {
+                    self.error("unexpected-end-tag");
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                self.check_current_node(name);
+                while let Some(node) = self.open_elements.pop() {
+                    if is_heading(self.tag_name(node)) {
+                        break;
+                    }
+                }
+            }
+            "a" | "b" | "big" | "code" | "em" | "font" | "i" | "nobr" | "s" | "small" | "strike" | "strong"
+            | "tt" | "u" => {
+                if !self.adoption_agency(name) {
+                    self.any_other_end_tag(name);
+                }
+            }
+            "applet" | "marquee" | "object" => {
+                if self.in_scope(name, Scope::Default) {
+                    self.generate_implied_end_tags(None);
+                    self.check_current_node(name);
+                    self.pop_until(name);
+                    self.clear_active_formatting_to_marker();
+                } else {
+                    self.error("unexpected-end-tag");
+                }
+            }
+            "br" => {
+                self.error("unexpected-end-tag");
+                self.in_body_start_tag(implied_tag("br"));
+            }
+            _ => self.any_other_end_tag(name),
+        }
+    }
+
+    fn any_other_end_tag(&mut self, name: &str) {
+        for index in (0..self.open_elements.len()).rev() {
+            let node = self.open_elements[index];
+            if self.tag_name(node) == name {
+                self.generate_implied_end_tags(Some(name));
+                self.check_current_node(name);
+                self.open_elements.truncate(index);
+                return;
+            }
+            if self.is_special(node) {
+                self.error("unexpected-end-tag");
+                return;
+            }
+        }
+    }
+
+    fn start_list_item(&mut self, tag: &Tag, names: &[&str]) {
+        self.frameset_ok = false;
+        for index in (0..self.open_elements.len()).rev() {
+            let node_name = self.tag_name(self.open_elements[index]).to_string();
+            if names.contains(&node_name.as_str()) {
+                self.generate_implied_end_tags(Some(&node_name));
+                self.pop_until(&node_name);
+                break;
+            }
+            if is_special(&node_name) && !matches!(node_name.as_str(), "address" | "div" | "p") {
+                break;
+            }
+        }
+        self.close_p_in_button_scope();
+        self.insert_html_element(tag);
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#generic-raw-text-element-parsing-algorithm
+    fn parse_text_element(&mut self, tag: &Tag, state: tokenizer::State) {
+        self.insert_html_element(tag);
+        self.tokenizer_state = Some(state);
+        self.original_mode = self.mode;
+        self.mode = InsertionMode::Text;
+    }
+
+// ---------------------
+// tables
+// ---------------------
+
+    fn in_table(&mut self, token: Token) {
+        match token {
+            Token::Character(_)
+                if matches!(self.current_tag_name(), "table" | "tbody" | "template" | "tfoot" | "thead" | "tr") =>
+            {
+                self.pending_table_text.clear();
+                self.original_mode = self.mode;
+                self.reprocess_in(InsertionMode::InTableText, token);
+            }
+            Token::Comment(data) => self.insert_comment(data),
+            Token::StartTag(tag) => match tag.name.as_str() {
+                "caption" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.active_formatting.push(FormattingEntry::Marker);
+                    self.insert_html_element(&tag);
+                    self.mode = InsertionMode::InCaption;
+                }
+                "colgroup" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.insert_html_element(&tag);
+                    self.mode = InsertionMode::InColumnGroup;
+                }
+                "col" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.insert_html_element(&implied_tag("colgroup"));
+                    self.reprocess_in(InsertionMode::InColumnGroup, Token::StartTag(tag));
+                }
+                "tbody" | "tfoot" | "thead" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.insert_html_element(&tag);
+                    self.mode = InsertionMode::InTableBody;
+                }
+                "td" | "th" | "tr" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.insert_html_element(&implied_tag("tbody"));
+                    self.reprocess_in(InsertionMode::InTableBody, Token::StartTag(tag));
+                }
+                "table" => {
+                    self.error("unexpected-start-tag-implies-end-tag");
+                    if self.in_scope("table", Scope::Table) {
+                        self.pop_until("table");
+                        self.reset_insertion_mode();
+                        self.dispatch(Token::StartTag(tag));
+                    }
+                }
+                "style" | "script" | "template" => self.in_head(Token::StartTag(tag)),
+                "input" if is_hidden_input(&tag) => {
+                    self.error("unexpected-start-tag");
+                    self.insert_html_element(&tag);
+                    self.open_elements.pop();
+                }
+                "form" => {
+                    self.error("unexpected-start-tag");
+                    if self.form.is_none() && !self.has_open_element("template") {
+                        self.form = Some(self.insert_html_element(&tag));
+                        self.open_elements.pop();
+                    }
+                }
+                _ => self.anything_else_in_table(Token::StartTag(tag)),
+            },
+            Token::EndTag(tag) => match tag.name.as_str() {
+                "table" => {
+                    if self.in_scope("table", Scope::Table) {
+                        self.pop_until("table");
+                        self.reset_insertion_mode();
+                    } else {
+                        self.error("unexpected-end-tag");
+                    }
+                }
+                "body" | "caption" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot" | "th" | "thead"
+                | "tr" => self.error("unexpected-end-tag"),
+                "template" => self.in_head(Token::EndTag(tag)),
+                _ => self.anything_else_in_table(Token::EndTag(tag)),
+            },
+            Token::Eof => self.in_body(token),
+            token => self.anything_else_in_table(token),
+        }
+    }
+
+    fn anything_else_in_table(&mut self, token: Token) {
+        self.error("foster-parenting");
+        self.foster_parenting = true;
+        self.in_body(token);
+        self.foster_parenting = false;
+    }
+
+    fn in_table_text(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => self.error("unexpected-null-character"),
+            Token::Character(c) => {
+                self.pending_table_text_span = if self.pending_table_text.is_empty() {
+                    self.span
+                } else {
+                    self.pending_table_text_span.to(self.span)
+                };
+                self.pending_table_text.push(c);
+            }
+            token => {
+                let text = std::mem::take(&mut self.pending_table_text);
+                let span = std::mem::replace(&mut self.span, self.pending_table_text_span);
+                if text.chars().all(is_whitespace) {
+                    for c in text.chars() {
+                        self.insert_char(c);
+                    }
+                } else {
+                    // one error for the whole run rather than one per character
+                    self.error("foster-parenting");
+                    self.foster_parenting = true;
+                    for c in text.chars() {
+                        self.in_body(Token::Character(c));
+                    }
+                    self.foster_parenting = false;
+                }
+                self.span = span;
+                self.reprocess_in(self.original_mode, token);
+            }
+        }
+    }
+
+    fn in_caption(&mut self, token: Token) {
+        match token {
+            Token::EndTag(ref tag) if tag.name == "caption" => {
+                if !self.close_caption() {
+                    self.error("unexpected-end-tag");
+                }
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "caption" | "col" | "colgroup" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+                ) =>
+            {
+                if self.close_caption() {
+                    self.dispatch(token);
+                } else {
+                    self.error("unexpected-start-tag");
+                }
+            }
+            Token::EndTag(ref tag) if tag.name == "table" => {
+                if self.close_caption() {
+                    self.dispatch(token);
+                } else {
+                    self.error("unexpected-end-tag");
+                }
+            }
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "body" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+                ) =>
+            {
+                self.error("unexpected-end-tag")
+            }
+            token => self.in_body(token),
+        }
+    }
+
+    fn close_caption(&mut self) -> bool {
+        if !self.in_scope("caption", Scope::Table) {
+            return false;
+        }
+        self.generate_implied_end_tags(None);
+        self.check_current_node("caption");
+        self.pop_until("caption");
+        self.clear_active_formatting_to_marker();
+        self.mode = InsertionMode::InTable;
+        true
+    }
+
+    fn in_column_group(&mut self, token: Token) {
+        match token {
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::Character(c) if is_whitespace(c) => self.insert_char(c),
+            Token::Comment(data) => self.insert_comment(data),
+            Token::StartTag(ref tag) if tag.name == "html" => self.in_body(token),
+            Token::StartTag(ref tag) if tag.name == "col" => {
+                self.insert_html_element(tag);
+                self.open_elements.pop();
+            }
+            Token::EndTag(ref tag) if tag.name == "colgroup" => {
+                if self.current_tag_name() == "colgroup" {
+                    self.open_elements.pop();
+                    self.mode = InsertionMode::InTable;
+                } else {
+                    self.error("unexpected-end-tag");
+                }
+            }
+            Token::EndTag(ref tag) if tag.name == "col" => self.error("unexpected-end-tag"),
+            Token::StartTag(ref tag) if tag.name == "template" => self.in_head(token),
+            Token::EndTag(ref tag) if tag.name == "template" => self.in_head(token),
+            Token::Eof => self.in_body(token),
+            token => {
+                if self.current_tag_name() == "colgroup" {
+                    self.open_elements.pop();
+                    self.reprocess_in(InsertionMode::InTable, token);
+                } else {
+                    self.error("unexpected-token-in-column-group");
+                }
+            }
+        }
+    }
+
+    fn in_table_body(&mut self, token: Token) {
+        const TABLE_BODY_CONTEXT: [&str; 5] = ["tbody", "tfoot", "thead", "template", "html"];
+        match token {
+            Token::StartTag(ref tag) if tag.name == "tr" => {
+                self.clear_stack_back_to(&TABLE_BODY_CONTEXT);
+                self.insert_html_element(tag);
+                self.mode = InsertionMode::InRow;
+            }
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(), "th" | "td") => {
+                self.error("unexpected-cell-in-table-body");
+                self.clear_stack_back_to(&TABLE_BODY_CONTEXT);
+                self.insert_html_element(&implied_tag("tr"));
+                self.reprocess_in(InsertionMode::InRow, token);
+            }
+            Token::EndTag(ref tag) if matches!(tag.name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if self.in_scope(&tag.name, Scope::Table) {
+                    self.clear_stack_back_to(&TABLE_BODY_CONTEXT);
+                    self.open_elements.pop();
+                    self.mode = InsertionMode::InTable;
+                } else {
+                    self.error("unexpected-end-tag");
+                }
+            }
+            Token::StartTag(ref tag)
+                if matches!(tag.name.as_str(), "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead") =>
+            {
+                self.close_table_body(token);
+            }
+            Token::EndTag(ref tag) if tag.name == "table" => self.close_table_body(token),
+            Token::EndTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th" | "tr"
+                ) =>
+            {
+                self.error("unexpected-end-tag")
+            }
+            token => self.in_table(token),
+        }
+    }
+
+    fn close_table_body(&mut self, token: Token) {
+        let in_scope = ["tbody", "thead", "tfoot"]
+            .iter()
+            .any(|name| self.in_scope(name, Scope::Table));
+        if in_scope {
+            self.clear_stack_back_to(&["tbody", "tfoot", "thead", "template", "html"]);
+            self.open_elements.pop();
+            self.reprocess_in(InsertionMode::InTable, token);
+        } else {
+            self.error("unexpected-token-in-table-body");
+        }
+    }
+
+    fn in_row(&mut self, token: Token) {
+        match token {
+            Token::StartTag(ref tag) if matches!(tag.name.as_str(), "th" | "td") => {
+                self.clear_stack_back_to(&["tr", "template", "html"]);
+                self.insert_html_element(tag);
+                self.mode = InsertionMode::InCell;
+                self.active_formatting.push(FormattingEntry::Marker);
+            }
+            Token::EndTag(ref tag) if tag.name == "tr" => {
+                if !self.close_row() {
+                    self.error("unexpected-end-tag");
+                }
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead" | "tr"
+                ) =>
+            {
+                if self.close_row() {
+                    self.dispatch(token);
+                } else {
+                    self.error("unexpected-start-tag");
+                }
+            }
+            Token::EndTag(ref tag) if tag.name == "table" => {
+                if self.close_row() {
+                    self.dispatch(token);
+                } else {
+                    self.error("unexpected-end-tag");
+                }
+            }
+            Token::EndTag(ref tag) if matches!(tag.name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if self.in_scope(&tag.name, Scope::Table) && self.close_row() {
+                    self.dispatch(token);
+                } else {
+                    self.error("unexpected-end-tag");
+                }
+            }
+            Token::EndTag(ref tag)
+                if matches!(tag.name.as_str(), "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th") =>
+            {
+                self.error("unexpected-end-tag")
+            }
+            token => self.in_table(token),
+        }
+    }
+
+    fn close_row(&mut self) -> bool {
+        if !self.in_scope("tr", Scope::Table) {
+            return false;
+        }
+        self.clear_stack_back_to(&["tr", "template", "html"]);
+        self.open_elements.pop();
+        self.mode = InsertionMode::InTableBody;
+        true
+    }
+
+    fn in_cell(&mut self, token: Token) {
+        match token {
+            Token::EndTag(ref tag) if matches!(tag.name.as_str(), "td" | "th") => {
+                if self.in_scope(&tag.name, Scope::Table) {
+                    self.generate_implied_end_tags(None);
+                    self.check_current_node(&tag.name);
+                    self.pop_until(&tag.name);
+                    self.clear_active_formatting_to_marker();
+                    self.mode = InsertionMode::InRow;
+                } else {
+                    self.error("unexpected-end-tag");
+                }
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_str(),
+                    "caption" | "col" | "colgroup" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+                ) =>
+            {
+                if self.in_scope("td", Scope::Table) || self.in_scope("th", Scope::Table) {
+                    self.close_cell();
+                    self.dispatch(token);
+                } else {
+                    self.error("unexpected-start-tag");
+                }
+            }
+            Token::EndTag(ref tag) if matches!(tag.name.as_str(), "body" | "caption" | "col" | "colgroup" | "html") => {
+                self.error("unexpected-end-tag")
+            }
+            Token::EndTag(ref tag) if matches!(tag.name.as_str(), "table" | "tbody" | "tfoot" | "thead" | "tr") => {
+                if self.in_scope(&tag.name, Scope::Table) {
+                    self.close_cell();
+                    self.dispatch(token);
+                } else {
+                    self.error("unexpected-end-tag");
+                }
+            }
+            token => self.in_body(token),
+        }
+    }
+
+    fn close_cell(&mut self) {
+        self.generate_implied_end_tags(None);
+        if !matches!(self.current_tag_name(), "td" | "th") {
+            self.error("end-tag-too-early");
+        }
+        while let Some(node) = self.open_elements.pop() {
+            if matches!(self.tag_name(node), "td" | "th") {
+                break;
+            }
+        }
+        self.clear_active_formatting_to_marker();
+        self.mode = InsertionMode::InRow;
+    }
+
+// ---------------------
+// select
+// ---------------------
+
+    fn in_select(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => self.error("unexpected-null-character"),
+            Token::Character(c) => self.insert_char(c),
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(tag) => match tag.name.as_str() {
+                "html" => self.in_body(Token::StartTag(tag)),
+                "option" => {
+                    if self.current_tag_name() == "option" {
+                        self.open_elements.pop();
+                    }
+                    self.insert_html_element(&tag);
+                }
+                "optgroup" => {
+                    if self.current_tag_name() == "option" {
+                        self.open_elements.pop();
+                    }
+                    if self.current_tag_name() == "optgroup" {
+                        self.open_elements.pop();
+                    }
+                    self.insert_html_element(&tag);
+                }
+                "hr" => {
+                    if self.current_tag_name() == "option" {
+                        self.open_elements.pop();
+                    }
+                    if self.current_tag_name() == "optgroup" {
+                        self.open_elements.pop();
+                    }
+                    self.insert_html_element(&tag);
+                    self.open_elements.pop();
+                }
+                "select" => {
+                    self.error("unexpected-start-tag-implies-end-tag");
+                    if self.in_scope("select", Scope::Select) {
+                        self.pop_until("select");
+                        self.reset_insertion_mode();
+                    }
+                }
+                "input" | "keygen" | "textarea" if self.in_scope("select", Scope::Select) => {
+                    self.error("unexpected-start-tag-implies-end-tag");
+                    self.pop_until("select");
+                    self.reset_insertion_mode();
+                    self.dispatch(Token::StartTag(tag));
+                }
+                "script" | "template" => self.in_head(Token::StartTag(tag)),
+                _ => self.error("unexpected-start-tag"),
+            },
+            Token::EndTag(tag) => match tag.name.as_str() {
+                "optgroup" => {
+                    let len = self.open_elements.len();
+                    if self.current_tag_name() == "option"
+                        && len > 1
+                        && self.tag_name(self.open_elements[len - 2]) == "optgroup"
+                    {
+                        self.open_elements.pop();
+                    }
+                    if self.current_tag_name() == "optgroup" {
+                        self.open_elements.pop();
+                    } else {
+                        self.error("unexpected-end-tag");
+                    }
+                }
+                "option" if self.current_tag_name() == "option" => {
+                    self.open_elements.pop();
+                }
+                "select" if self.in_scope("select", Scope::Select) => {
+                    self.pop_until("select");
+                    self.reset_insertion_mode();
+                }
+                "template" => self.in_head(Token::EndTag(tag)),
+                _ => self.error("unexpected-end-tag"),
+            },
+            Token::Eof => self.in_body(token),
+        }
+    }
+
+    fn in_select_in_table(&mut self, token: Token) {
+        const TABLE_TAGS: [&str; 8] = ["caption", "table", "tbody", "tfoot", "thead", "tr", "td", "th"];
+        match token {
+            Token::StartTag(ref tag) if TABLE_TAGS.contains(&tag.name.as_str()) => {
+                self.error("unexpected-start-tag-implies-end-tag");
+                self.pop_until("select");
+                self.reset_insertion_mode();
+                self.dispatch(token);
+            }
+            Token::EndTag(ref tag) if TABLE_TAGS.contains(&tag.name.as_str()) => {
+                self.error("unexpected-end-tag");
+                if self.in_scope(&tag.name, Scope::Table) {
+                    self.pop_until("select");
+                    self.reset_insertion_mode();
+                    self.dispatch(token);
+                }
+            }
+            token => self.in_select(token),
+        }
+    }
+
+// ---------------------
+// templates
+// ---------------------
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intemplate
+    fn in_template(&mut self, token: Token) {
+        match token {
+            Token::Character(_) | Token::Comment(_) | Token::Doctype(_) => self.in_body(token),
+            Token::StartTag(ref tag) => match tag.name.as_str() {
+                "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style" | "template"
+                | "title" => self.in_head(token),
+                // the first table part decides what the template is parsed as
+                "caption" | "colgroup" | "tbody" | "tfoot" | "thead" => {
+                    self.switch_template_mode(InsertionMode::InTable, token)
+                }
+                "col" => self.switch_template_mode(InsertionMode::InColumnGroup, token),
+                "tr" => self.switch_template_mode(InsertionMode::InTableBody, token),
+                "td" | "th" => self.switch_template_mode(InsertionMode::InRow, token),
+                _ => self.switch_template_mode(InsertionMode::InBody, token),
+            },
+            Token::EndTag(ref tag) if tag.name == "template" => self.in_head(token),
+            Token::EndTag(_) => self.error("unexpected-end-tag"),
+            Token::Eof => {
+                // a fragment parsed with a template as context has none open
+                if !self.has_open_element("template") {
+                    return;
+                }
+                self.error("eof-in-element");
+                self.close_template();
+                self.process_in(self.mode, token);
+            }
+        }
+    }
+
+    fn switch_template_mode(&mut self, mode: InsertionMode, token: Token) {
+        self.template_modes.pop();
+        self.template_modes.push(mode);
+        self.reprocess_in(mode, token);
+    }
+
+    // the end tags have been dealt with by the caller
+    fn close_template(&mut self) {
+        self.pop_until("template");
+        self.clear_active_formatting_to_marker();
+        self.template_modes.pop();
+        self.reset_insertion_mode();
+    }
+
+// ---------------------
+// foreign content
+// ---------------------
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inforeign
+    fn foreign_content(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => {
+                self.error("unexpected-null-character");
+                self.insert_char('\u{FFFD}');
+            }
+            Token::Character(c) => self.insert_char(c),
+            Token::Comment(data) => self.insert_comment(data),
+            Token::Doctype(_) => self.error("unexpected-doctype"),
+            Token::StartTag(tag) if breaks_out_of_foreign_content(&tag) => {
+                self.error("unexpected-html-element-in-foreign-content");
+                self.pop_foreign_elements();
+                self.process_in(self.mode, Token::StartTag(tag));
+            }
+            Token::EndTag(tag) if tag.name == "br" || tag.name == "p" => {
+                self.error("unexpected-html-element-in-foreign-content");
+                self.pop_foreign_elements();
+                self.process_in(self.mode, Token::EndTag(tag));
+            }
+            Token::StartTag(mut tag) => {
+                let namespace = self.adjusted_current_node().unwrap().namespace;
+                if namespace == Namespace::Svg && let Some(name) = foreign::svg_tag_name(&tag.name) {
+                    tag.name = name.to_string();
+                }
+                self.insert_foreign_element(tag, namespace);
+            }
+            Token::EndTag(tag) => self.foreign_end_tag(tag),
+            Token::Eof => self.process_in(self.mode, Token::Eof),
+        }
+    }
+
+    fn foreign_end_tag(&mut self, tag: Tag) {
+        let mut index = self.open_elements.len() - 1;
+        if self.element(self.open_elements[index]).tag.to_ascii_lowercase() != tag.name {
+            self.error("unexpected-end-tag");
+        }
+        loop {
+            // the context element of a fragment is never popped
+            if index == 0 {
+                return;
+            }
+            if self.element(self.open_elements[index]).tag.to_ascii_lowercase() == tag.name {
+                self.open_elements.truncate(index);
+                return;
+            }
+            index -= 1;
+            if self.element(self.open_elements[index]).is_html() {
+                self.process_in(self.mode, Token::EndTag(tag));
+                return;
+            }
+        }
+    }
+
+    // html tags inside svg or math close the foreign elements around them
+    fn pop_foreign_elements(&mut self) {
+        while let Some(&node) = self.open_elements.last() {
+            let elem = self.element(node);
+            if elem.is_html() || is_mathml_text_integration_point(elem) || is_html_integration_point(elem) {
+                break;
+            }
+            self.open_elements.pop();
+        }
+    }
+
+// ---------------------
+// stack of open elements
+// ---------------------
+
+    fn reset_insertion_mode(&mut self) {
+        for index in (0..self.open_elements.len()).rev() {
+            let last = index == 0;
+            let node = self.open_elements[index];
+            // in a fragment the context element stands in for the root
+            let name = match &self.context {
+                Some(context) if last && context.is_html() => context.tag.as_str(),
+                Some(_) if last => "",
+                _ => self.tag_name(node),
+            };
+            let mode = match name {
+                "select" => {
+                    let in_table = self.open_elements[..index]
+                        .iter()
+                        .rev()
+                        .take_while(|&&ancestor| self.tag_name(ancestor) != "template")
+                        .any(|&ancestor| self.tag_name(ancestor) == "table");
+                    if in_table { InsertionMode::InSelectInTable } else { InsertionMode::InSelect }
+                }
+                "td" | "th" if !last => InsertionMode::InCell,
+                "tr" => InsertionMode::InRow,
+                "tbody" | "thead" | "tfoot" => InsertionMode::InTableBody,
+                "caption" => InsertionMode::InCaption,
+                "colgroup" => InsertionMode::InColumnGroup,
+                "table" => InsertionMode::InTable,
+                "template" => *self.template_modes.last().unwrap_or(&InsertionMode::InTemplate),
+                "head" if !last => InsertionMode::InHead,
+                "body" => InsertionMode::InBody,
+                "frameset" => InsertionMode::InFrameset,
+                "html" => {
+                    if self.head.is_none() { InsertionMode::BeforeHead } else { InsertionMode::AfterHead }
+                }
+                _ if last => InsertionMode::InBody,
+                _ => continue,
+            };
+            self.mode = mode;
+            return;
+        }
+    }
+
+    fn in_scope(&self, target: &str, scope: Scope) -> bool {
+        for &node in self.open_elements.iter().rev() {
+            if self.tag_name(node) == target {
+                return true;
+            }
+            if self.is_scope_boundary(node, scope) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn node_in_scope(&self, target: NodeId) -> bool {
+        for &node in self.open_elements.iter().rev() {
+            if node == target {
+                return true;
+            }
+            if self.is_scope_boundary(node, Scope::Default) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn is_scope_boundary(&self, node: NodeId, scope: Scope) -> bool {
+        let foreign_boundary = matches!(scope, Scope::Default | Scope::ListItem | Scope::Button)
+            && is_foreign_boundary(self.element(node));
+        is_scope_boundary(self.tag_name(node), scope) || foreign_boundary
+    }
+
+    fn is_special(&self, node: NodeId) -> bool {
+        is_special(self.tag_name(node)) || is_foreign_boundary(self.element(node))
+    }
+
+    fn has_open_element(&self, name: &str) -> bool {
+        self.open_elements.iter().any(|&node| self.tag_name(node) == name)
+    }
+
+    fn pop_until(&mut self, name: &str) {
+        while let Some(node) = self.open_elements.pop() {
+            if self.tag_name(node) == name {
+                break;
+            }
+        }
+    }
+
+    fn clear_stack_back_to(&mut self, names: &[&str]) {
+        while !names.contains(&self.current_tag_name()) {
+            self.open_elements.pop();
+        }
+    }
+
+    fn remove_from_stack(&mut self, node: NodeId) {
+        self.open_elements.retain(|&open| open != node);
+    }
+
+    fn generate_implied_end_tags(&mut self, except: Option<&str>) {
+        loop {
+            let name = self.current_tag_name();
+            if Some(name) == except
+                || !matches!(name, "dd" | "dt" | "li" | "optgroup" | "option" | "p" | "rb" | "rp" | "rt" | "rtc")
+            {
+                break;
+            }
+            self.open_elements.pop();
+        }
+    }
+
+    // also closes what is left of a table inside a <template>
+    fn generate_all_implied_end_tags(&mut self) {
+        while matches!(
+            self.current_tag_name(),
+            "caption" | "colgroup" | "dd" | "dt" | "li" | "optgroup" | "option" | "p" | "rb" | "rp" | "rt" | "rtc"
+                | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+        ) {
+            self.open_elements.pop();
+        }
+    }
+
+    fn close_p_in_button_scope(&mut self) {
+        if self.in_scope("p", Scope::Button) {
+            self.generate_implied_end_tags(Some("p"));
+            self.pop_until("p");
+        }
+    }
+
+// ---------------------
+// active formatting elements
+// ---------------------
+
+    fn push_active_formatting(&mut self, node: NodeId, tag: Tag) {
+        // Noah's Ark clause: at most three identical entries after the last marker
+        let mut same = Vec::new();
+        for (index, entry) in self.active_formatting.iter().enumerate().rev() {
+            match entry {
+                FormattingEntry::Marker => break,
+                FormattingEntry::Element(_, other) => {
+                    if other.name == tag.name && same_attributes(other, &tag) {
+                        same.push(index);
+                    }
+                }
+            }
+        }
+        if same.len() >= 3 {
+            self.active_formatting.remove(*same.last().unwrap());
+        }
+        self.active_formatting.push(FormattingEntry::Element(node, tag));
+    }
+
+    fn reconstruct_active_formatting_elements(&mut self) {
+        let Some(FormattingEntry::Element(last, _)) = self.active_formatting.last() else {
+            return;
+        };
+        if self.open_elements.contains(last) {
+            return;
+        }
+
+        // rewind to the entry after the last marker or open element
+        let mut index = self.active_formatting.len() - 1;
+        while index > 0 {
+            match &self.active_formatting[index - 1] {
+                FormattingEntry::Marker => break,
+                FormattingEntry::Element(node, _) if self.open_elements.contains(node) => break,
+                _ => index -= 1,
+            }
+        }
+
+        // advance and create
+        for index in index..self.active_formatting.len() {
+            let FormattingEntry::Element(old, tag) = &self.active_formatting[index] else {
+                continue;
+            };
+            let (old, tag) = (*old, tag.clone());
+            let node = self.insert_html_element(&tag);
+            self.copy_span(old, node);
+            self.active_formatting[index] = FormattingEntry::Element(node, tag);
+        }
+    }
+
+    fn clear_active_formatting_to_marker(&mut self) {
+        while let Some(entry) = self.active_formatting.pop() {
+            if let FormattingEntry::Marker = entry {
+                break;
+            }
+        }
+    }
+
+    fn formatting_element_after_marker(&self, name: &str) -> Option<NodeId> {
+        for entry in self.active_formatting.iter().rev() {
+            match entry {
+                FormattingEntry::Marker => return None,
+                FormattingEntry::Element(node, tag) if tag.name == name => return Some(*node),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn formatting_index(&self, node: NodeId) -> Option<usize> {
+        self.active_formatting.iter()
+            .position(|entry| matches!(entry, FormattingEntry::Element(id, _) if *id == node))
+    }
+
+    fn remove_from_active_formatting(&mut self, node: NodeId) {
+        if let Some(index) = self.formatting_index(node) {
+            self.active_formatting.remove(index);
+        }
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#adoption-agency-algorithm
+    // returns false when the end tag should be handled as "any other end tag"
+    fn adoption_agency(&mut self, subject: &str) -> bool {
+        let current = *self.open_elements.last().unwrap();
+        if self.tag_name(current) == subject && self.formatting_index(current).is_none() {
+            self.open_elements.pop();
+            return true;
+        }
+
+        for _ in 0..8 {
+            let Some(formatting_element) = self.formatting_element_after_marker(subject) else {
+                return false;
+            };
+            let Some(fe_stack_index) = self.open_elements.iter().position(|&n| n == formatting_element) else {
+                self.error("unexpected-end-tag");
+                self.remove_from_active_formatting(formatting_element);
+                return true;
+            };
+            if !self.node_in_scope(formatting_element) {
+                self.error("unexpected-end-tag");
+                return true;
+            }
+            if self.open_elements.last() != Some(&formatting_element) {
+                self.error("misnested-tag");
+            }
+
+            let furthest_block = self.open_elements[fe_stack_index + 1..]
+                .iter()
+                .copied()
+                .find(|&node| self.is_special(node));
+            let Some(furthest_block) = furthest_block else {
+                self.open_elements.truncate(fe_stack_index);
+                self.remove_from_active_formatting(formatting_element);
+                return true;
+            };
+
+            let common_ancestor = self.open_elements[fe_stack_index - 1];
+            let mut bookmark = self.formatting_index(formatting_element).unwrap();
+
+            let mut node_index = self.open_elements.iter().position(|&n| n == furthest_block).unwrap();
+            let mut last_node = furthest_block;
+            let mut inner_counter = 0;
+            loop {
+                inner_counter += 1;
+                node_index -= 1;
+                let mut node = self.open_elements[node_index];
+                if node == formatting_element {
+                    break;
+                }
+
+                if inner_counter > 3 && let Some(index) = self.formatting_index(node) {
+                    self.active_formatting.remove(index);
+                    if index < bookmark {
+                        bookmark -= 1;
+                    }
+                }
+                let Some(af_index) = self.formatting_index(node) else {
+                    self.open_elements.remove(node_index);
+                    continue;
+                };
+
+                let FormattingEntry::Element(_, tag) = &self.active_formatting[af_index] else {
+                    unreachable!()
+                };
+                let tag = tag.clone();
+                let new_node = self.create_element(&tag);
+                self.copy_span(node, new_node);
+                self.active_formatting[af_index] = FormattingEntry::Element(new_node, tag);
+                self.open_elements[node_index] = new_node;
+                node = new_node;
+
+                if last_node == furthest_block {
+                    bookmark = af_index + 1;
+                }
+
+                self.document.append(node, last_node);
+                last_node = node;
+            }
+
+            let (parent, before) = self.appropriate_place(Some(common_ancestor));
+            self.document.insert(parent, last_node, before);
+
+            let FormattingEntry::Element(_, tag) = &self.active_formatting[self.formatting_index(formatting_element).unwrap()]
+            else {
+                unreachable!()
+            };
+            let tag = tag.clone();
+            let new_element = self.create_element(&tag);
+            self.copy_span(formatting_element, new_element);
+            let children: Vec<NodeId> = self.document.children(furthest_block).collect();
+            for child in children {
+                self.document.append(new_element, child);
+            }
+            self.document.append(furthest_block, new_element);
+
+            let fe_index = self.formatting_index(formatting_element).unwrap();
+            self.active_formatting.remove(fe_index);
+            if fe_index < bookmark {
+                bookmark -= 1;
+            }
+            self.active_formatting.insert(bookmark, FormattingEntry::Element(new_element, tag));
+
+            self.remove_from_stack(formatting_element);
+            let fb_index = self.open_elements.iter().position(|&n| n == furthest_block).unwrap();
+            self.open_elements.insert(fb_index + 1, new_element);
+        }
+        true
+    }
+
+// ---------------------
+// insert nodes
+// ---------------------
+
+    fn create_element(&mut self, tag: &Tag) -> NodeId {
+        let attributes: HashMap<String, String> = tag.attributes.iter().cloned().collect();
+        let node = self.document.create_element(tag.name.clone(), attributes);
+        self.set_span(node);
+        self.set_attribute_locations(node, tag);
+        node
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#insert-a-foreign-element
+    fn insert_foreign_element(&mut self, mut tag: Tag, namespace: Namespace) {
+        for (name, _) in &mut tag.attributes {
+            let fixed = match namespace {
+                Namespace::Svg => foreign::svg_attribute_name(name),
+                Namespace::MathMl => foreign::mathml_attribute_name(name),
+                _ => None,
+            };
+            if let Some(fixed) = fixed {
+                *name = fixed.to_string();
+            }
+        }
+
+        let attributes: HashMap<String, String> = tag.attributes.iter().cloned().collect();
+        let node = self.document.create_element_ns(tag.name.clone(), namespace, attributes);
+        self.set_span(node);
+        self.set_attribute_locations(node, &tag);
+        let (parent, before) = self.appropriate_place(None);
+        self.document.insert(parent, node, before);
+        if !tag.self_closing {
+            self.open_elements.push(node);
+        }
+    }
+
+    fn insert_html_element(&mut self, tag: &Tag) -> NodeId {
+        let node = self.create_element(tag);
+        let (parent, before) = self.appropriate_place(None);
+        self.document.insert(parent, node, before);
+        self.open_elements.push(node);
+        node
+    }
+
+    fn insert_comment(&mut self, data: String) {
+        let (parent, before) = self.appropriate_place(None);
+        let node = self.document.create_comment(data);
+        self.set_span(node);
+        self.document.insert(parent, node, before);
+    }
+
+    fn append_comment_to(&mut self, parent: NodeId, data: String) {
+        let node = self.document.create_comment(data);
+        self.set_span(node);
+        self.document.append(parent, node);
+    }
+
+    fn insert_char(&mut self, c: char) {
+        let (parent, before) = self.appropriate_place(None);
+        if parent == self.document.root() {
+            return;
+        }
+
+        let previous = match before {
+            Some(before) => self.document.node(before).prev_sibling(),
+            None => self.document.node(parent).last_child(),
+        };
+        if let Some(previous) = previous
+            && let dom::NodeType::Text(text) = &mut self.document.node_mut(previous).node_type
+        {
+            text.push(c);
+            let node = self.document.node_mut(previous);
+            node.span = node.span.map(|span| span.to(self.span));
+            return;
+        }
+
+        let node = self.document.create_text(c.to_string());
+        self.set_span(node);
+        self.document.insert(parent, node, before);
+    }
+
+    fn set_span(&mut self, node: NodeId) {
+        self.document.node_mut(node).span = Some(self.span);
+    }
+
+    fn set_attribute_locations(&mut self, node: NodeId, tag: &Tag) {
+        let names = tag.attributes.iter().map(|(name, _)| name.clone());
+        self.document.node_mut(node).attribute_locations = names.zip(tag.value_locations.iter().copied()).collect();
+    }
+
+    // elements recreated for formatting keep the span of the tag that opened them
+    fn copy_span(&mut self, from: NodeId, to: NodeId) {
+        self.document.node_mut(to).span = self.document.node(from).span;
+        self.document.node_mut(to).attribute_locations = self.document.node(from).attribute_locations.clone();
+    }
+
+    fn merge_attributes(&mut self, node: NodeId, tag: Tag) {
+        let node = self.document.node_mut(node);
+        if let dom::NodeType::Element(elem) = &mut node.node_type {
+            for ((name, value), location) in tag.attributes.into_iter().zip(tag.value_locations) {
+                if let Entry::Vacant(entry) = elem.attributes.entry(name) {
+                    node.attribute_locations.insert(entry.key().clone(), location);
+                    entry.insert(value);
+                }
+            }
+        }
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#appropriate-place-for-inserting-a-node
+    // what goes into a template goes into its contents
+    fn appropriate_place(&self, override_target: Option<NodeId>) -> (NodeId, Option<NodeId>) {
+        let target = override_target.unwrap_or_else(|| *self.open_elements.last().unwrap());
+        if !self.foster_parenting || !matches!(self.tag_name(target), "table" | "tbody" | "tfoot" | "thead" | "tr") {
+            return (self.contents_of(target), None);
+        }
+
+        let last_template = self.open_elements.iter().rposition(|&n| self.tag_name(n) == "template");
+        let last_table = self.open_elements.iter().rposition(|&n| self.tag_name(n) == "table");
+        let table_index = match (last_template, last_table) {
+            (Some(template), table) if table.is_none_or(|table| template > table) => {
+                return (self.contents_of(self.open_elements[template]), None);
+            }
+            (_, Some(table)) => table,
+            (_, None) => return (self.open_elements[0], None),
+        };
+        let table = self.open_elements[table_index];
+        match self.document.node(table).parent() {
+            Some(parent) => (parent, Some(table)),
+            None => (self.contents_of(self.open_elements[table_index - 1]), None),
+        }
+    }
+
+    fn contents_of(&self, node: NodeId) -> NodeId {
+        self.document.node(node).template_contents().unwrap_or(node)
+    }
+
+// ---------------------
+// parse errors
+// ---------------------
+
+    // tree construction errors point at the start of the token
+    fn error(&mut self, code: &'static str) {
+        self.errors.push(ParseError { code, location: self.span.start });
+    }
+
+    // closing an element that still has open children
+    fn check_current_node(&mut self, name: &str) {
+        if self.current_tag_name() != name {
+            self.error("end-tag-too-early");
+        }
+    }
+
+// ---------------------
+// assistant functions
+// ---------------------
+
+    // only html elements answer to html tag names
+    fn tag_name(&self, node: NodeId) -> &str {
+        match self.document.node(node).as_element() {
+            Some(elem) if elem.is_html() => &elem.tag,
+            _ => "",
+        }
+    }
+
+    fn element(&self, node: NodeId) -> &dom::ElementData {
+        self.document.node(node).as_element().unwrap()
+    }
+
+    // https://html.spec.whatwg.org/multipage/parsing.html#adjusted-current-node
+    fn adjusted_current_node(&self) -> Option<&dom::ElementData> {
+        match &self.context {
+            Some(context) if self.open_elements.len() == 1 => Some(context),
+            _ => self.open_elements.last().map(|&node| self.element(node)),
+        }
+    }
+
+    fn current_tag_name(&self) -> &str {
+        self.open_elements.last().map_or("", |&node| self.tag_name(node))
+    }
+}
+
+// https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode
+fn quirks_mode(doctype: &Doctype) -> dom::QuirksMode {
+    const QUIRKS_PUBLIC_PREFIXES: [&str; 55] = [
+        "+//silmaril//dtd html pro v0r11 19970101//",
+        "-//as//dtd html 3.0 aswedit + extensions//",
+        "-//advasoft ltd//dtd html 3.0 aswedit + extensions//",
+        "-//ietf//dtd html 2.0 level 1//",
+        "-//ietf//dtd html 2.0 level 2//",
+        "-//ietf//dtd html 2.0 strict level 1//",
+        "-//ietf//dtd html 2.0 strict level 2//",
+        "-//ietf//dtd html 2.0 strict//",
+        "-//ietf//dtd html 2.0//",
+        "-//ietf//dtd html 2.1e//",
+        "-//ietf//dtd html 3.0//",
+        "-//ietf//dtd html 3.2 final//",
+        "-//ietf//dtd html 3.2//",
+        "-//ietf//dtd html 3//",
+        "-//ietf//dtd html level 0//",
+        "-//ietf//dtd html level 1//",
+        "-//ietf//dtd html level 2//",
+        "-//ietf//dtd html level 3//",
+        "-//ietf//dtd html strict level 0//",
+        "-//ietf//dtd html strict level 1//",
+        "-//ietf//dtd html strict level 2//",
+        "-//ietf//dtd html strict level 3//",
+        "-//ietf//dtd html strict//",
+        "-//ietf//dtd html//",
+        "-//metrius//dtd metrius presentational//",
+        "-//microsoft//dtd internet explorer 2.0 html strict//",
+        "-//microsoft//dtd internet explorer 2.0 html//",
+        "-//microsoft//dtd internet explorer 2.0 tables//",
+        "-//microsoft//dtd internet explorer 3.0 html strict//",
+        "-//microsoft//dtd internet explorer 3.0 html//",
+        "-//microsoft//dtd internet explorer 3.0 tables//",
+        "-//netscape comm. corp.//dtd html//",
+        "-//netscape comm. corp.//dtd strict html//",
+        "-//o'reilly and associates//dtd html 2.0//",
+        "-//o'reilly and associates//dtd html extended 1.0//",
+        "-//o'reilly and associates//dtd html extended relaxed 1.0//",
+        "-//sq//dtd html 2.0 hotmetal + extensions//",
+        "-//softquad software//dtd hotmetal pro 6.0::19990601::extensions to html 4.0//",
+        "-//softquad//dtd hotmetal pro 4.0::19971010::extensions to html 4.0//",
+        "-//spyglass//dtd html 2.0 extended//",
+        "-//sun microsystems corp.//dtd hotjava html//",
+        "-//sun microsystems corp.//dtd hotjava strict html//",
+        "-//w3c//dtd html 3 1995-03-24//",
+        "-//w3c//dtd html 3.2 draft//",
+        "-//w3c//dtd html 3.2 final//",
+        "-//w3c//dtd html 3.2//",
+        "-//w3c//dtd html 3.2s draft//",
+        "-//w3c//dtd html 4.0 frameset//",
+        "-//w3c//dtd html 4.0 transitional//",
+        "-//w3c//dtd html experimental 19960712//",
+        "-//w3c//dtd html experimental 970421//",
+        "-//w3c//dtd w3 html//",
+        "-//w3o//dtd w3 html 3.0//",
+        "-//webtechs//dtd mozilla html 2.0//",
+        "-//webtechs//dtd mozilla html//",
+    ];
+    const HTML4_PUBLIC_PREFIXES: [&str; 2] = [
+        "-//w3c//dtd html 4.01 frameset//",
+        "-//w3c//dtd html 4.01 transitional//",
+    ];
+    const XHTML_PUBLIC_PREFIXES: [&str; 2] = [
+        "-//w3c//dtd xhtml 1.0 frameset//",
+        "-//w3c//dtd xhtml 1.0 transitional//",
+    ];
+
+    let public_id = doctype.public_id.as_deref().unwrap_or("").to_ascii_lowercase();
+    let system_id = doctype.system_id.as_deref().map(|id| id.to_ascii_lowercase());
+    let starts_with_any = |prefixes: &[&str]| prefixes.iter().any(|prefix| public_id.starts_with(prefix));
+
+    if doctype.force_quirks
+        || doctype.name.as_deref() != Some("html")
+        || matches!(public_id.as_str(), "-//w3o//dtd w3 html strict 3.0//en//" | "-/w3c/dtd html 4.0 transitional/en" | "html")
+        || system_id.as_deref() == Some("http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd")
+        || starts_with_any(&QUIRKS_PUBLIC_PREFIXES)
+        || (system_id.is_none() && starts_with_any(&HTML4_PUBLIC_PREFIXES))
+    {
+        dom::QuirksMode::Quirks
+    } else if starts_with_any(&XHTML_PUBLIC_PREFIXES)
+        || (system_id.is_some() && starts_with_any(&HTML4_PUBLIC_PREFIXES))
+    {
+        dom::QuirksMode::LimitedQuirks
+    } else {
+        dom::QuirksMode::NoQuirks
+    }
+}
+
+// https://html.spec.whatwg.org/multipage/syntax.html#the-doctype
+fn is_conforming_doctype(doctype: &Doctype) -> bool {
+    doctype.name.as_deref() == Some("html")
+        && doctype.public_id.is_none()
+        && doctype.system_id.as_deref().is_none_or(|id| id == "about:legacy-compat")
+}
+
+// a trailing solidus is fine on void elements and on svg or math
+fn acknowledges_self_closing(name: &str) -> bool {
+    parser::is_void_tag(name) || matches!(name, "image" | "math" | "svg")
+}
+
+fn implied_tag(name: &str) -> Tag {
+    Tag { name: name.to_string(), ..Tag::default() }
+}
+
+fn is_hidden_input(tag: &Tag) -> bool {
+    tag.attributes.iter()
+        .any(|(name, value)| name == "type" && value.eq_ignore_ascii_case("hidden"))
+}
+
+fn same_attributes(a: &Tag, b: &Tag) -> bool {
+    a.attributes.len() == b.attributes.len()
+        && a.attributes.iter().all(|attr| b.attributes.contains(attr))
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0C' | '\r' | ' ')
+}
+
+fn is_heading(name: &str) -> bool {
+    matches!(name, "h1" | "h2" | "h3" | "h4" | "h5" | "h6")
+}
+
+fn is_scope_boundary(name: &str, scope: Scope) -> bool {
+    match scope {
+        Scope::Default => is_default_scope_boundary(name),
+        Scope::ListItem => is_default_scope_boundary(name) || matches!(name, "ol" | "ul"),
+        Scope::Button => is_default_scope_boundary(name) || name == "button",
+        Scope::Table => matches!(name, "html" | "table" | "template"),
+        Scope::Select => !matches!(name, "optgroup" | "option"),
+    }
+}
+
+// https://html.spec.whatwg.org/multipage/parsing.html#mathml-text-integration-point
+fn is_mathml_text_integration_point(elem: &dom::ElementData) -> bool {
+    elem.namespace == Namespace::MathMl && matches!(elem.tag.as_str(), "mi" | "mo" | "mn" | "ms" | "mtext")
+}
+
+// https://html.spec.whatwg.org/multipage/parsing.html#html-integration-point
+fn is_html_integration_point(elem: &dom::ElementData) -> bool {
+    match elem.namespace {
+        Namespace::MathMl => {
+            elem.tag == "annotation-xml"
+                && elem.attributes.get("encoding").is_some_and(|encoding| {
+                    encoding.eq_ignore_ascii_case("text/html") || encoding.eq_ignore_ascii_case("application/xhtml+xml")
+                })
+        }
+        Namespace::Svg => matches!(elem.tag.as_str(), "foreignObject" | "desc" | "title"),
+        _ => false,
+    }
+}
+
+// the foreign elements that bound a scope, and that are special
+fn is_foreign_boundary(elem: &dom::ElementData) -> bool {
+    match elem.namespace {
+        Namespace::MathMl => matches!(elem.tag.as_str(), "mi" | "mo" | "mn" | "ms" | "mtext" | "annotation-xml"),
+        Namespace::Svg => matches!(elem.tag.as_str(), "foreignObject" | "desc" | "title"),
+        _ => false,
+    }
+}
+
+fn breaks_out_of_foreign_content(tag: &Tag) -> bool {
+    match tag.name.as_str() {
+        "font" => tag.attributes.iter().any(|(name, _)| matches!(name.as_str(), "color" | "face" | "size")),
+        name => {
+            is_heading(name)
+                || matches!(name,
+                    "b" | "big" | "blockquote" | "body" | "br" | "center" | "code" | "dd" | "div" | "dl" |
+                    "dt" | "em" | "embed" | "head" | "hr" | "i" | "img" | "li" | "listing" | "menu" |
+                    "meta" | "nobr" | "ol" | "p" | "pre" | "ruby" | "s" | "small" | "span" | "strong" |
+                    "strike" | "sub" | "sup" | "table" | "tt" | "u" | "ul" | "var"
+                )
+        }
+    }
+}
+
+fn is_default_scope_boundary(name: &str) -> bool {
+    matches!(name, "applet" | "caption" | "html" | "table" | "td" | "th" | "marquee" | "object" | "template")
+}
+
+// https://html.spec.whatwg.org/multipage/parsing.html#special
+fn is_special(name: &str) -> bool {
+    matches!(name,
+        "address" | "applet" | "area" | "article" | "aside" | "base" | "basefont" | "bgsound" |
+        "blockquote" | "body" | "br" | "button" | "caption" | "center" | "col" | "colgroup" | "dd" |
+        "details" | "dir" | "div" | "dl" | "dt" | "embed" | "fieldset" | "figcaption" | "figure" |
+        "footer" | "form" | "frame" | "frameset" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "head" |
+        "header" | "hgroup" | "hr" | "html" | "iframe" | "img" | "input" | "keygen" | "li" | "link" |
+        "listing" | "main" | "marquee" | "menu" | "meta" | "nav" | "noembed" | "noframes" | "noscript" |
+        "object" | "ol" | "p" | "param" | "plaintext" | "pre" | "script" | "search" | "section" |
+        "select" | "source" | "style" | "summary" | "table" | "tbody" | "td" | "template" | "textarea" |
+        "tfoot" | "th" | "thead" | "title" | "tr" | "track" | "ul" | "wbr" | "xmp"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::{parse_html, parse_html_with_errors};
+
+    // the document as markup; the contents of a template are serialized inside it
+    fn tree(html_input: &str) -> String {
+        let document = parse_html(html_input.to_string());
+        document.get(document.root()).outer_html()
+    }
+
+    fn errors(html_input: &str) -> Vec<&'static str> {
+        parse_html_with_errors(html_input.to_string()).1.iter().map(|error| error.code).collect()
+    }
+
+    fn first(document: &dom::Document, tag: &str) -> NodeId {
+        document.descendants(document.root())
+            .find(|&id| document.node(id).as_element().is_some_and(|elem| elem.tag == tag))
+            .unwrap()
+    }
+
+    // the body of a document with a doctype, so the tests need not repeat it
+    fn body(html_input: &str) -> String {
+        let html = tree(&format!("<!DOCTYPE html>{}", html_input));
+        let start = html.find("<body>").map_or(0, |index| index + "<body>".len());
+        let end = html.rfind("</body>").unwrap_or(html.len());
+        html[start..end].to_string()
+    }
+
+    #[test]
+    fn implied_html_head_and_body() {
+        assert_eq!(tree("x"), "<html><head></head><body>x</body></html>");
+        assert_eq!(
+            tree("<!DOCTYPE html><title>t</title><p>a"),
+            "<!DOCTYPE html><html><head><title>t</title></head><body><p>a</p></body></html>"
+        );
+        assert_eq!(
+            tree("<!DOCTYPE html><meta charset=utf-8><noscript><link></noscript>"),
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><noscript><link></noscript></head><body></body></html>"
+        );
+        assert_eq!(errors("x"), ["missing-doctype"]);
+    }
+
+    #[test]
+    fn after_body() {
+        assert_eq!(
+            tree("<!DOCTYPE html><p>a</body><!--c--></html> "),
+            "<!DOCTYPE html><html><head></head><body><p>a </p></body><!--c--></html>"
+        );
+        assert_eq!(body("</body><p>b"), "<p>b</p>");
+        assert_eq!(errors("<!DOCTYPE html></body><p>b"), ["unexpected-content-after-body"]);
+    }
+
+    #[test]
+    fn implied_end_tags() {
+        assert_eq!(
+            body("<ul><li>a<li>b</ul><dl><dt>c<dd>d</dl>"),
+            "<ul><li>a</li><li>b</li></ul><dl><dt>c</dt><dd>d</dd></dl>"
+        );
+        assert_eq!(body("<p>a<div>b</div>"), "<p>a</p><div>b</div>");
+        assert_eq!(body("<h1>a<h2>b</h1>c"), "<h1>a</h1><h2>b</h2>c");
+        assert_eq!(body("<div></p></div>"), "<div><p></p></div>");
+        assert_eq!(errors("<!DOCTYPE html><h1>a<h2>b</h1>"), ["unexpected-start-tag", "end-tag-too-early"]);
+    }
+
+    #[test]
+    fn adoption_agency() {
+        assert_eq!(body("<a><p>x</a>y"), "<a></a><p><a>x</a>y</p>");
+        assert_eq!(body("<b>1<p>2</b>3</p>"), "<b>1</b><p><b>2</b>3</p>");
+        assert_eq!(body("<b><i>a</b>b</i>c"), "<b><i>a</i></b><i>b</i>c");
+        // a second <a> closes the first
+        assert_eq!(body("<a href=1>a<a href=2>b"), "<a href=\"1\">a</a><a href=\"2\">b</a>");
+        assert_eq!(errors("<!DOCTYPE html><b><i>a</b>b</i>"), ["misnested-tag"]);
+    }
+
+    #[test]
+    fn at_most_three_identical_formatting_elements_are_reopened() {
+        assert_eq!(body("<p><b><b><b><b>x</p>y"), "<p><b><b><b><b>x</b></b></b></b></p><b><b><b>y</b></b></b>");
+        // only identical entries count
+        assert_eq!(
+            body("<p><b class=a><b><b><b><b>x</p>y"),
+            "<p><b class=\"a\"><b><b><b><b>x</b></b></b></b></b></p><b class=\"a\"><b><b><b>y</b></b></b></b>"
+        );
+    }
+
+    #[test]
+    fn tables() {
+        assert_eq!(body("<table><tr><td>a</table>"), "<table><tbody><tr><td>a</td></tr></tbody></table>");
+        assert_eq!(
+            body("<table><caption>c<tr><td>d</table>"),
+            "<table><caption>c</caption><tbody><tr><td>d</td></tr></tbody></table>"
+        );
+        assert_eq!(
+            body("<table><col><tr><th>h<td>d</table>"),
+            "<table><colgroup><col></colgroup><tbody><tr><th>h</th><td>d</td></tr></tbody></table>"
+        );
+        // formatting elements opened in a cell end with it
+        assert_eq!(
+            body("<table><tr><td><b>x</td></tr></table>y"),
+            "<table><tbody><tr><td><b>x</b></td></tr></tbody></table>y"
+        );
+    }
+
+    #[test]
+    fn foster_parenting() {
+        assert_eq!(body("<table>a<tr><td>b</td></tr>c</table>"), "ac<table><tbody><tr><td>b</td></tr></tbody></table>");
+        assert_eq!(body("<table><b>x<tr><td>y</table>"), "<b>x</b><table><tbody><tr><td>y</td></tr></tbody></table>");
+        // whitespace stays in the table
+        assert_eq!(body("<table> <tr> </tr></table>"), "<table> <tbody><tr> </tr></tbody></table>");
+        assert_eq!(errors("<!DOCTYPE html><table>ab</table>"), ["foster-parenting"]);
+    }
+
+    #[test]
+    fn select() {
+        assert_eq!(
+            body("<select><option>a<option>b<optgroup><option>c</select>"),
+            "<select><option>a</option><option>b</option><optgroup><option>c</option></optgroup></select>"
+        );
+        assert_eq!(body("<select><div>x</div><input>"), "<select>x</select><input>");
+        assert_eq!(
+            body("<table><td><select><option>a<td>b"),
+            "<table><tbody><tr><td><select><option>a</option></select></td><td>b</td></tr></tbody></table>"
+        );
+    }
+
+    #[test]
+    fn text_after_pre_and_textarea_drops_one_newline() {
+        assert_eq!(body("<pre>\n\nx</pre><textarea>\ny</textarea>"), "<pre>\n\nx</pre><textarea>y</textarea>");
+    }
+
+    fn mode(html_input: &str) -> dom::QuirksMode {
+        parse_html(html_input.to_string()).mode
+    }
+
+    #[test]
+    fn quirks_mode_from_the_doctype() {
+        use dom::QuirksMode::*;
+        assert_eq!(mode("<p>"), Quirks);
+        assert_eq!(mode("<!DOCTYPE html>"), NoQuirks);
+        assert_eq!(mode("<!DOCTYPE html SYSTEM \"about:legacy-compat\">"), NoQuirks);
+        assert_eq!(mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01//EN\">"), NoQuirks);
+        assert_eq!(mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\">"), Quirks);
+        assert_eq!(
+            mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\" \"http://www.w3.org/TR/html4/loose.dtd\">"),
+            LimitedQuirks
+        );
+        assert_eq!(mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Transitional//EN\" \"\">"), LimitedQuirks);
+        assert_eq!(mode("<!DOCTYPE html PUBLIC \"-//IETF//DTD HTML 2.0//EN\">"), Quirks);
+        assert_eq!(mode("<!DOCTYPE svg>"), Quirks);
+        assert_eq!(mode("<!DOCTYPE>"), Quirks);
+    }
+
+    #[test]
+    fn doctypes_and_comments_around_the_root() {
+        assert_eq!(
+            tree("<!--a--><!DOCTYPE html PUBLIC \"x\" \"y\"><!--b--><html><!--c-->"),
+            "<!--a--><!DOCTYPE html PUBLIC \"x\" \"y\"><!--b--><html><!--c--><head></head><body></body></html>"
+        );
+        assert_eq!(errors("<!DOCTYPE html><!DOCTYPE html>"), ["unexpected-doctype"]);
+        assert_eq!(errors("<!DOCTYPE html PUBLIC \"x\">"), ["non-conforming-doctype"]);
+    }
+
+    // a table closes an open paragraph, except in quirks mode
+    #[test]
+    fn tables_in_quirks_mode() {
+        assert_eq!(body("<p><table></table>"), "<p></p><table></table>");
+        assert_eq!(
+            tree("<p><table></table>"),
+            "<html><head></head><body><p><table></table></p></body></html>"
+        );
+    }
+
+    #[test]
+    fn cdata_sections_in_svg() {
+        assert_eq!(body("<svg><![CDATA[a<b]]></svg>"), "<svg>a&lt;b</svg>");
+        assert_eq!(body("<div><![CDATA[a]]></div>"), "<div><!--[CDATA[a]]--></div>");
+    }
+
+    #[test]
+    fn raw_text_elements() {
+        assert_eq!(
+            tree("<!DOCTYPE html><script>a<b></scriptx></script><style>a&amp;<p></style><title>a&amp;<b></title>"),
+            "<!DOCTYPE html><html><head><script>a<b></scriptx></script><style>a&amp;<p></style>\
+             <title>a&amp;&lt;b&gt;</title></head><body></body></html>"
+        );
+        assert_eq!(body("<textarea>a&amp;<b></TEXTAREA>"), "<textarea>a&amp;&lt;b&gt;</textarea>");
+        assert_eq!(
+            body("<xmp><b></xmp><noembed><i></noembed><iframe><u></iframe><noframes><s></noframes>"),
+            "<xmp><b></xmp><noembed><i></noembed><iframe><u></iframe><noframes><s></noframes>"
+        );
+        // nothing ends plain text
+        assert_eq!(body("<plaintext>a</plaintext>&amp;"), "<plaintext>a</plaintext>&amp;</plaintext>");
+    }
+
+    #[test]
+    fn raw_text_up_to_the_end_of_file() {
+        assert_eq!(tree("<!DOCTYPE html><script>x"), "<!DOCTYPE html><html><head><script>x</script></head><body></body></html>");
+        assert_eq!(errors("<!DOCTYPE html><textarea>x"), ["eof-in-element"]);
+    }
+
+    #[test]
+    fn frameset_replaces_an_empty_body() {
+        assert_eq!(
+            tree("<!DOCTYPE html><frameset cols=50%><frame src=a><frameset><frame></frameset></frameset>"),
+            "<!DOCTYPE html><html><head></head><frameset cols=\"50%\"><frame src=\"a\"><frameset><frame></frameset></frameset></html>"
+        );
+        assert_eq!(
+            tree("<!DOCTYPE html><body> <frameset><frame></frameset>"),
+            "<!DOCTYPE html><html><head></head><body> </body></html>"
+        );
+        // whitespace, hidden inputs and elements without content leave the body replaceable
+        assert_eq!(
+            tree("<!DOCTYPE html> <input type=hidden><div><frameset></frameset>"),
+            "<!DOCTYPE html><html><head></head><frameset></frameset></html>"
+        );
+    }
+
+    #[test]
+    fn frameset_after_content_is_ignored() {
+        assert_eq!(
+            tree("<!DOCTYPE html><p>a</p><frameset><frame></frameset>"),
+            "<!DOCTYPE html><html><head></head><body><p>a</p></body></html>"
+        );
+        assert_eq!(
+            tree("<!DOCTYPE html><div>a<frameset></frameset>"),
+            "<!DOCTYPE html><html><head></head><body><div>a</div></body></html>"
+        );
+    }
+
+    #[test]
+    fn after_frameset() {
+        assert_eq!(
+            tree("<!DOCTYPE html><frameset></frameset> <noframes>x</noframes><!--a--></html><!--b--><p>"),
+            "<!DOCTYPE html><html><head></head><frameset></frameset> <noframes>x</noframes><!--a--></html><!--b-->"
+        );
+        assert_eq!(
+            errors("<!DOCTYPE html><frameset>x</frameset>a"),
+            ["unexpected-character-in-frameset", "unexpected-content-after-frameset"]
+        );
+    }
+
+    #[test]
+    fn template_contents_are_not_children() {
+        let document = parse_html("<!DOCTYPE html><template><p>a</p></template>".to_string());
+        let template = document.get(first(&document, "template"));
+        assert_eq!(template.children().count(), 0);
+        let contents = template.template_contents().unwrap();
+        assert_eq!(contents.node_type(), &dom::NodeType::DocumentFragment);
+        assert_eq!(contents.inner_html(), "<p>a</p>");
+        assert_eq!(template.inner_html(), "<p>a</p>");
+        // nothing in a template is found by walking the document
+        assert!(!document.descendants(document.root())
+            .any(|id| document.node(id).as_element().is_some_and(|elem| elem.tag == "p")));
+    }
+
+    #[test]
+    fn template_parses_table_parts_in_place() {
+        assert_eq!(
+            tree("<!DOCTYPE html><template><td>a</td><td>b</template>"),
+            "<!DOCTYPE html><html><head><template><td>a</td><td>b</td></template></head><body></body></html>"
+        );
+        assert_eq!(
+            tree("<!DOCTYPE html><template><tr><td>a</template>"),
+            "<!DOCTYPE html><html><head><template><tr><td>a</td></tr></template></head><body></body></html>"
+        );
+        assert_eq!(
+            tree("<!DOCTYPE html><template><col><div></template>"),
+            "<!DOCTYPE html><html><head><template><col></template></head><body></body></html>"
+        );
+        assert_eq!(
+            tree("<!DOCTYPE html><table><template><tr><td>a</template></table>"),
+            "<!DOCTYPE html><html><head></head><body><table><template><tr><td>a</td></tr></template></table></body></html>"
+        );
+    }
+
+    #[test]
+    fn templates_nest_and_close() {
+        assert_eq!(
+            tree("<!DOCTYPE html><body><template><template><b>a</template>b</template>c"),
+            "<!DOCTYPE html><html><head></head><body><template><template><b>a</b></template>b</template>c</body></html>"
+        );
+        // formatting elements do not leak out of a template
+        assert_eq!(
+            tree("<!DOCTYPE html><body><template><i>a</template>b"),
+            "<!DOCTYPE html><html><head></head><body><template><i>a</i></template>b</body></html>"
+        );
+        assert_eq!(errors("<!DOCTYPE html><template><div>a"), ["eof-in-element"]);
+        assert_eq!(errors("<!DOCTYPE html></template>"), ["unexpected-end-tag"]);
+    }
+
+    #[test]
+    fn forms_inside_templates_stay_unassociated() {
+        assert_eq!(
+            tree("<!DOCTYPE html><body><template><form><input></form></template><form></form>"),
+            "<!DOCTYPE html><html><head></head><body><template><form><input></form></template><form></form></body></html>"
+        );
+    }
+
+    #[test]
+    fn foreign_elements_and_attributes() {
+        let document = parse_html(concat!(
+            r#"<svg viewbox="0 0 1 1" XLINK:HREF="a">"#,
+            "<foreignobject><p>x</p></foreignobject><circle/></svg>",
+        ).to_string());
+        let svg = document.node(first(&document, "svg")).as_element().unwrap();
+        assert_eq!(svg.namespace, Namespace::Svg);
+        assert_eq!(svg.attributes["viewBox"], "0 0 1 1");
+        assert_eq!(svg.attribute_namespace("xlink:href"), Some(Namespace::XLink));
+        assert_eq!(svg.attribute_namespace("viewBox"), None);
+        let foreign_object = first(&document, "foreignObject");
+        assert_eq!(document.node(foreign_object).as_element().unwrap().namespace, Namespace::Svg);
+        // html again inside an integration point
+        assert_eq!(document.node(first(&document, "p")).as_element().unwrap().namespace, Namespace::Html);
+
+        let math = parse_html("<math definitionurl=u><mi>x</mi></math>".to_string());
+        let mi = math.node(first(&math, "mi")).as_element().unwrap();
+        assert_eq!(mi.namespace, Namespace::MathMl);
+        assert!(math.node(first(&math, "math")).as_element().unwrap().attributes.contains_key("definitionURL"));
+    }
+
+    #[test]
+    fn self_closing_foreign_elements_are_empty() {
+        assert_eq!(body("<svg><circle/>t<path/></svg>"), "<svg><circle></circle>t<path></path></svg>");
+        assert_eq!(body("<math><mi/>x</math>"), "<math><mi></mi>x</math>");
+        // but html elements other than void ones are not
+        assert_eq!(body("<div/>x</div>"), "<div>x</div>");
+        assert_eq!(
+            errors("<!DOCTYPE html><div/>"),
+            ["non-void-html-element-start-tag-with-trailing-solidus", "eof-in-element"]
+        );
+    }
+
+    #[test]
+    fn html_breaks_out_of_foreign_content() {
+        assert_eq!(body("<svg><g><p>x</svg>"), "<svg><g></g></svg><p>x</p>");
+        assert_eq!(body("<svg><font color=red>a</font></svg>"), "<svg></svg><font color=\"red\">a</font>");
+        assert_eq!(body("<svg><font>a</font></svg>"), "<svg><font>a</font></svg>");
+        assert_eq!(errors("<!DOCTYPE html><svg><p>"), ["unexpected-html-element-in-foreign-content"]);
+    }
+
+    #[test]
+    fn integration_points_parse_html() {
+        assert_eq!(
+            body("<math><mi>x<b>y</b></mi><annotation-xml encoding=\"text/html\"><div>d</div></annotation-xml></math>"),
+            "<math><mi>x<b>y</b></mi><annotation-xml encoding=\"text/html\"><div>d</div></annotation-xml></math>"
+        );
+        let document = parse_html("<svg><title><p>t</p></title><desc><svg/></desc></svg>".to_string());
+        assert_eq!(document.node(first(&document, "p")).as_element().unwrap().namespace, Namespace::Html);
+        assert_eq!(document.node(first(&document, "title")).as_element().unwrap().namespace, Namespace::Svg);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::css::stylesheet::{Value, Unit};
-use crate::css::style::{StyledNode, Display};
+use crate::css::style::{StyledNode, Display, WhiteSpace};
+use crate::html::dom::NodeType;
 
 #[derive(Default, Debug, Clone, Copy)]
 pub struct Dimensions {
@@ -36,9 +37,17 @@ pub struct LayoutBox<'a> {
 pub enum BoxType<'a> {
     BlockNode(&'a StyledNode<'a>),
     InlineNode(&'a StyledNode<'a>),
+    TextNode(&'a StyledNode<'a>, TextRun),
     AnonymousBlock,
 }
 
+// the text of a text node after white-space processing
+#[derive(Debug, Clone)]
+pub struct TextRun {
+    pub text: String,
+    pub white_space: WhiteSpace,
+}
+
 impl<'a> LayoutBox<'a> {
     pub fn new(node: &'a StyledNode<'a>) -> LayoutBox<'a> {
         LayoutBox {
@@ -52,9 +61,17 @@ impl<'a> LayoutBox<'a> {
         }
     }
 
+    fn text(node: &'a StyledNode<'a>, text: &str, white_space: WhiteSpace) -> LayoutBox<'a> {
+        LayoutBox {
+            dimensions: Dimensions::default(),
+            box_type: BoxType::TextNode(node, TextRun { text: process_text(text, white_space), white_space }),
+            children: Vec::new(),
+        }
+    }
+
     fn get_style_node(&self) -> &'a StyledNode<'a> {
         match self.box_type {
-            BoxType::BlockNode(node) | BoxType::InlineNode(node) => node,
+            BoxType::BlockNode(node) | BoxType::InlineNode(node) | BoxType::TextNode(node, _) => node,
             BoxType::AnonymousBlock => panic!("Anonymous block box has no style node")
         }
     }
@@ -62,21 +79,87 @@ impl<'a> LayoutBox<'a> {
 
 pub fn build_layout_tree<'a>(node: &'a StyledNode<'a>, mut containing_block: Dimensions) -> LayoutBox<'a> {
     containing_block.content.height = 0.0;
-    let mut root_box = layout_tree(node);
+    let mut root_box = layout_tree(node, WhiteSpace::default());
     root_box.layout(containing_block);
-    return root_box;
+    root_box
 }
 
-fn layout_tree<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
+// white-space is inherited, so each node passes down its own or its parent's value
+fn layout_tree<'a>(style_node: &'a StyledNode<'a>, white_space: WhiteSpace) -> LayoutBox<'a> {
+    let white_space = style_node.white_space().unwrap_or(white_space);
     let mut root = LayoutBox::new(style_node);
+    // the box of a ::before or ::after holds its generated text
+    if let Some(generated) = &style_node.generated {
+        root.get_inline_container().children.push(LayoutBox::text(style_node, &generated.text, white_space));
+    }
     for child in &style_node.children {
-        match child.display() {
-            Display::Block => root.children.push(layout_tree(child)),
-            Display::Inline => root.get_inline_container().children.push(layout_tree(child)),
-            Display::None => {} 
+        match child.node.node_type() {
+            NodeType::Text(text) => root.get_inline_container().children.push(LayoutBox::text(child, text, white_space)),
+            NodeType::Comment(_) | NodeType::Doctype(_) => {}
+            _ => match child.display() {
+                Display::Block => root.children.push(layout_tree(child, white_space)),
+                Display::Inline => root.get_inline_container().children.push(layout_tree(child, white_space)),
+                Display::None => {}
+            }
         }
     }
-    return root;
+    if let BoxType::BlockNode(_) = root.box_type {
+        root.collapse_white_space();
+    }
+    root
+}
+
+// ---------------------
+// white space
+// ---------------------
+
+// https://drafts.csswg.org/css-text/#white-space-phase-1
+// what can be done inside a single text node; spaces that collapse across
+// node boundaries are handled per line by collapse_white_space
+fn process_text(text: &str, white_space: WhiteSpace) -> String {
+    if !white_space.collapses_spaces() {
+        return text.to_string();
+    }
+    // spaces and tabs around a segment break go away, then the break
+    // itself becomes a space unless it is preserved
+    let lines: Vec<&str> = text.split('\n').collect();
+    let last = lines.len() - 1;
+    let separator = if white_space == WhiteSpace::PreLine { "\n" } else { " " };
+    let joined = lines.iter().enumerate()
+        .map(|(i, line)| {
+            let line = if i > 0 { line.trim_start_matches([' ', '\t']) } else { line };
+            if i < last { line.trim_end_matches([' ', '\t']) } else { line }
+        })
+        .collect::<Vec<_>>()
+        .join(separator);
+
+    let mut result = String::new();
+    for c in joined.chars() {
+        let c = if c == '\t' { ' ' } else { c };
+        if c == ' ' && result.ends_with(' ') { continue; }
+        result.push(c);
+    }
+    result
+}
+
+fn collect_text_runs<'a, 'b>(lbox: &'b mut LayoutBox<'a>, runs: &mut Vec<&'b mut TextRun>) {
+    let LayoutBox { box_type, children, .. } = lbox;
+    if let BoxType::TextNode(_, run) = box_type {
+        runs.push(run);
+    }
+    for child in children {
+        collect_text_runs(child, runs);
+    }
+}
+
+// true if anything is left
+fn remove_empty_text(lbox: &mut LayoutBox) -> bool {
+    lbox.children.retain_mut(remove_empty_text);
+    match &lbox.box_type {
+        BoxType::TextNode(_, run) => !run.text.is_empty(),
+        BoxType::AnonymousBlock => !lbox.children.is_empty(),
+        _ => true,
+    }
 }
 
 impl LayoutBox<'_> {
@@ -84,10 +167,43 @@ impl LayoutBox<'_> {
         match self.box_type {
             BoxType::BlockNode(_) => self.layout_block(containing_block),
             BoxType::InlineNode(_) => {}
+            BoxType::TextNode(..) => {}
             BoxType::AnonymousBlock => {}
         }
     }
 
+    // https://drafts.csswg.org/css-text/#white-space-phase-2
+    // each anonymous block holds one run of inline content; a collapsible space
+    // goes away after another one and at the start and end of the run, so
+    // whitespace between blocks leaves nothing behind
+    fn collapse_white_space(&mut self) {
+        for child in &mut self.children {
+            if !matches!(child.box_type, BoxType::AnonymousBlock) { continue; }
+            let mut runs = Vec::new();
+            collect_text_runs(child, &mut runs);
+
+            let mut after_space = true;
+            for run in runs.iter_mut() {
+                if run.text.is_empty() { continue; }
+                if !run.white_space.collapses_spaces() {
+                    after_space = false;
+                    continue;
+                }
+                if after_space && run.text.starts_with(' ') {
+                    run.text.remove(0);
+                }
+                if !run.text.is_empty() {
+                    after_space = run.text.ends_with([' ', '\n']);
+                }
+            }
+            if let Some(run) = runs.iter_mut().rev().find(|run| !run.text.is_empty())
+                && run.white_space.collapses_spaces() && run.text.ends_with(' ') {
+                run.text.pop();
+            }
+        }
+        self.children.retain_mut(remove_empty_text);
+    }
+
     fn layout_block(&mut self, containing_block: Dimensions) {
         self.calculate_block_width(containing_block);
         self.calculate_block_position(containing_block);
@@ -197,7 +313,7 @@ impl LayoutBox<'_> {
 
     fn get_inline_container(&mut self) -> &mut Self {
         match self.box_type {
-            BoxType::InlineNode(_) | BoxType::AnonymousBlock => self,
+            BoxType::InlineNode(_) | BoxType::TextNode(..) | BoxType::AnonymousBlock => self,
             BoxType::BlockNode(_) => {
                 match self.children.last() {
                     Some(&LayoutBox { box_type: BoxType::AnonymousBlock,..}) => {}
@@ -235,3 +351,88 @@ impl Dimensions {
         self.border_box().expanded_by(self.margin)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css::{build_styled_tree, parse_css};
+    use crate::html::parse_html;
+
+    // the text of each run of inline content, in tree order
+    fn inline_text(html_input: &str, css_input: &str) -> Vec<String> {
+        let document = parse_html(html_input.to_string());
+        let css = format!(
+            "head {{ display: none }} html, body, div, p, pre {{ display: block }} pre {{ white-space: pre }} {}",
+            css_input,
+        );
+        let stylesheet = parse_css(css);
+        let styled = build_styled_tree(document.get(document.document_element().unwrap()), &stylesheet);
+        let mut viewport = Dimensions::default();
+        viewport.content.width = 800.0;
+        let layout = build_layout_tree(&styled, viewport);
+
+        fn walk(lbox: &LayoutBox, text: &mut Vec<String>) {
+            if let BoxType::AnonymousBlock = lbox.box_type {
+                let mut runs = Vec::new();
+                collect(lbox, &mut runs);
+                text.push(runs.concat());
+                return;
+            }
+            for child in &lbox.children {
+                walk(child, text);
+            }
+        }
+        fn collect(lbox: &LayoutBox, runs: &mut Vec<String>) {
+            if let BoxType::TextNode(_, run) = &lbox.box_type {
+                runs.push(run.text.clone());
+            }
+            for child in &lbox.children {
+                collect(child, runs);
+            }
+        }
+        let mut text = Vec::new();
+        walk(&layout, &mut text);
+        text
+    }
+
+    #[test]
+    fn spaces_and_tabs_collapse_within_a_text_node() {
+        assert_eq!(process_text("a \t b", WhiteSpace::Normal), "a b");
+        assert_eq!(process_text(" a  \n  b \n", WhiteSpace::Normal), " a b ");
+        assert_eq!(process_text("a  \n  b", WhiteSpace::PreLine), "a\nb");
+        assert_eq!(process_text("a \t b", WhiteSpace::Nowrap), "a b");
+        assert_eq!(process_text("a \t\n b", WhiteSpace::Pre), "a \t\n b");
+        assert_eq!(process_text("a  b", WhiteSpace::PreWrap), "a  b");
+        assert_eq!(process_text("a  b", WhiteSpace::BreakSpaces), "a  b");
+    }
+
+    #[test]
+    fn whitespace_between_inline_elements_is_kept_once() {
+        assert_eq!(inline_text("<b>a</b> <i>b</i>", ""), ["a b"]);
+        assert_eq!(inline_text("<b>a </b> <i> b</i>", ""), ["a b"]);
+        assert_eq!(inline_text("<b>a</b><i>b</i>", ""), ["ab"]);
+    }
+
+    #[test]
+    fn whitespace_at_the_edges_of_a_line_goes_away() {
+        assert_eq!(inline_text("<div>\n  <b>a</b>\n</div>", ""), ["a"]);
+        assert_eq!(inline_text("<p> a </p>\n\n<p>b</p>\n", ""), ["a", "b"]);
+        // whitespace only between blocks leaves no anonymous block
+        assert_eq!(inline_text("<div><p>a</p>   <p>b</p></div>", ""), ["a", "b"]);
+    }
+
+    #[test]
+    fn preserved_whitespace_is_left_alone() {
+        assert_eq!(inline_text("<pre>  a\n   b  </pre>", ""), ["  a\n   b  "]);
+        assert_eq!(inline_text("<p> a <span> b </span></p>", "span { white-space: pre }"), ["a  b "]);
+        // inherited from the parent
+        assert_eq!(inline_text("<div> <b> a  b </b> </div>", "div { white-space: pre-wrap }"), ["  a  b  "]);
+    }
+
+    #[test]
+    fn generated_content_flows_with_the_text() {
+        assert_eq!(inline_text("<p>b</p>", "p::before { content: 'a ' } p::after { content: ' c' }"), ["a b c"]);
+        assert_eq!(inline_text("<p>b</p>", "p::before { content: 'a'; display: block }"), ["a", "b"]);
+        assert_eq!(inline_text("<p><i>b</i></p>", "p::after { content: '  ' }"), ["b"]);
+    }
+}
@@ -0,0 +1,5 @@
+pub mod html;
+pub mod css;
+pub mod layout;
+pub mod render;
+pub mod loader;
@@ -0,0 +1,148 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::html::dom::Document;
+
+// resolves and reads resources of a document from the local filesystem
+pub struct Loader {
+    base_dir: PathBuf,
+}
+
+impl Loader {
+    // the base is the document's own directory, or <base href> when present
+    pub fn for_document(document_path: &Path, document: &Document) -> Loader {
+        let document_dir = document_path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let loader = Loader { base_dir: document_dir };
+
+        match find_base_href(document).and_then(|href| loader.resolve(&href).ok().map(|path| (href, path))) {
+            Some((href, path)) if href.ends_with('/') => Loader { base_dir: path },
+            Some((_, path)) => Loader { base_dir: path.parent().unwrap_or(Path::new("")).to_path_buf() },
+            None => loader,
+        }
+    }
+
+    pub fn resolve(&self, href: &str) -> Result<PathBuf, LoadError> {
+        // query and fragment have no meaning for local files
+        let href = href.trim().split(['?', '#']).next().unwrap_or("");
+        if href.is_empty() {
+            return Err(LoadError::NoPath);
+        }
+
+        if let Some(path) = href.strip_prefix("file://") {
+            return Ok(PathBuf::from(path));
+        }
+        if href.contains("://") {
+            return Err(LoadError::NotLocal(href.to_string()));
+        }
+        Ok(self.base_dir.join(href))
+    }
+
+    pub fn load_text(&self, href: &str) -> Result<String, LoadError> {
+        let path = self.resolve(href)?;
+        fs::read_to_string(&path).map_err(|e| LoadError::Io(path, e))
+    }
+}
+
+// why a resource could not be loaded; the caller decides how to report it
+#[derive(Debug)]
+pub enum LoadError {
+    // only a query or fragment, or nothing at all
+    NoPath,
+    NotLocal(String),
+    Io(PathBuf, io::Error),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::NoPath => write!(f, "no file to load"),
+            LoadError::NotLocal(href) => write!(f, "only local files can be loaded, skipping {}", href),
+            LoadError::Io(path, e) => write!(f, "failed to load {}: {}", path.display(), e),
+        }
+    }
+}
+
+// the first <base> with an href, in tree order
+fn find_base_href(document: &Document) -> Option<String> {
+    document.descendants(document.root())
+        .filter_map(|id| document.node(id).as_element())
+        .find(|elem| elem.tag == "base" && elem.attributes.contains_key("href"))
+        .map(|elem| elem.attributes["href"].clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css::author_stylesheet_with_warnings;
+    use crate::css::stylesheet::Selector;
+    use crate::html::parse_html;
+
+    // a fresh directory of its own for each test, as they run in parallel
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("open_otk_browser_{}_{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("css")).unwrap();
+        dir
+    }
+
+    fn loader(html_input: &str) -> Loader {
+        Loader::for_document(Path::new("/site/pages/index.html"), &parse_html(html_input.to_string()))
+    }
+
+    #[test]
+    fn resolves_against_the_document_directory() {
+        let loader = loader("<p>x</p>");
+        assert_eq!(loader.resolve("a.css").ok(), Some(PathBuf::from("/site/pages/a.css")));
+        assert_eq!(loader.resolve("css/a.css?v=2#top").ok(), Some(PathBuf::from("/site/pages/css/a.css")));
+        assert_eq!(loader.resolve("file:///etc/a.css").ok(), Some(PathBuf::from("/etc/a.css")));
+        assert!(matches!(loader.resolve("https://example.com/a.css"), Err(LoadError::NotLocal(_))));
+        assert!(matches!(loader.resolve("  "), Err(LoadError::NoPath)));
+        assert!(matches!(loader.resolve("#top"), Err(LoadError::NoPath)));
+    }
+
+    #[test]
+    fn base_href_changes_the_directory() {
+        let to_dir = loader(r#"<base href="/site/shared/"><p>x</p>"#);
+        assert_eq!(to_dir.resolve("a.css").ok(), Some(PathBuf::from("/site/shared/a.css")));
+        let to_file = loader(r#"<base href="other/page.html"><p>x</p>"#);
+        assert_eq!(to_file.resolve("a.css").ok(), Some(PathBuf::from("/site/pages/other/a.css")));
+        let first = loader(r#"<base target="_top"><base href="/one/"><base href="/two/">"#);
+        assert_eq!(first.resolve("a.css").ok(), Some(PathBuf::from("/one/a.css")));
+    }
+
+    #[test]
+    fn linked_stylesheets_are_loaded_in_document_order() {
+        let dir = scratch_dir("linked");
+        fs::write(dir.join("css/a.css"), "p { display: block }").unwrap();
+        fs::write(dir.join("css/b.css"), "div { display: none }\nq:nope { }").unwrap();
+        let document = parse_html(concat!(
+            r#"<base href="css/"><link rel="stylesheet" href="a.css"><style>span { display: none }</style>"#,
+            r#"<link rel="alternate stylesheet" href="a.css"><link rel="stylesheet" href="missing.css">"#,
+            r#"<link rel="stylesheet" href="b.css"><link rel="stylesheet" href="http://example.com/c.css">"#,
+        ).to_string());
+        let loader = Loader::for_document(&dir.join("index.html"), &document);
+        let (stylesheet, warnings) = author_stylesheet_with_warnings(&document, &loader);
+        let tags: Vec<_> = stylesheet.rules.iter()
+            .map(|rule| match &rule.selectors[0] {
+                Selector::Simple(simple) => simple.tag.clone().unwrap_or_default(),
+                selector => panic!("unexpected selector {:?}", selector),
+            })
+            .collect();
+        assert_eq!(tags, ["p", "span", "div"]);
+        let warnings: Vec<_> = warnings.iter()
+            .map(|(href, warning)| (href.as_deref().unwrap_or(""), warning.location.line, warning.message.as_str()))
+            .collect();
+        assert_eq!(warnings.len(), 3);
+        // what could not be loaded is reported with the rest
+        assert_eq!(warnings[0].0, "missing.css");
+        assert!(warnings[0].2.starts_with("failed to load "), "{}", warnings[0].2);
+        assert_eq!(warnings[1], ("b.css", 2, "unsupported pseudo-class ':nope', rule dropped"));
+        assert_eq!(
+            warnings[2],
+            ("http://example.com/c.css", 1, "only local files can be loaded, skipping http://example.com/c.css")
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
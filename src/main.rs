@@ -3,44 +3,68 @@ use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::{WindowBuilder};
 use softbuffer::{Context, Surface};
 use std::num::NonZeroU32;
+use std::path::PathBuf;
 use std::sync::Arc;
 
-mod html;
-mod css;
-mod layout;
-mod render;
+use open_otk_browser::{html, css, layout, render, loader};
 
 fn main() {
-    let html_input = r#"
-        <div class="a">
-            <div class="b">
-                <div class="c">
-                    <div class="d">
-                        <div class="e">
-                            <div class="f">
-                                <div class="g">
+    let sample_input = r#"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            <style>
+                * { display: block; padding: 12px; }
+                head { display: none; }
+                .a { background: #ff0000; }
+                .b { background: #ffa500; }
+                .c { background: #ffff00; }
+                .d { background: #008000; }
+                .e { background: #0000ff; }
+                .f { background: #4b0082; }
+            </style>
+        </head>
+        <body>
+            <div class="a">
+                <div class="b">
+                    <div class="c">
+                        <div class="d">
+                            <div class="e">
+                                <div class="f">
+                                    <div class="g" style="background: #800080">
+                                    </div>
                                 </div>
                             </div>
                         </div>
                     </div>
                 </div>
             </div>
-        </div>
-    "#.to_string();
+        </body>
+        </html>
+    "#;
 
-    let css_input = r#"
-        * { display: block; padding: 12px; }
-        .a { background: #ff0000; }
-        .b { background: #ffa500; }
-        .c { background: #ffff00; }
-        .d { background: #008000; }
-        .e { background: #0000ff; }
-        .f { background: #4b0082; }
-        .g { background: #800080; }
-    "#.to_string();
+    // open_otk_browser [path/to/page.html]
+    let (html_input, document_path) = match std::env::args().nth(1) {
+        Some(path) => {
+            let html_input = std::fs::read(&path).expect("Failed to read html file");
+            (html_input, PathBuf::from(path))
+        }
+        None => (sample_input.as_bytes().to_vec(), PathBuf::from("index.html")),
+    };
 
-    let node_root = html::parse_html(html_input);
-    let stylesheet = css::parse_css(css_input);
+    // pages that declare nothing are taken to be windows-1252, like browsers do
+    let (document, parse_errors) = html::parse_html_bytes_with_errors(&html_input, encoding_rs::WINDOWS_1252);
+    for error in &parse_errors {
+        eprintln!("{}:{}", document_path.display(), error);
+    }
+    let resource_loader = loader::Loader::for_document(&document_path, &document);
+    let (stylesheet, css_warnings) = css::author_stylesheet_with_warnings(&document, &resource_loader);
+    for (href, warning) in &css_warnings {
+        match href {
+            Some(href) => eprintln!("{}:{}", href, warning),
+            None => eprintln!("{}:{}", document_path.display(), warning),
+        }
+    }
 
     // println!("{:#?}", stylesheet);
 
@@ -56,7 +80,7 @@ fn main() {
     let context = Context::new(window.clone()).unwrap();
     let mut surface = Surface::new(&context, window.clone()).unwrap();
 
-    let dom_ref = &node_root;
+    let dom_ref = document.get(document.document_element().unwrap());
     let style_ref = &stylesheet;
 
     event_loop.run(move |event, elwt| {